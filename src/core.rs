@@ -1,4 +1,8 @@
-use crate::exchange::{AudioCodecConfig, Destination, ExchangeRegistrable, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, PackedContentToDecoder, PackedContentToDemuxer, PackedContentToRemuxer, RemuxedData, VideoCodecConfig};
+use crate::exchange::{AudioCodecConfig, Destination, ExchangeRegistrable, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, PackedContentToDecoder, PackedContentToDemuxer, PackedContentToRemuxer, PackedContentToStage, RemuxedData, SessionId, StageId, VideoCodecConfig};
+use crate::session::SessionWaker;
+use crate::event::{Logger, MetricsSnapshot};
+use crate::fmpeg::codec_string;
+use crate::fmpeg::caption::CaptionEvent;
 use std::collections::VecDeque;
 use std::sync::mpsc;
 
@@ -8,9 +12,17 @@ pub struct Core {
     channel_sender: mpsc::Sender<PackedContent>,
 
     pub buffer: VecDeque<RemuxedData>,
+    captions: VecDeque<CaptionEvent>,
 
     audio_codec_conf: Option<AudioCodecConfig>,
     video_codec_conf: Option<VideoCodecConfig>,
+
+    stages: Vec<StageId>,
+    seeking: bool,
+
+    waker: Option<SessionWaker>,
+
+    logger: Logger,
 }
 
 impl Core {
@@ -21,17 +33,69 @@ impl Core {
             channel_receiver,
             channel_sender,
             buffer: VecDeque::new(),
+            captions: VecDeque::new(),
             audio_codec_conf: None,
             video_codec_conf: None,
+            stages: vec![],
+            seeking: false,
+            waker: None,
+            logger: Logger::new(Destination::Core),
+        }
+    }
+
+    /// The logger shared by every stage registered to the same exchange.
+    /// Attach observers here to route events to your own logging.
+    pub fn get_logger(&self) -> Logger {
+        self.logger.clone()
+    }
+
+    pub fn get_metrics(&self) -> MetricsSnapshot {
+        self.logger.metrics().snapshot()
+    }
+
+    /// Set when the core drives a session of a `SessionPool` instead of its own worker threads.
+    pub(crate) fn set_waker(&mut self, waker: SessionWaker) {
+        self.waker = Some(waker);
+    }
+
+    /// The id of the pooled session this core drives, if any.
+    pub fn session_id(&self) -> Option<SessionId> {
+        self.waker.as_ref().map(|waker| waker.session_id())
+    }
+
+    /// Registers a custom stage so that start, stop, now and drop commands reach it as well.
+    pub fn add_stage(&mut self, stage_id: StageId) {
+        self.stages.push(stage_id);
+    }
+
+    fn send_to_stages(&self, command: fn() -> PackedContentToStage) -> Result<(), Box<dyn std::error::Error>> {
+        for stage_id in self.stages.iter() {
+            self.send(
+                Packed {
+                    packed_routing: Destination::Stage(*stage_id),
+                    packed_content: PackedContent::ToStage(command()),
+                }
+            )?;
         }
+        Ok(())
     }
 
     pub fn process_incoming(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         while let Ok(data) = self.channel_receiver.try_recv() {
             match data {
                 PackedContent::ToCore(PackedContentToCore::Data(data)) => {
+                    if self.seeking && !matches!(data, RemuxedData::Header(_)) {
+                        continue;
+                    }
                     self.buffer.push_back(data);
                 },
+                PackedContent::ToCore(PackedContentToCore::Caption(event)) if !self.seeking => {
+                    self.captions.push_back(event);
+                },
+                PackedContent::ToCore(PackedContentToCore::SeekCompleted(time_ms)) => {
+                    self.logger.debug(format!("Seek completed, resuming at {} ms.", time_ms));
+                    self.seeking = false;
+                },
                 PackedContent::ToCore(PackedContentToCore::DecoderConfig(conf)) => {
                     match conf {
                         MseDecoderConfig::AudioCodec(audio_codec) => {
@@ -48,12 +112,20 @@ impl Core {
         Ok(())
     }
 
+    /// Returns the captions decoded since the last call, in presentation order.
+    pub fn take_captions(&mut self) -> Result<Vec<CaptionEvent>, Box<dyn std::error::Error>> {
+        self.process_incoming()?;
+        Ok(self.captions.drain(..).collect())
+    }
+
     pub fn send(&self, packed: Packed) -> Result<(), Box<dyn std::error::Error>> {
         if let Err(_) = self.channel_exchange
             .as_ref()
             .unwrap()
             .send(packed) {
             Err("[Core] Channel closed.".into())
+        } else if let Some(waker) = self.waker.as_ref() {
+            waker.wake()
         } else {
             Ok(())
         }
@@ -73,6 +145,7 @@ impl Core {
     pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.start_decoding()?;
         self.start_demuxing()?;
+        self.send_to_stages(|| PackedContentToStage::StartProcessing)?;
         self.start_remuxing()?;
         Ok(())
     }
@@ -111,9 +184,29 @@ impl Core {
         )
     }
 
+    /// Seeks to the nearest keyframe at or before `time_ms`.
+    /// Every stage flushes its caches; fragments emitted afterwards keep absolute decode times,
+    /// and the init segment already sent remains valid.
+    /// Going backwards needs a seekable pipeline, see `PipelineBuilder::seekable`.
+    pub fn seek(&mut self, time_ms: u32) -> Result<(), Box<dyn std::error::Error>> {
+        // fragments produced before the seek are dropped until the remuxer confirms it.
+        self.buffer.clear();
+        self.captions.clear();
+        self.seeking = true;
+        self.send(
+            Packed {
+                packed_routing: Destination::Decoder,
+                packed_content: PackedContent::ToDecoder(
+                    PackedContentToDecoder::Seek(time_ms)
+                ),
+            }
+        )
+    }
+
     pub fn stop(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.stop_decoding()?;
         self.stop_demuxing()?;
+        self.send_to_stages(|| PackedContentToStage::StopProcessing)?;
         self.stop_remuxing()?;
         Ok(())
     }
@@ -154,6 +247,7 @@ impl Core {
     pub fn now(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.decode_now()?;
         self.demux_now()?;
+        self.send_to_stages(|| PackedContentToStage::Now)?;
         self.remux_now()?;
         Ok(())
     }
@@ -194,6 +288,7 @@ impl Core {
     pub fn drop_all_workers(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.drop_decoding_worker()?;
         self.drop_demuxing_worker()?;
+        self.send_to_stages(|| PackedContentToStage::CloseWorkerThread)?;
         self.drop_remuxing_worker()?;
         Ok(())
     }
@@ -244,6 +339,10 @@ impl ExchangeRegistrable for Core {
     fn get_self_as_destination(&self) -> Destination {
         Destination::Core
     }
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
 }
 
 impl IConsumable for Core {
@@ -290,6 +389,13 @@ impl Core {
         None
     }
 
+    /// Returns the MIME type with both codecs, for `MediaSource.isTypeSupported`, if they are already set.
+    pub fn try_get_mime_type(&mut self) -> Option<String> {
+        let (audio, video) = self.try_get_codec_conf()?;
+        let audio = Some(audio.as_str()).filter(|audio| !audio.is_empty());
+        Some(codec_string::mime_type(Some(video.as_str()), audio))
+    }
+
     /// Returns the codec configuration. This method will block until the codec configuration is ready.
     pub fn get_codec_conf(&mut self) -> Result<(String, String), Box<dyn std::error::Error>> {
         self.process_incoming()?;
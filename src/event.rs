@@ -0,0 +1,214 @@
+use crate::exchange::Destination;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Identifies the stream a stage is working on.
+/// Defaults to 0 for single-stream pipelines.
+pub type StreamId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for EventLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EventLevel::Trace => "TRACE",
+            EventLevel::Debug => "DEBUG",
+            EventLevel::Info => "INFO",
+            EventLevel::Warn => "WARN",
+            EventLevel::Error => "ERROR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub level: EventLevel,
+    pub stage: Destination,
+    pub stream_id: StreamId,
+    pub message: String,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stage = match self.stage {
+            Destination::Core => "Core".to_string(),
+            Destination::Decoder => "Decoder".to_string(),
+            Destination::Demuxer => "Demuxer".to_string(),
+            Destination::Remuxer => "Remuxer".to_string(),
+            Destination::Stage(id) => format!("Stage {}", id),
+        };
+        write!(f, "{} [{}] #{} {}", self.level, stage, self.stream_id, self.message)
+    }
+}
+
+/// Receives every event emitted by the stages it is attached to.
+/// Observers are called from the worker threads, so they must be cheap and thread safe.
+pub trait IEventObserver: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+/// Prints events at or above `min_level` to stdout.
+pub struct StdoutObserver {
+    pub min_level: EventLevel,
+}
+
+impl StdoutObserver {
+    pub fn new(min_level: EventLevel) -> Self {
+        Self { min_level }
+    }
+}
+
+impl IEventObserver for StdoutObserver {
+    fn on_event(&self, event: &Event) {
+        if event.level >= self.min_level {
+            println!("{}", event);
+        }
+    }
+}
+
+/// Counters shared by all stages of a pipeline.
+#[derive(Default)]
+pub struct Metrics {
+    pub audio_tags_decoded: AtomicU64,
+    pub video_tags_decoded: AtomicU64,
+    pub script_tags_decoded: AtomicU64,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub fragments_emitted: AtomicU64,
+    pub errors: AtomicU64,
+    pub resyncs: AtomicU64,
+}
+
+/// A point-in-time copy of `Metrics`.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub audio_tags_decoded: u64,
+    pub video_tags_decoded: u64,
+    pub script_tags_decoded: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub fragments_emitted: u64,
+    pub errors: u64,
+    pub resyncs: u64,
+}
+
+impl Metrics {
+    #[inline]
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            audio_tags_decoded: self.audio_tags_decoded.load(Ordering::Relaxed),
+            video_tags_decoded: self.video_tags_decoded.load(Ordering::Relaxed),
+            script_tags_decoded: self.script_tags_decoded.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            fragments_emitted: self.fragments_emitted.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            resyncs: self.resyncs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Handle used by a stage to emit events and bump counters.
+/// Cloning is cheap; all clones share the same observers and metrics.
+#[derive(Clone)]
+pub struct Logger {
+    stage: Destination,
+    stream_id: StreamId,
+    observers: Arc<RwLock<Vec<Arc<dyn IEventObserver>>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Logger {
+    pub fn new(stage: Destination) -> Self {
+        Self {
+            stage,
+            stream_id: 0,
+            observers: Arc::new(RwLock::new(vec![])),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /// Returns a logger sharing observers and metrics, but reporting as another stage.
+    pub fn for_stage(&self, stage: Destination) -> Self {
+        Self {
+            stage,
+            stream_id: self.stream_id,
+            observers: self.observers.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub fn with_stream_id(mut self, stream_id: StreamId) -> Self {
+        self.stream_id = stream_id;
+        self
+    }
+
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    pub fn add_observer(&self, observer: Arc<dyn IEventObserver>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    #[inline]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn log(&self, level: EventLevel, message: impl Into<String>) {
+        let observers = self.observers.read().unwrap();
+        if observers.is_empty() {
+            return;
+        }
+        let event = Event {
+            level,
+            stage: self.stage.clone(),
+            stream_id: self.stream_id,
+            message: message.into(),
+        };
+        for observer in observers.iter() {
+            observer.on_event(&event);
+        }
+    }
+
+    #[inline]
+    pub fn trace(&self, message: impl Into<String>) {
+        self.log(EventLevel::Trace, message);
+    }
+
+    #[inline]
+    pub fn debug(&self, message: impl Into<String>) {
+        self.log(EventLevel::Debug, message);
+    }
+
+    #[inline]
+    pub fn info(&self, message: impl Into<String>) {
+        self.log(EventLevel::Info, message);
+    }
+
+    #[inline]
+    pub fn warn(&self, message: impl Into<String>) {
+        self.log(EventLevel::Warn, message);
+    }
+
+    /// Emits an error event and counts it.
+    #[inline]
+    pub fn error(&self, message: impl Into<String>) {
+        Metrics::add(&self.metrics.errors, 1);
+        self.log(EventLevel::Error, message);
+    }
+}
@@ -6,20 +6,34 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::mpsc;
 use std::thread::JoinHandle;
+use crate::fmpeg::caption::CaptionEvent;
+use crate::fmpeg::codec_string;
 use crate::fmpeg::remux_context::AudioCodecType;
+use crate::event::Logger;
 
 pub struct Exchange {
     receiver: mpsc::Receiver<Packed>,
     pub sender: mpsc::Sender<Packed>,
 
-    pub channels: HashMap<Destination, mpsc::Sender<PackedContent>>
+    pub channels: HashMap<Destination, mpsc::Sender<PackedContent>>,
+
+    logger: Logger,
 }
 
+/// Identifies a user-provided pipeline stage.
+/// Ids are chosen by the caller when the pipeline is built and must be unique.
+pub type StageId = u32;
+
+/// Identifies one stream served by a `SessionPool`.
+pub type SessionId = u64;
+
+#[derive(Debug, Clone)]
 pub enum Destination {
     Core,
     Decoder,
     Demuxer,
     Remuxer,
+    Stage(StageId),
 }
 
 impl Hash for Destination {
@@ -28,7 +42,11 @@ impl Hash for Destination {
             Destination::Core => 0.hash(state),
             Destination::Decoder => 1.hash(state),
             Destination::Demuxer => 2.hash(state),
-            Destination::Remuxer => 3.hash(state)
+            Destination::Remuxer => 3.hash(state),
+            Destination::Stage(id) => {
+                4.hash(state);
+                id.hash(state);
+            }
         }
     }
 }
@@ -52,6 +70,10 @@ impl PartialEq<Self> for Destination {
                 Destination::Remuxer => true,
                 _ => false
             },
+            Destination::Stage(id) => match other {
+                Destination::Stage(other_id) => id == other_id,
+                _ => false
+            },
         }
     }
 }
@@ -63,6 +85,20 @@ pub trait ExchangeRegistrable {
 
     fn get_sender(&self) -> mpsc::Sender<PackedContent>;
     fn get_self_as_destination(&self) -> Destination;
+
+    /// Called on registration; the logger shares observers and metrics with the whole pipeline.
+    fn set_logger(&mut self, _logger: Logger) { }
+}
+
+/// A stage split into message handling and the work that follows it,
+/// so it can be driven either by its own worker thread or by a shared pool.
+pub trait IStateMachine {
+    /// Handles one message. Returns `false` once the machine has been closed.
+    fn handle(&mut self, content: PackedContent) -> Result<bool, Box<dyn std::error::Error>>;
+
+    /// Processes whatever the handled messages made available.
+    /// Returns `false` if the machine cannot continue.
+    fn pump(&mut self) -> Result<bool, Box<dyn std::error::Error>>;
 }
 
 impl Exchange {
@@ -71,10 +107,20 @@ impl Exchange {
         Exchange {
             receiver,
             sender,
-            channels: HashMap::new()
+            channels: HashMap::new(),
+            logger: Logger::new(Destination::Core),
         }
     }
 
+    /// Attach observers to this logger to receive events from every registered stage.
+    pub fn get_logger(&self) -> Logger {
+        self.logger.clone()
+    }
+
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
     pub fn get_exchange_sender(&self) -> mpsc::Sender<Packed> {
         self.sender.clone()
     }
@@ -83,8 +129,9 @@ impl Exchange {
         self.channels.get(&channel_dest).cloned()
     }
 
-    pub fn register(&mut self, registry: &mut dyn ExchangeRegistrable) {
+    pub fn register<T: ExchangeRegistrable + ?Sized>(&mut self, registry: &mut T) {
         registry.set_exchange(self.sender.clone());
+        registry.set_logger(self.logger.for_stage(registry.get_self_as_destination()));
         self.channels.insert(registry.get_self_as_destination(), registry.get_sender());
     }
 
@@ -119,19 +166,59 @@ pub enum PackedContent {
     ToCore(PackedContentToCore),
     ToDecoder(PackedContentToDecoder),
     ToDemuxer(PackedContentToDemuxer),
-    ToRemuxer(PackedContentToRemuxer)
+    ToRemuxer(PackedContentToRemuxer),
+    ToStage(PackedContentToStage)
+}
+
+/// Data flowing from the demuxer towards the remuxer.
+/// Custom stages sitting in between receive the same items and pass them on.
+pub enum StreamItem {
+    Tag(Tag),
+    FlvHeader(FlvHeader),
+    Metadata(RawMetaData),
+    /// In-band seek marker: every stage flushes its caches when it sees it, then passes it on.
+    Seek(u32),
+}
+
+impl StreamItem {
+    /// Wraps the item into the message understood by the given destination.
+    /// Only the remuxer and pipeline stages take stream items.
+    pub fn pack(self, destination: &Destination) -> Result<Packed, Box<dyn std::error::Error>> {
+        let packed_content = match destination {
+            Destination::Remuxer => PackedContent::ToRemuxer(
+                match self {
+                    StreamItem::Tag(tag) => PackedContentToRemuxer::PushTag(tag),
+                    StreamItem::FlvHeader(header) => PackedContentToRemuxer::PushFlvHeader(header),
+                    StreamItem::Metadata(metadata) => PackedContentToRemuxer::PushMetadata(metadata),
+                    StreamItem::Seek(time_ms) => PackedContentToRemuxer::Seek(time_ms),
+                }
+            ),
+            Destination::Stage(_) => PackedContent::ToStage(PackedContentToStage::Push(self)),
+            _ => return Err(format!("{:?} is not a pipeline stage.", destination).into()),
+        };
+        Ok(Packed {
+            packed_routing: destination.clone(),
+            packed_content,
+        })
+    }
 }
 
 pub enum PackedContentToCore {
     Data(RemuxedData),
     DecoderConfig(MseDecoderConfig),
+    /// Sent by the remuxer once it has flushed for a seek; carries the keyframe time it resumes at.
+    SeekCompleted(u32),
+    /// A caption decoded from the SEI of the video samples.
+    Caption(CaptionEvent),
     Command
 }
 
 pub enum RemuxedData {
     Header(Vec<u8>),
     Audio(Vec<u8>),
-    Video(Vec<u8>)
+    Video(Vec<u8>),
+    /// A WebVTT caption fragment; only produced when the caption track is enabled.
+    Text(Vec<u8>)
 }
 
 pub enum MseDecoderConfig {
@@ -157,21 +244,13 @@ impl AudioCodecConfig {
         }
     }
 
+    /// For MP3, `audio_object_type` holds the `esds` objectTypeIndication.
+    /// Empty when the codec is unknown.
     pub fn audio_conf(&mut self) -> String {
-        match self.audio_codec_type {
-            AudioCodecType::Aac => {
-                if self.conf_string.is_empty() {
-                    self.conf_string = format!("mp4a.40.{}", self.audio_object_type);
-                }
-                self.conf_string.clone()
-            }
-            AudioCodecType::Mp3 => {
-                "mp3".to_string()
-            }
-            AudioCodecType::None => {
-                panic!("No audio codec type specified.")
-            }
+        if self.conf_string.is_empty() {
+            self.conf_string = codec_string::audio(&self.audio_codec_type, self.audio_object_type).unwrap_or_default();
         }
+        self.conf_string.clone()
     }
 }
 
@@ -193,14 +272,29 @@ impl VideoCodecConfig {
         }
     }
 
+    /// For codecs whose string is derived elsewhere, e.g. from an `hvcC` record.
+    pub fn with_codec_string(conf_string: String) -> VideoCodecConfig {
+        Self {
+            conf_string,
+            avc_profile_indication: 0,
+            avc_profile_compatibility: 0,
+            avc_level_indication: 0
+        }
+    }
+
     pub fn video_conf(&mut self) -> String {
-        self.conf_string = format!("avc1.{:x}{:x}{:x}", self.avc_profile_indication, self.avc_profile_compatibility, self.avc_level_indication);
+        if self.avc_profile_indication == 0 && !self.conf_string.is_empty() {
+            return self.conf_string.clone();
+        }
+        self.conf_string = codec_string::avc(self.avc_profile_indication, self.avc_profile_compatibility, self.avc_level_indication);
         self.conf_string.clone()
     }
 }
 
 pub enum PackedContentToDecoder {
     PushData(VecDeque<u8>),
+    /// Seek to the nearest keyframe at or before the given time in ms.
+    Seek(u32),
 
     StartDecoding,
     StopDecoding,
@@ -212,6 +306,8 @@ pub enum PackedContentToDecoder {
 pub enum PackedContentToDemuxer {
     PushTag(Tag),
     PushFlvHeader(FlvHeader),
+    /// Sent by the decoder after repositioning; carries the time of the keyframe it resumes at.
+    Seek(u32),
 
     StartDemuxing,
     StopDemuxing,
@@ -224,10 +320,20 @@ pub enum PackedContentToRemuxer {
     PushTag(Tag),
     PushFlvHeader(FlvHeader),
     PushMetadata(RawMetaData),
+    Seek(u32),
 
     StartRemuxing,
     StopRemuxing,
     CloseWorkerThread,
 
     Now
-}
\ No newline at end of file
+}
+pub enum PackedContentToStage {
+    Push(StreamItem),
+
+    StartProcessing,
+    StopProcessing,
+    CloseWorkerThread,
+
+    Now
+}
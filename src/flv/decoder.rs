@@ -1,20 +1,38 @@
-use crate::exchange::{Destination, ExchangeRegistrable, Packed, PackedContent, PackedContentToDecoder, PackedContentToDemuxer};
+use crate::exchange::{Destination, ExchangeRegistrable, IStateMachine, Packed, PackedContent, PackedContentToDecoder, PackedContentToDemuxer};
 use crate::flv::header::{AudioTagHeader, EncryptionTagHeader, FilterParameters, FlvHeader, TagHeader, VideoTagHeader};
+use crate::flv::meta::{KeyframeIndex, RawMetaData};
 use crate::flv::script::ScriptTagBody;
 use crate::flv::tag::{EncryptedTagBody, NormalTagBody, Tag, TagBody, TagType};
 use crate::io::bit::BitIO;
+use crate::event::{Logger, Metrics};
 use std::collections::VecDeque;
 use std::sync::mpsc;
 use std::thread;
 use std::thread::JoinHandle;
 
+const FLV_HEADER_SIZE: usize = 9;
+
 pub struct Decoder {
     data: VecDeque<u8>,
+    // index of the next unread byte in `data`.
+    // bytes before it are kept around while seekable, so that seeking backwards is possible.
+    cursor: usize,
+    // absolute stream offset of data[0].
+    base_offset: u64,
+    seekable: bool,
+
     previous_tag_size: u32,
+
+    metadata_keyframes: Option<KeyframeIndex>,
+    scanned_keyframes: KeyframeIndex,
+
     channel_exchange: Option<mpsc::Sender<Packed>>,
     channel_receiver: mpsc::Receiver<PackedContent>,
     channel_sender: mpsc::Sender<PackedContent>,
     decoding: bool,
+    header_decoded: bool,
+
+    logger: Logger,
 }
 
 impl ExchangeRegistrable for Decoder {
@@ -29,6 +47,10 @@ impl ExchangeRegistrable for Decoder {
     fn get_self_as_destination(&self) -> Destination {
         Destination::Decoder
     }
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
 }
 
 impl Decoder {
@@ -36,11 +58,49 @@ impl Decoder {
         let (channel_sender, channel_receiver) = mpsc::channel();
         Decoder {
             data,
+            cursor: 0,
+            base_offset: 0,
+            seekable: false,
             previous_tag_size: 0,
+            metadata_keyframes: None,
+            scanned_keyframes: KeyframeIndex::new(),
             channel_exchange: None,
             channel_receiver,
             channel_sender,
             decoding: false,
+            header_decoded: false,
+            logger: Logger::new(Destination::Decoder),
+        }
+    }
+
+    #[inline]
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    /// When seekable, consumed bytes are retained so `Core::seek` can jump backwards.
+    /// Off by default, so that live streams keep memory bounded; turn it on for VOD files.
+    pub fn set_seekable(&mut self, flag: bool) {
+        self.seekable = flag;
+    }
+
+    /// Absolute offset of the next unread byte.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.base_offset + self.cursor as u64
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.cursor
+    }
+
+    /// Drops the consumed bytes when the decoder is not seekable.
+    fn compact(&mut self) {
+        if !self.seekable && self.cursor > 0 {
+            self.data.drain(0..self.cursor);
+            self.base_offset += self.cursor as u64;
+            self.cursor = 0;
         }
     }
 
@@ -54,7 +114,9 @@ impl Decoder {
 
     #[inline]
     pub fn drain_u8(&mut self) -> u8 {
-        self.data.pop_front().unwrap()
+        let byte = self.data[self.cursor];
+        self.cursor += 1;
+        byte
     }
 
     #[inline]
@@ -68,13 +130,15 @@ impl Decoder {
 
     #[inline]
     pub fn drain_bytes_vec(&mut self, size: usize) -> Vec<u8> {
-        let drained = self.data.drain(0..size).collect::<Vec<_>>();
+        let drained = self.data.range(self.cursor..self.cursor + size).copied().collect::<Vec<_>>();
+        self.cursor += size;
         drained
     }
 
     #[inline]
     pub fn drain_bytes_deque(&mut self, size: usize) -> VecDeque<u8> {
-        let drained = self.data.drain(0..size).collect::<VecDeque<_>>();
+        let drained = self.data.range(self.cursor..self.cursor + size).copied().collect::<VecDeque<_>>();
+        self.cursor += size;
         drained
     }
 
@@ -247,6 +311,8 @@ impl Decoder {
         let has_audio = bits.read_bit(5);
         let has_video = bits.read_bit(7);
         let data_offset = self.drain_u32();
+        Metrics::add(&self.logger.metrics().bytes_in, FLV_HEADER_SIZE as u64);
+        self.header_decoded = true;
         Ok(
             FlvHeader::new(
                 signature,
@@ -334,43 +400,14 @@ impl Decoder {
     pub fn decode_body(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         loop {
             if let Ok(received) = self.channel_receiver.recv() {
-                if let PackedContent::ToDecoder(packed_content) = received {
-                    match packed_content {
-                        PackedContentToDecoder::PushData(mut data) => {
-                            self.data.append(&mut data)
-                        }
-                        PackedContentToDecoder::StartDecoding => {
-                            println!("[Decoder] Start decoding.");
-                            self.set_decoding(true);
-                        }
-                        PackedContentToDecoder::StopDecoding => {
-                            println!("[Decoder] Stop decoding.");
-                            self.set_decoding(false);
-                        }
-                        PackedContentToDecoder::CloseWorkerThread => {
-                            println!("[Decoder] Closing worker thread.");
-                            return Ok(());
-                        }
-                        PackedContentToDecoder::Now => {
-                            // this will literally do nothing.
-                            // just applied to remove potential blockage.
-                        }
-                    }
+                if !self.handle(received)? || !self.pump()? {
+                    return Ok(());
                 }
             } else {
                 // todo: use a better way to replace recv().
-                println!("[Decoder] Channel closed.");
+                self.logger.warn("Channel closed.");
                 return Ok(());
             }
-
-            'decoding: loop {
-                if self.data.is_empty() || (!self.decoding) {
-                    break 'decoding;
-                }
-                if self.decode_body_once().is_err() {
-                    break 'decoding;
-                }
-            }
         }
     }
 
@@ -379,9 +416,10 @@ impl Decoder {
 
         let previous_tag_size = self.drain_u32();
 
-        if self.data.is_empty() {
+        if self.remaining() == 0 {
             return Err("No more data.".into());
         }
+        let tag_position = self.position();
 
         //dbg!(previous_tag_size);
         if previous_tag_size == self.previous_tag_size {
@@ -389,10 +427,23 @@ impl Decoder {
             //dbg!(tag.data_size + HEADER_SIZE);
             self.previous_tag_size = tag.data_size + HEADER_SIZE;
 
+            let metrics = self.logger.metrics();
+            Metrics::add(&metrics.bytes_in, (4 + tag.data_size + HEADER_SIZE) as u64);
+            match tag.tag_type {
+                TagType::Audio => Metrics::add(&metrics.audio_tags_decoded, 1),
+                TagType::Video => Metrics::add(&metrics.video_tags_decoded, 1),
+                TagType::Script => Metrics::add(&metrics.script_tags_decoded, 1),
+                TagType::Encryption => {}
+            }
+
+            self.index_tag(&tag, tag_position);
+
             // dbg!(&tag);
             self.send_tag_to_demuxer(tag)?;
+            self.compact();
             Ok(())
         } else {
+            self.logger.error(format!("Tag size mismatch: expected {}, read {}.", previous_tag_size, self.previous_tag_size));
             Err(
                 Box::new(
                     std::io::Error::new(
@@ -404,6 +455,99 @@ impl Decoder {
         }
     }
 
+    fn index_tag(&mut self, tag: &Tag, position: u64) {
+        match tag.tag_header {
+            TagHeader::Video(ref header) if header.frame_type == 1 => {
+                self.scanned_keyframes.push(tag.timestamp, position);
+            }
+            TagHeader::Script => {
+                if let TagBody::Normal(NormalTagBody::Script(ref script)) = tag.tag_body {
+                    if script.name.data == "onMetaData" {
+                        self.metadata_keyframes = KeyframeIndex::from_metadata(&RawMetaData::new(script.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Walks tag headers from the current position without consuming anything,
+    /// adding keyframes to the scan index until `time_ms` is passed or the data runs out.
+    fn scan_keyframes_until(&mut self, time_ms: u32) {
+        const HEADER_SIZE: usize = 11;
+        let mut idx = self.cursor;
+        // previous tag size + tag header + first byte of the video tag body.
+        while idx + 4 + HEADER_SIZE < self.data.len() {
+            let tag_start = idx + 4;
+            let tag_type = self.data[tag_start] & 0x1F;
+            let data_size = ((self.data[tag_start + 1] as usize) << 16)
+                | ((self.data[tag_start + 2] as usize) << 8)
+                | (self.data[tag_start + 3] as usize);
+            let timestamp = Self::concat_ts(
+                ((self.data[tag_start + 4] as u32) << 16)
+                    | ((self.data[tag_start + 5] as u32) << 8)
+                    | (self.data[tag_start + 6] as u32),
+                self.data[tag_start + 7],
+            );
+
+            let body_start = tag_start + HEADER_SIZE;
+            if tag_type == 9 && data_size > 0 && body_start < self.data.len() && (self.data[body_start] >> 4) & 0x07 == 1 {
+                self.scanned_keyframes.push(timestamp, self.base_offset + tag_start as u64);
+            }
+            if timestamp > time_ms {
+                break;
+            }
+            idx = body_start + data_size;
+        }
+    }
+
+    /// Repositions the decoder at the nearest keyframe at or before `time_ms`
+    /// and tells the downstream stages to flush.
+    pub fn seek(&mut self, time_ms: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let in_range = |decoder: &Self, position: u64| {
+            position >= decoder.base_offset + 4 && position < decoder.base_offset + decoder.data.len() as u64
+        };
+
+        let mut target = self.metadata_keyframes
+            .as_ref()
+            .and_then(|index| index.lookup(time_ms))
+            .filter(|entry| in_range(self, entry.position));
+
+        if target.is_none() {
+            let scanned_far_enough = self.scanned_keyframes
+                .last()
+                .map(|last| last.time_ms >= time_ms)
+                .unwrap_or(false);
+            if !scanned_far_enough {
+                self.scan_keyframes_until(time_ms);
+            }
+            target = self.scanned_keyframes
+                .lookup(time_ms)
+                .filter(|entry| in_range(self, entry.position));
+        }
+
+        let target = match target {
+            Some(target) => target,
+            None => return Err("No keyframe available for the requested time.".into()),
+        };
+
+        // the previous tag size field sits right before the tag.
+        self.cursor = (target.position - self.base_offset) as usize - 4;
+        let mut size_bytes = [0u8; 4];
+        for (i, byte) in size_bytes.iter_mut().enumerate() {
+            *byte = self.data[self.cursor + i];
+        }
+        self.previous_tag_size = u32::from_be_bytes(size_bytes);
+
+        self.logger.info(format!("Seek to {} ms, resuming at keyframe {} ms (offset {}).", time_ms, target.time_ms, target.position));
+        self.send_to_demuxer(
+            Packed {
+                packed_routing: Destination::Demuxer,
+                packed_content: PackedContent::ToDemuxer(PackedContentToDemuxer::Seek(target.time_ms)),
+            }
+        )
+    }
+
     fn send_to_demuxer(&mut self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
         if let Err(e) = self.channel_exchange
             .as_ref()
@@ -432,13 +576,7 @@ impl Decoder {
     }
 
     fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        loop {
-            // this is to ensure the header is read.
-            if let Ok(flv_header) = self.decode_header() {
-                self.send_header_to_demuxer(flv_header)?;
-                break;
-            }
-        }
+        // the header is read by the first pump once enough data is available.
         // todo: use a better way to control the decoding loop.
         self.decode_body()?;
         Ok(())
@@ -454,4 +592,59 @@ impl Decoder {
             }
         })
     }
-}
\ No newline at end of file
+}
+
+impl IStateMachine for Decoder {
+    fn handle(&mut self, content: PackedContent) -> Result<bool, Box<dyn std::error::Error>> {
+        if let PackedContent::ToDecoder(packed_content) = content {
+            match packed_content {
+                PackedContentToDecoder::PushData(mut data) => {
+                    self.data.append(&mut data)
+                }
+                PackedContentToDecoder::StartDecoding => {
+                    self.logger.info("Start decoding.");
+                    self.set_decoding(true);
+                }
+                PackedContentToDecoder::StopDecoding => {
+                    self.logger.info("Stop decoding.");
+                    self.set_decoding(false);
+                }
+                PackedContentToDecoder::CloseWorkerThread => {
+                    self.logger.info("Closing worker thread.");
+                    return Ok(false);
+                }
+                PackedContentToDecoder::Seek(time_ms) => {
+                    if let Err(e) = self.seek(time_ms) {
+                        self.logger.error(format!("Seek to {} ms failed: {}", time_ms, e));
+                    }
+                }
+                PackedContentToDecoder::Now => {
+                    // this will literally do nothing.
+                    // just applied to remove potential blockage.
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn pump(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        // this is to ensure the header is read.
+        if !self.header_decoded {
+            if self.remaining() < FLV_HEADER_SIZE {
+                return Ok(true);
+            }
+            let flv_header = self.decode_header()?;
+            self.send_header_to_demuxer(flv_header)?;
+        }
+
+        'decoding: loop {
+            if self.remaining() == 0 || (!self.decoding) {
+                break 'decoding;
+            }
+            if self.decode_body_once().is_err() {
+                break 'decoding;
+            }
+        }
+        Ok(true)
+    }
+}
@@ -1,24 +1,36 @@
 use std::collections::VecDeque;
-use crate::exchange::{Destination, ExchangeRegistrable, Packed, PackedContent, PackedContentToDemuxer, PackedContentToRemuxer};
+use crate::exchange::{Destination, ExchangeRegistrable, IStateMachine, Packed, PackedContent, PackedContentToDemuxer, StreamItem};
 use std::sync::mpsc;
 use std::thread::JoinHandle;
 use crate::flv::header::FlvHeader;
 use crate::flv::meta::RawMetaData;
 use crate::flv::tag::{NormalTagBody, Tag, TagBody, TagType};
+use crate::event::Logger;
 
 pub struct Demuxer {
     channel_exchange: Option<mpsc::Sender<Packed>>,
     channel_receiver: mpsc::Receiver<PackedContent>,
     channel_sender: mpsc::Sender<PackedContent>,
     demuxing: bool,
+    downstream: Destination,
 
     cache_video_tags: VecDeque<Tag>,
     cache_audio_tags: VecDeque<Tag>,
     cache_script_tags: VecDeque<Tag>,
     cache_metadata: Option<RawMetaData>,
     cache_flv_header: Option<FlvHeader>,
+
+    // tracks announced by the flv header; only those are waited for when interleaving.
+    expect_audio: bool,
+    expect_video: bool,
+    max_interleave_delta: u32,
+
+    logger: Logger,
 }
 
+/// Default for `Demuxer::set_max_interleave_delta`, in ms.
+pub const DEFAULT_MAX_INTERLEAVE_DELTA: u32 = 1000;
+
 impl Demuxer {
     pub fn new() -> Self {
         let (channel_sender, channel_receiver) = mpsc::channel();
@@ -27,11 +39,16 @@ impl Demuxer {
             channel_receiver,
             channel_sender,
             demuxing: false,
+            downstream: Destination::Remuxer,
             cache_video_tags: VecDeque::new(),
             cache_audio_tags: VecDeque::new(),
             cache_script_tags: VecDeque::new(),
             cache_metadata: None,
             cache_flv_header: None,
+            expect_audio: false,
+            expect_video: false,
+            max_interleave_delta: DEFAULT_MAX_INTERLEAVE_DELTA,
+            logger: Logger::new(Destination::Demuxer),
         }
     }
 
@@ -39,6 +56,18 @@ impl Demuxer {
         self.demuxing = flag;
     }
 
+    /// Sets where demuxed data goes next. Defaults to the remuxer.
+    pub fn set_downstream(&mut self, destination: Destination) {
+        self.downstream = destination;
+    }
+
+    /// Tags are sent in timestamp order across tracks. When a track announced by the flv header
+    /// has nothing cached, the others are held back until they span more than `delta` ms,
+    /// after which they are sent without waiting for it.
+    pub fn set_max_interleave_delta(&mut self, delta: u32) {
+        self.max_interleave_delta = delta;
+    }
+
     fn process_incoming_tag(&mut self, tag: Tag) {
         if tag.tag_type != TagType::Script {
             match tag.tag_type {
@@ -73,45 +102,53 @@ impl Demuxer {
         }
     }
 
-    fn send_from_cache(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(flv_header) = self.cache_flv_header.take() {
-            let pack = Packed {
-                packed_routing: Destination::Remuxer,
-                packed_content: PackedContent::ToRemuxer(PackedContentToRemuxer::PushFlvHeader(flv_header)),
-            };
-            self.send_to_remuxer(pack)?;
+    fn send_item(&mut self, item: StreamItem) -> Result<(), Box<dyn std::error::Error>> {
+        let pack = item.pack(&self.downstream)?;
+        self.send_to_remuxer(pack)
+    }
+
+    /// Time span covered by all cached tags.
+    fn cached_span(&self) -> u32 {
+        let queues = [&self.cache_audio_tags, &self.cache_video_tags, &self.cache_script_tags];
+        let first = queues.iter().filter_map(|queue| queue.front()).map(|tag| tag.timestamp).min();
+        let last = queues.iter().filter_map(|queue| queue.back()).map(|tag| tag.timestamp).max();
+        match (first, last) {
+            (Some(first), Some(last)) => last.saturating_sub(first),
+            _ => 0
         }
+    }
 
-        if let Some(metadata) = self.cache_metadata.take() {
-            let pack = Packed {
-                packed_routing: Destination::Remuxer,
-                packed_content: PackedContent::ToRemuxer(PackedContentToRemuxer::PushMetadata(metadata)),
-            };
-            self.send_to_remuxer(pack)?;
+    /// Pops the cached tag with the smallest timestamp, or `None` if it is better to wait for a lagging track.
+    /// On ties, audio goes before video and video before script data, as they did before interleaving.
+    fn next_interleaved(&mut self, flush: bool) -> Option<Tag> {
+        let lagging = (self.expect_audio && self.cache_audio_tags.is_empty())
+            || (self.expect_video && self.cache_video_tags.is_empty());
+        if !flush && lagging && self.cached_span() <= self.max_interleave_delta {
+            return None;
         }
 
-        while let Some(audio) = self.cache_audio_tags.pop_front() {
-            let pack = Packed {
-                packed_routing: Destination::Remuxer,
-                packed_content: PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(audio)),
-            };
-            self.send_to_remuxer(pack)?;
+        let queues = [&mut self.cache_audio_tags, &mut self.cache_video_tags, &mut self.cache_script_tags];
+        let (_, queue) = queues
+            .iter()
+            .enumerate()
+            .filter_map(|(order, queue)| queue.front().map(|tag| (tag.timestamp, order)))
+            .min()?;
+        queues[queue].pop_front()
+    }
+
+    /// Sends the header, the metadata and every tag that can be interleaved.
+    /// With `flush`, lagging tracks are not waited for and the caches are emptied.
+    fn send_from_cache(&mut self, flush: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(flv_header) = self.cache_flv_header.take() {
+            self.send_item(StreamItem::FlvHeader(flv_header))?;
         }
 
-        while let Some(video) = self.cache_video_tags.pop_front() {
-            let pack = Packed {
-                packed_routing: Destination::Remuxer,
-                packed_content: PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(video)),
-            };
-            self.send_to_remuxer(pack)?;
+        if let Some(metadata) = self.cache_metadata.take() {
+            self.send_item(StreamItem::Metadata(metadata))?;
         }
 
-        while let Some(script) = self.cache_script_tags.pop_front() {
-            let pack = Packed {
-                packed_routing: Destination::Remuxer,
-                packed_content: PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(script)),
-            };
-            self.send_to_remuxer(pack)?;
+        while let Some(tag) = self.next_interleaved(flush) {
+            self.send_item(StreamItem::Tag(tag))?;
         }
 
         Ok(())
@@ -120,47 +157,14 @@ impl Demuxer {
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         loop {
             if let Ok(received) = self.channel_receiver.recv() {
-                match received {
-                    PackedContent::ToDemuxer(content) => {
-                        match content {
-                            PackedContentToDemuxer::PushTag(tag) => {
-                                // todo: implement tag processing.
-                                self.process_incoming_tag(tag);
-                            }
-                            PackedContentToDemuxer::PushFlvHeader(flv_header) => {
-                                println!("[Demuxer] Received flv header.");
-                                self.cache_flv_header = Some(flv_header);
-                            }
-                            PackedContentToDemuxer::StartDemuxing => {
-                                println!("[Demuxer] Start demuxing.");
-                                self.set_demuxing(true);
-                            }
-                            PackedContentToDemuxer::StopDemuxing => {
-                                println!("[Demuxer] Stop demuxing.");
-                                self.set_demuxing(false);
-                            }
-                            PackedContentToDemuxer::CloseWorkerThread => {
-                                println!("[Demuxer] Close worker thread.");
-                                return Ok(());
-                            }
-                            PackedContentToDemuxer::Now => {
-                                // just to temporarily remove thread blockage.
-                            }
-                        }
-                    }
-                    _ => {}
+                if !self.handle(received)? || !self.pump()? {
+                    return Ok(());
                 }
             } else {
                 // todo: use a better way instead of recv().
-                println!("[Demuxer] Channel closed.");
+                self.logger.warn("Channel closed.");
                 return Ok(());
             }
-
-            if !self.demuxing {
-                continue;
-            }
-
-            self.send_from_cache()?;
         }
     }
 
@@ -187,4 +191,61 @@ impl ExchangeRegistrable for Demuxer {
     fn get_self_as_destination(&self) -> Destination {
         Destination::Demuxer
     }
-}
\ No newline at end of file
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+}
+
+impl IStateMachine for Demuxer {
+    fn handle(&mut self, content: PackedContent) -> Result<bool, Box<dyn std::error::Error>> {
+        if let PackedContent::ToDemuxer(content) = content {
+            match content {
+                PackedContentToDemuxer::PushTag(tag) => {
+                    // todo: implement tag processing.
+                    self.process_incoming_tag(tag);
+                }
+                PackedContentToDemuxer::PushFlvHeader(flv_header) => {
+                    self.logger.debug("Received flv header.");
+                    self.expect_audio = flv_header.type_flags_audio;
+                    self.expect_video = flv_header.type_flags_video;
+                    self.cache_flv_header = Some(flv_header);
+                }
+                PackedContentToDemuxer::Seek(time_ms) => {
+                    self.logger.debug(format!("Seek to {} ms, flushing caches.", time_ms));
+                    self.cache_audio_tags.clear();
+                    self.cache_video_tags.clear();
+                    self.cache_script_tags.clear();
+                    self.send_item(StreamItem::Seek(time_ms))?;
+                }
+                PackedContentToDemuxer::StartDemuxing => {
+                    self.logger.info("Start demuxing.");
+                    self.set_demuxing(true);
+                }
+                PackedContentToDemuxer::StopDemuxing => {
+                    self.logger.info("Stop demuxing.");
+                    // nothing more is waited for; hand over whatever is still held back.
+                    if self.demuxing {
+                        self.send_from_cache(true)?;
+                    }
+                    self.set_demuxing(false);
+                }
+                PackedContentToDemuxer::CloseWorkerThread => {
+                    self.logger.info("Closing worker thread.");
+                    return Ok(false);
+                }
+                PackedContentToDemuxer::Now => {
+                    // just to temporarily remove thread blockage.
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn pump(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.demuxing {
+            self.send_from_cache(false)?;
+        }
+        Ok(true)
+    }
+}
@@ -35,13 +35,26 @@ pub struct AudioTagHeader {
     // UB1
     pub sound_type: bool,
     // UI8
-    // if sound_format == 10
+    // if sound_format == 10, or with an ex header.
+    // ex header packet types are mapped onto 0 (sequence header) and 1 (raw); other ones leave this unset.
     pub aac_packet_type: Option<u8>,
+    // Enhanced FLV: set when sound_format == 9, e.g. `Opus`.
+    pub fourcc: Option<[u8; 4]>,
 }
 
+/// Enhanced FLV sound format: an audio packet type and a FourCC follow.
+pub const SOUND_FORMAT_EX_HEADER: u8 = 9;
+
+/// Enhanced FLV audio packet types, carried in the low nibble of the first byte.
+pub const AUDIO_PACKET_TYPE_SEQUENCE_START: u8 = 0;
+pub const AUDIO_PACKET_TYPE_CODED_FRAMES: u8 = 1;
+pub const AUDIO_PACKET_TYPE_SEQUENCE_END: u8 = 2;
+pub const AUDIO_PACKET_TYPE_MULTICHANNEL_CONFIG: u8 = 4;
+pub const AUDIO_PACKET_TYPE_MULTITRACK: u8 = 5;
+
 impl AudioTagHeader {
     pub fn new(sound_format: u8, sound_rate: u8, sound_size: bool, sound_type: bool, aac_packet_type: Option<u8>) -> Self {
-        Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type }
+        Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type, fourcc: None }
     }
 
     pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
@@ -52,51 +65,127 @@ impl AudioTagHeader {
         let sound_size = bits.read_bit(6);
         let sound_type = bits.read_bit(7);
 
+        if sound_format == SOUND_FORMAT_EX_HEADER {
+            // the low nibble is the packet type rather than rate, size and type.
+            let packet_type = bits.read_range(4, 7);
+            *header_size += 4;
+            let fourcc = decoder.drain_bytes::<4>();
+            let aac_packet_type = match packet_type {
+                AUDIO_PACKET_TYPE_SEQUENCE_START => Some(0),
+                AUDIO_PACKET_TYPE_CODED_FRAMES => Some(1),
+                _ => None,
+            };
+            return Ok(Self { sound_format, sound_rate: 0, sound_size: false, sound_type: false, aac_packet_type, fourcc: Some(fourcc) });
+        }
+
         let aac_packet_type = if sound_format == 10 {
             *header_size += 1;
             Some(decoder.drain_u8())
         } else {
             None
         };
-        Ok(Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type })
+        Ok(Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type, fourcc: None })
+    }
+
+    #[inline]
+    pub fn is_ex_header(&self) -> bool {
+        self.fourcc.is_some()
     }
 }
 
+/// Enhanced FLV video packet types, carried in the low nibble when the ex header bit is set.
+pub const VIDEO_PACKET_TYPE_SEQUENCE_START: u8 = 0;
+pub const VIDEO_PACKET_TYPE_CODED_FRAMES: u8 = 1;
+pub const VIDEO_PACKET_TYPE_SEQUENCE_END: u8 = 2;
+/// Coded frames without a composition time, which is then 0.
+pub const VIDEO_PACKET_TYPE_CODED_FRAMES_X: u8 = 3;
+pub const VIDEO_PACKET_TYPE_METADATA: u8 = 4;
+pub const VIDEO_PACKET_TYPE_MPEG2TS_SEQUENCE_START: u8 = 5;
+
+/// Legacy codec ids whose tags carry a packet type and a composition time.
+const CODEC_ID_AVC: u8 = 7;
+const CODEC_ID_HEVC: u8 = 12;
+
 #[derive(Debug, Clone)]
 pub struct VideoTagHeader {
     // UB4
     pub frame_type: u8,
     // UB4
+    // 0 when the codec is signalled by `fourcc` instead.
     pub codec_id: u8,
     // UI24
-    // if codec_id == 7
+    // if codec_id == 7 or 12, or with an ex header.
+    // ex header packet types are mapped onto 0 (sequence header), 1 (coded frames), 2 (end of sequence);
+    // other ones leave this unset.
     pub avc_packet_type: Option<u8>,
     // SI24
-    // if codec_id == 7
+    // if codec_id == 7 or 12, or with an ex header carrying coded frames.
     pub composition_time: Option<i32>,
+    // Enhanced FLV: set when the ex header bit is, e.g. `hvc1`.
+    pub fourcc: Option<[u8; 4]>,
 }
 
 impl VideoTagHeader {
     pub fn new(frame_type: u8, codec_id: u8, avc_packet_type: Option<u8>, composition_time: Option<i32>) -> Self {
-        Self { frame_type, codec_id, avc_packet_type, composition_time }
+        Self { frame_type, codec_id, avc_packet_type, composition_time, fourcc: None }
     }
 
     pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
         *header_size += 1;
-        let bits = BitIO::new(decoder.drain_u8());
+        let first = decoder.drain_u8();
+        if first & 0x80 != 0 {
+            return Self::parse_ex_header(decoder, header_size, first);
+        }
+
+        let bits = BitIO::new(first);
         let frame_type = bits.read_range(0, 3);
         let codec_id = bits.read_range(4, 7);
 
         let mut avc_packet_type = None;
         let mut composition_time = None;
-        if codec_id == 7 {
+        if codec_id == CODEC_ID_AVC || codec_id == CODEC_ID_HEVC {
             *header_size += 1;
             avc_packet_type = Some(decoder.drain_u8());
 
             *header_size += 3;
             composition_time = Some(decoder.drain_i24());
         }
-        Ok(Self { frame_type, codec_id, avc_packet_type, composition_time })
+        Ok(Self { frame_type, codec_id, avc_packet_type, composition_time, fourcc: None })
+    }
+
+    fn parse_ex_header(decoder: &mut Decoder, header_size: &mut usize, first: u8) -> Result<Self, Box<dyn std::error::Error>> {
+        let frame_type = (first >> 4) & 0x07;
+        let packet_type = first & 0x0F;
+
+        *header_size += 4;
+        let fourcc = decoder.drain_bytes::<4>();
+
+        let mut composition_time = None;
+        let avc_packet_type = match packet_type {
+            VIDEO_PACKET_TYPE_SEQUENCE_START => Some(0),
+            VIDEO_PACKET_TYPE_CODED_FRAMES => {
+                // only the codecs with B-frames carry a composition time.
+                if &fourcc == b"avc1" || &fourcc == b"hvc1" {
+                    *header_size += 3;
+                    composition_time = Some(decoder.drain_i24());
+                } else {
+                    composition_time = Some(0);
+                }
+                Some(1)
+            }
+            VIDEO_PACKET_TYPE_CODED_FRAMES_X => {
+                composition_time = Some(0);
+                Some(1)
+            }
+            VIDEO_PACKET_TYPE_SEQUENCE_END => Some(2),
+            _ => None,
+        };
+        Ok(Self { frame_type, codec_id: 0, avc_packet_type, composition_time, fourcc: Some(fourcc) })
+    }
+
+    #[inline]
+    pub fn is_ex_header(&self) -> bool {
+        self.fourcc.is_some()
     }
 }
 
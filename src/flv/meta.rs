@@ -103,4 +103,91 @@ impl XMPData {
     pub fn new(xmp: String) -> Self {
         Self { xmp }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyframeEntry {
+    pub time_ms: u32,
+    /// Byte offset of the tag in the stream, counted from the first byte of the flv header.
+    pub position: u64,
+}
+
+/// Time to byte-offset index of the video keyframes, used for seeking.
+/// Built either from the `keyframes` object of onMetaData or while scanning the stream.
+#[derive(Debug, Clone, Default)]
+pub struct KeyframeIndex {
+    entries: Vec<KeyframeEntry>,
+}
+
+impl KeyframeIndex {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    /// Reads `keyframes.times` (seconds) and `keyframes.filepositions` from onMetaData.
+    pub fn from_metadata(metadata: &RawMetaData) -> Option<Self> {
+        let properties = match metadata.try_get("keyframes")? {
+            ScriptData::Object(object) => object.properties,
+            ScriptData::EcmaArray(array) => array.properties,
+            _ => return None,
+        };
+
+        let numbers = |name: &str| -> Option<Vec<f64>> {
+            let prop = properties.iter().find(|prop| prop.name.data == name)?;
+            match &prop.value {
+                ScriptData::StrictArray(array) => Some(
+                    array.values.iter().filter_map(|value| match value {
+                        ScriptData::Number(number) => Some(*number),
+                        _ => None,
+                    }).collect()
+                ),
+                _ => None,
+            }
+        };
+
+        let times = numbers("times")?;
+        let positions = numbers("filepositions")?;
+
+        let mut index = Self::new();
+        for (time, position) in times.iter().zip(positions.iter()) {
+            index.push((time * 1000.0) as u32, *position as u64);
+        }
+        if index.is_empty() {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    /// Appends an entry. Entries that do not move forward in both time and position are ignored,
+    /// so rescanning a region does not produce duplicates.
+    pub fn push(&mut self, time_ms: u32, position: u64) {
+        if let Some(last) = self.entries.last() {
+            if time_ms < last.time_ms || position <= last.position {
+                return;
+            }
+        }
+        self.entries.push(KeyframeEntry { time_ms, position });
+    }
+
+    /// Returns the nearest keyframe at or before `time_ms`.
+    /// Targets before the first keyframe resolve to the first keyframe.
+    pub fn lookup(&self, time_ms: u32) -> Option<KeyframeEntry> {
+        let idx = self.entries.partition_point(|entry| entry.time_ms <= time_ms);
+        if idx == 0 {
+            self.entries.first().copied()
+        } else {
+            Some(self.entries[idx - 1])
+        }
+    }
+
+    #[inline]
+    pub fn last(&self) -> Option<KeyframeEntry> {
+        self.entries.last().copied()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
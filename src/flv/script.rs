@@ -16,7 +16,7 @@ pub fn parse_object(data: &mut Decoder) -> Result<ScriptData, Box<dyn std::error
         11 => ScriptData::Date(ScriptDataDate::parse_no_marker(data)?),
         12 => ScriptData::LongString(ScriptDataLongString::parse_no_marker(data)?),
         _ => {
-            println!("Reserved type {}.", data_type);
+            data.logger().warn(format!("Reserved script data type {}.", data_type));
             ScriptData::NotImplemented
         }
     };
@@ -216,7 +216,8 @@ impl ScriptStrictArray {
     pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptStrictArray, Box<dyn std::error::Error>> {
         let length = data.drain_u32();
         let mut values = Vec::with_capacity(length as usize);
-        for _ in 0..length + 1 {
+        // unlike ecma arrays, strict arrays are not terminated by an end marker.
+        for _ in 0..length {
             let value = parse_object(data)?;
             values.push(value);
         }
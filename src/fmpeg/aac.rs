@@ -0,0 +1,436 @@
+use crate::io::bit::BitReader;
+
+pub const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000,
+    44100, 32000, 24000, 22050,
+    16000, 12000, 11025, 8000,
+    7350
+];
+
+const ESCAPE_AUDIO_OBJECT_TYPE: u8 = 31;
+const EXPLICIT_SAMPLING_FREQUENCY_INDEX: u8 = 15;
+
+pub const AOT_AAC_LC: u8 = 2;
+pub const AOT_SBR: u8 = 5;
+pub const AOT_ER_BSAC: u8 = 22;
+pub const AOT_PS: u8 = 29;
+
+const SYNC_EXTENSION_SBR: u32 = 0x2B7;
+const SYNC_EXTENSION_PS: u32 = 0x548;
+
+/// Object types using GASpecificConfig.
+const GA_AUDIO_OBJECT_TYPES: [u8; 12] = [1, 2, 3, 4, 6, 7, 17, 19, 20, 21, 22, 23];
+
+/// Channel layout described by a program_config_element (ISO/IEC 14496-3, 4.4.1.1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramConfigElement {
+    pub element_instance_tag: u8,
+    pub object_type: u8,
+    pub sampling_frequency_index: u8,
+    pub front_channels: u8,
+    pub side_channels: u8,
+    pub back_channels: u8,
+    pub lfe_channels: u8,
+    pub comment: Vec<u8>,
+}
+
+impl ProgramConfigElement {
+    fn parse(reader: &mut BitReader) -> Result<Self, Box<dyn std::error::Error>> {
+        let element_instance_tag = reader.read_bits(4)? as u8;
+        let object_type = reader.read_bits(2)? as u8;
+        let sampling_frequency_index = reader.read_bits(4)? as u8;
+        let num_front_channel_elements = reader.read_bits(4)?;
+        let num_side_channel_elements = reader.read_bits(4)?;
+        let num_back_channel_elements = reader.read_bits(4)?;
+        let num_lfe_channel_elements = reader.read_bits(2)?;
+        let num_assoc_data_elements = reader.read_bits(3)?;
+        let num_valid_cc_elements = reader.read_bits(4)?;
+
+        // mono_mixdown, stereo_mixdown, matrix_mixdown
+        if reader.read_bit()? {
+            reader.skip_bits(4)?;
+        }
+        if reader.read_bit()? {
+            reader.skip_bits(4)?;
+        }
+        if reader.read_bit()? {
+            reader.skip_bits(3)?;
+        }
+
+        let front_channels = Self::read_channel_elements(reader, num_front_channel_elements)?;
+        let side_channels = Self::read_channel_elements(reader, num_side_channel_elements)?;
+        let back_channels = Self::read_channel_elements(reader, num_back_channel_elements)?;
+        reader.skip_bits(4 * num_lfe_channel_elements as usize)?;
+        reader.skip_bits(4 * num_assoc_data_elements as usize)?;
+        // cc_element_is_ind_sw + valid_cc_element_tag_select
+        reader.skip_bits(5 * num_valid_cc_elements as usize)?;
+
+        reader.byte_align();
+        let comment_field_bytes = reader.read_bits(8)? as usize;
+        let comment = reader.read_bytes(comment_field_bytes)?.to_vec();
+
+        Ok(
+            Self {
+                element_instance_tag,
+                object_type,
+                sampling_frequency_index,
+                front_channels,
+                side_channels,
+                back_channels,
+                lfe_channels: num_lfe_channel_elements as u8,
+                comment,
+            }
+        )
+    }
+
+    /// Counts channels: a channel pair element carries two, a single channel element one.
+    fn read_channel_elements(reader: &mut BitReader, count: u32) -> Result<u8, Box<dyn std::error::Error>> {
+        let mut channels = 0;
+        for _ in 0..count {
+            let is_cpe = reader.read_bit()?;
+            // element_tag_select
+            reader.skip_bits(4)?;
+            channels += if is_cpe { 2 } else { 1 };
+        }
+        Ok(channels)
+    }
+
+    #[inline]
+    pub fn channels(&self) -> u8 {
+        self.front_channels + self.side_channels + self.back_channels + self.lfe_channels
+    }
+}
+
+/// AudioSpecificConfig as carried by the AAC sequence header (ISO/IEC 14496-3, 1.6.2.1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSpecificConfig {
+    /// Object type of the core coder, e.g. 2 for AAC LC even when SBR is signalled.
+    pub audio_object_type: u8,
+    pub sampling_frequency_index: u8,
+    pub sampling_frequency: u32,
+    pub channel_configuration: u8,
+
+    /// 5 when SBR is present, either explicitly (hierarchical) or through the sync extension.
+    pub extension_audio_object_type: Option<u8>,
+    pub extension_sampling_frequency: Option<u32>,
+    pub sbr_present: bool,
+    pub ps_present: bool,
+
+    /// GASpecificConfig frameLengthFlag: 960 instead of 1024 samples per frame.
+    pub frame_length_flag: bool,
+    pub program_config: Option<ProgramConfigElement>,
+}
+
+impl AudioSpecificConfig {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BitReader::new(data);
+
+        let mut audio_object_type = Self::read_audio_object_type(&mut reader)?;
+        let (sampling_frequency_index, sampling_frequency) = Self::read_sampling_frequency(&mut reader)?;
+        let channel_configuration = reader.read_bits(4)? as u8;
+
+        let mut config = Self {
+            audio_object_type,
+            sampling_frequency_index,
+            sampling_frequency,
+            channel_configuration,
+            extension_audio_object_type: None,
+            extension_sampling_frequency: None,
+            sbr_present: false,
+            ps_present: false,
+            frame_length_flag: false,
+            program_config: None,
+        };
+
+        // explicit hierarchical signalling: the core object type follows the extension.
+        if audio_object_type == AOT_SBR || audio_object_type == AOT_PS {
+            config.extension_audio_object_type = Some(AOT_SBR);
+            config.sbr_present = true;
+            config.ps_present = audio_object_type == AOT_PS;
+            config.extension_sampling_frequency = Some(Self::read_sampling_frequency(&mut reader)?.1);
+            audio_object_type = Self::read_audio_object_type(&mut reader)?;
+            if audio_object_type == AOT_ER_BSAC {
+                // extensionChannelConfiguration
+                reader.skip_bits(4)?;
+            }
+            config.audio_object_type = audio_object_type;
+        }
+
+        if GA_AUDIO_OBJECT_TYPES.contains(&audio_object_type) {
+            config.parse_ga_specific_config(&mut reader)?;
+        } else {
+            // other specific configs are not needed to set up the track; nothing after them can be trusted.
+            return Ok(config);
+        }
+
+        // backward compatible signalling, appended after the core config.
+        if config.extension_audio_object_type != Some(AOT_SBR) && reader.remaining() >= 16 && reader.read_bits(11)? == SYNC_EXTENSION_SBR {
+            let extension_audio_object_type = Self::read_audio_object_type(&mut reader)?;
+            if extension_audio_object_type == AOT_SBR {
+                config.extension_audio_object_type = Some(AOT_SBR);
+                config.sbr_present = reader.read_bit()?;
+                if config.sbr_present {
+                    config.extension_sampling_frequency = Some(Self::read_sampling_frequency(&mut reader)?.1);
+                    if reader.remaining() >= 12 && reader.read_bits(11)? == SYNC_EXTENSION_PS {
+                        config.ps_present = reader.read_bit()?;
+                    }
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn read_audio_object_type(reader: &mut BitReader) -> Result<u8, Box<dyn std::error::Error>> {
+        let audio_object_type = reader.read_bits(5)? as u8;
+        if audio_object_type == ESCAPE_AUDIO_OBJECT_TYPE {
+            Ok(32 + reader.read_bits(6)? as u8)
+        } else {
+            Ok(audio_object_type)
+        }
+    }
+
+    fn read_sampling_frequency(reader: &mut BitReader) -> Result<(u8, u32), Box<dyn std::error::Error>> {
+        let index = reader.read_bits(4)? as u8;
+        if index == EXPLICIT_SAMPLING_FREQUENCY_INDEX {
+            return Ok((index, reader.read_bits(24)?));
+        }
+        match AAC_SAMPLE_RATES.get(index as usize) {
+            Some(rate) => Ok((index, *rate)),
+            None => Err(format!("Reserved AAC sampling frequency index {}.", index).into())
+        }
+    }
+
+    fn parse_ga_specific_config(&mut self, reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        self.frame_length_flag = reader.read_bit()?;
+        // dependsOnCoreCoder -> coreCoderDelay
+        if reader.read_bit()? {
+            reader.skip_bits(14)?;
+        }
+        let extension_flag = reader.read_bit()?;
+        if self.channel_configuration == 0 {
+            self.program_config = Some(ProgramConfigElement::parse(reader)?);
+        }
+        if self.audio_object_type == 6 || self.audio_object_type == 20 {
+            // layerNr
+            reader.skip_bits(3)?;
+        }
+        if extension_flag {
+            if self.audio_object_type == AOT_ER_BSAC {
+                // numOfSubFrame, layer_length
+                reader.skip_bits(16)?;
+            }
+            if [17, 19, 20, 23].contains(&self.audio_object_type) {
+                // aacSectionDataResilienceFlag, aacScalefactorDataResilienceFlag, aacSpectralDataResilienceFlag
+                reader.skip_bits(3)?;
+            }
+            // extensionFlag3
+            reader.skip_bits(1)?;
+        }
+        Ok(())
+    }
+
+    /// Rate of the decoded output; with SBR this is the extension rate.
+    pub fn output_sampling_frequency(&self) -> u32 {
+        match self.extension_sampling_frequency {
+            Some(rate) if self.sbr_present => rate,
+            _ => self.sampling_frequency
+        }
+    }
+
+    /// Number of decoded output channels; parametric stereo turns mono into stereo.
+    pub fn output_channels(&self) -> u8 {
+        let channels = match self.channel_configuration {
+            0 => self.program_config.as_ref().map_or(0, |pce| pce.channels()),
+            1..=6 => self.channel_configuration,
+            7 | 12 | 14 => 8,
+            11 => 7,
+            _ => 0,
+        };
+        if self.ps_present && channels == 1 {
+            2
+        } else {
+            channels
+        }
+    }
+
+    /// Output samples per access unit, doubled by SBR.
+    pub fn samples_per_frame(&self) -> u32 {
+        let core = if self.frame_length_flag { 960 } else { 1024 };
+        if self.sbr_present {
+            core * 2
+        } else {
+            core
+        }
+    }
+
+    /// Object type to advertise in the `mp4a.40.x` codec string: 29 for HE-AAC v2, 5 for HE-AAC v1.
+    pub fn codec_object_type(&self) -> u8 {
+        if self.ps_present {
+            AOT_PS
+        } else if self.sbr_present {
+            AOT_SBR
+        } else {
+            self.audio_object_type
+        }
+    }
+}
+
+const ADTS_HEADER_SIZE: usize = 7;
+const ADTS_CRC_SIZE: usize = 2;
+const ADTS_MAX_FRAME_LENGTH: usize = 0x1FFF;
+
+/// An ADTS frame header (ISO/IEC 13818-7, 6.2). Only the fields needed to strip or rebuild it are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdtsHeader {
+    /// MPEG-2 rather than MPEG-4; the payload is the same.
+    pub mpeg2: bool,
+    pub protection_absent: bool,
+    /// Audio object type, i.e. the ADTS profile plus one.
+    pub audio_object_type: u8,
+    pub sampling_frequency_index: u8,
+    pub channel_configuration: u8,
+    /// Length of the whole frame, header included.
+    pub frame_length: usize,
+    pub buffer_fullness: u16,
+    pub raw_data_blocks: u8,
+}
+
+impl AdtsHeader {
+    /// Whether the data starts with an ADTS sync word and layer 0.
+    pub fn is_adts(data: &[u8]) -> bool {
+        data.len() >= ADTS_HEADER_SIZE && data[0] == 0xFF && data[1] & 0xF6 == 0xF0
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if !Self::is_adts(data) {
+            return Err("ADTS sync word mismatch.".into());
+        }
+        let mut reader = BitReader::new(data);
+        // syncword, id, layer are checked above.
+        reader.skip_bits(12)?;
+        let mpeg2 = reader.read_bit()?;
+        reader.skip_bits(2)?;
+        let protection_absent = reader.read_bit()?;
+        let audio_object_type = reader.read_bits(2)? as u8 + 1;
+        let sampling_frequency_index = reader.read_bits(4)? as u8;
+        // private_bit
+        reader.skip_bits(1)?;
+        let channel_configuration = reader.read_bits(3)? as u8;
+        // original_copy, home, copyright_identification_bit, copyright_identification_start
+        reader.skip_bits(4)?;
+        let frame_length = reader.read_bits(13)? as usize;
+        let buffer_fullness = reader.read_bits(11)? as u16;
+        let raw_data_blocks = reader.read_bits(2)? as u8;
+
+        if sampling_frequency_index as usize >= AAC_SAMPLE_RATES.len() {
+            return Err(format!("Reserved AAC sampling frequency index {}.", sampling_frequency_index).into());
+        }
+        let header = Self {
+            mpeg2,
+            protection_absent,
+            audio_object_type,
+            sampling_frequency_index,
+            channel_configuration,
+            frame_length,
+            buffer_fullness,
+            raw_data_blocks,
+        };
+        if frame_length < header.header_length() {
+            return Err("Invalid ADTS frame length.".into());
+        }
+        Ok(header)
+    }
+
+    /// 9 bytes when a CRC follows the fixed and variable headers.
+    pub fn header_length(&self) -> usize {
+        if self.protection_absent {
+            ADTS_HEADER_SIZE
+        } else {
+            ADTS_HEADER_SIZE + ADTS_CRC_SIZE
+        }
+    }
+
+    /// Header for a frame carrying `payload_length` bytes of raw AAC, without CRC.
+    /// ADTS can only signal the core coder; SBR and PS are left to implicit signalling.
+    pub fn from_config(config: &AudioSpecificConfig, payload_length: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        if !(1..=4).contains(&config.audio_object_type) {
+            return Err(format!("Audio object type {} can't be carried in ADTS.", config.audio_object_type).into());
+        }
+        if config.sampling_frequency_index as usize >= AAC_SAMPLE_RATES.len() {
+            return Err("ADTS requires a sampling frequency from the table.".into());
+        }
+        if config.channel_configuration > 7 {
+            return Err(format!("Channel configuration {} can't be carried in ADTS.", config.channel_configuration).into());
+        }
+        let frame_length = ADTS_HEADER_SIZE + payload_length;
+        if frame_length > ADTS_MAX_FRAME_LENGTH {
+            return Err("AAC frame too large for ADTS.".into());
+        }
+
+        Ok(Self {
+            mpeg2: false,
+            protection_absent: true,
+            audio_object_type: config.audio_object_type,
+            sampling_frequency_index: config.sampling_frequency_index,
+            channel_configuration: config.channel_configuration,
+            frame_length,
+            // variable bitrate.
+            buffer_fullness: 0x7FF,
+            raw_data_blocks: 0,
+        })
+    }
+
+    /// The 7-byte header; a CRC is never written.
+    pub fn serialize(&self) -> Vec<u8> {
+        let profile = self.audio_object_type - 1;
+        let frame_length = self.frame_length as u32;
+        vec![
+            0xFF,
+            0xF0 | ((self.mpeg2 as u8) << 3) | 0x01,
+            (profile << 6) | (self.sampling_frequency_index << 2) | (self.channel_configuration >> 2),
+            ((self.channel_configuration & 0x03) << 6) | (frame_length >> 11) as u8,
+            (frame_length >> 3) as u8,
+            ((frame_length & 0x07) << 5) as u8 | (self.buffer_fullness >> 6) as u8,
+            ((self.buffer_fullness & 0x3F) << 2) as u8 | self.raw_data_blocks,
+        ]
+    }
+
+    /// The 2-byte AudioSpecificConfig a sequence header would have carried.
+    pub fn to_audio_specific_config(&self) -> Vec<u8> {
+        vec![
+            (self.audio_object_type << 3) | (self.sampling_frequency_index >> 1),
+            ((self.sampling_frequency_index & 0x01) << 7) | (self.channel_configuration << 3),
+        ]
+    }
+}
+
+/// A raw AAC frame with the ADTS header it was found under.
+pub struct AdtsFrame {
+    pub header: AdtsHeader,
+    pub payload: Vec<u8>,
+}
+
+/// Splits data made of back to back ADTS frames into raw AAC frames.
+/// A truncated last frame is dropped; frames holding several raw data blocks are kept whole.
+pub fn strip_adts(data: &[u8]) -> Result<Vec<AdtsFrame>, Box<dyn std::error::Error>> {
+    let mut frames = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = AdtsHeader::parse(&data[offset..])?;
+        if offset + header.frame_length > data.len() {
+            break;
+        }
+        let payload = data[offset + header.header_length()..offset + header.frame_length].to_vec();
+        offset += header.frame_length;
+        frames.push(AdtsFrame { header, payload });
+    }
+    Ok(frames)
+}
+
+/// Wraps a raw AAC frame into ADTS, e.g. for `.aac` files or MPEG-TS.
+pub fn to_adts(config: &AudioSpecificConfig, payload: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut frame = AdtsHeader::from_config(config, payload.len())?.serialize();
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
@@ -0,0 +1,423 @@
+use crate::fmpeg::codec_string;
+use crate::fmpeg::avc::ColourDescription;
+use crate::io::bit::BitReader;
+
+/// OBU types relevant to remuxing (AV1 spec, 6.2.2).
+pub const OBU_SEQUENCE_HEADER: u8 = 1;
+pub const OBU_TEMPORAL_DELIMITER: u8 = 2;
+pub const OBU_FRAME_HEADER: u8 = 3;
+pub const OBU_TILE_GROUP: u8 = 4;
+pub const OBU_METADATA: u8 = 5;
+pub const OBU_FRAME: u8 = 6;
+pub const OBU_PADDING: u8 = 15;
+
+const KEY_FRAME: u32 = 0;
+
+/// One OBU of a low overhead bitstream.
+pub struct Obu<'a> {
+    pub obu_type: u8,
+    /// The whole OBU, header included.
+    pub data: &'a [u8],
+    /// The OBU payload, without header and size field.
+    pub payload: &'a [u8],
+}
+
+/// Iterates over the OBUs of a sample or of the `configOBUs` of an `av1C` record.
+/// Stops at the first malformed OBU.
+pub struct ObuIter<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ObuIter<'a> {
+    pub fn new(data: &'a [u8]) -> ObuIter<'a> {
+        Self {
+            data,
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for ObuIter<'a> {
+    type Item = Obu<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.data[self.offset..];
+        let header = *rest.first()?;
+        let obu_type = (header >> 3) & 0x0F;
+        let has_extension = header & 0x04 != 0;
+        let has_size_field = header & 0x02 != 0;
+        let mut header_size = if has_extension { 2 } else { 1 };
+        if header_size > rest.len() {
+            return None;
+        }
+
+        let payload_size = if has_size_field {
+            let (size, length) = read_leb128(&rest[header_size..])?;
+            header_size += length;
+            size as usize
+        } else {
+            // without a size field, the OBU spans the rest of the data.
+            rest.len() - header_size
+        };
+        let end = header_size.checked_add(payload_size)?;
+        if end > rest.len() {
+            return None;
+        }
+
+        self.offset += end;
+        Some(
+            Obu {
+                obu_type,
+                data: &rest[..end],
+                payload: &rest[header_size..end],
+            }
+        )
+    }
+}
+
+/// Returns the value and the number of bytes used, or `None` if truncated or over 8 bytes.
+pub fn read_leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+    None
+}
+
+/// Removes temporal delimiter and padding OBUs, which must not be stored in ISO-BMFF samples (AV1-ISOBMFF, 2.4).
+pub fn strip_temporal_delimiters(sample: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(sample.len());
+    for obu in ObuIter::new(sample) {
+        if obu.obu_type != OBU_TEMPORAL_DELIMITER && obu.obu_type != OBU_PADDING {
+            result.extend_from_slice(obu.data);
+        }
+    }
+    result
+}
+
+/// Whether the sample starts a shown key frame, i.e. a sync sample.
+pub fn is_keyframe(sample: &[u8], reduced_still_picture_header: bool) -> bool {
+    let frame_header = ObuIter::new(sample)
+        .find(|obu| obu.obu_type == OBU_FRAME_HEADER || obu.obu_type == OBU_FRAME);
+    let frame_header = match frame_header {
+        Some(obu) => obu.payload,
+        None => return false,
+    };
+    if reduced_still_picture_header {
+        return true;
+    }
+
+    is_shown_key_frame(frame_header).unwrap_or(false)
+}
+
+fn is_shown_key_frame(frame_header: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut reader = BitReader::new(frame_header);
+    // show_existing_frame: a frame shown again is never a sync sample on its own.
+    if reader.read_bit()? {
+        return Ok(false);
+    }
+    let frame_type = reader.read_bits(2)?;
+    let show_frame = reader.read_bit()?;
+    Ok(frame_type == KEY_FRAME && show_frame)
+}
+
+/// The payload of an AV1 sequence start tag, i.e. the content of the `av1C` box (AV1-ISOBMFF, 2.3.3).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1CodecConfigurationRecord {
+    pub version: u8,
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1CodecConfigurationRecord {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() < 4 {
+            return Err("AV1 codec configuration record truncated.".into());
+        }
+        if data[0] & 0x80 == 0 {
+            return Err("AV1 codec configuration record marker bit not set.".into());
+        }
+        let version = data[0] & 0x7F;
+        if version != 1 {
+            return Err(format!("Unsupported AV1 codec configuration record version {}.", version).into());
+        }
+
+        Ok(Self {
+            version,
+            seq_profile: data[1] >> 5,
+            seq_level_idx_0: data[1] & 0x1F,
+            seq_tier_0: data[2] & 0x80 != 0,
+            high_bitdepth: data[2] & 0x40 != 0,
+            twelve_bit: data[2] & 0x20 != 0,
+            monochrome: data[2] & 0x10 != 0,
+            chroma_subsampling_x: data[2] & 0x08 != 0,
+            chroma_subsampling_y: data[2] & 0x04 != 0,
+            chroma_sample_position: data[2] & 0x03,
+            initial_presentation_delay_minus_one: if data[3] & 0x10 != 0 { Some(data[3] & 0x0F) } else { None },
+            config_obus: data[4..].to_vec(),
+        })
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (true, true) => 12,
+            (true, false) => 10,
+            _ => 8,
+        }
+    }
+
+    /// The sequence header carried in `configOBUs`, if any.
+    pub fn sequence_header(&self) -> Option<Result<Av1SequenceHeader, Box<dyn std::error::Error>>> {
+        ObuIter::new(&self.config_obus)
+            .find(|obu| obu.obu_type == OBU_SEQUENCE_HEADER)
+            .map(|obu| Av1SequenceHeader::parse(obu.payload))
+    }
+
+    /// RFC 6381 style codec string `av01.P.LLT.DD`.
+    pub fn codec_string(&self) -> String {
+        codec_string::av1(self)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![
+            0x80 | self.version,
+            (self.seq_profile << 5) | self.seq_level_idx_0,
+            ((self.seq_tier_0 as u8) << 7)
+                | ((self.high_bitdepth as u8) << 6)
+                | ((self.twelve_bit as u8) << 5)
+                | ((self.monochrome as u8) << 4)
+                | ((self.chroma_subsampling_x as u8) << 3)
+                | ((self.chroma_subsampling_y as u8) << 2)
+                | self.chroma_sample_position,
+            match self.initial_presentation_delay_minus_one {
+                Some(delay) => 0x10 | delay,
+                None => 0,
+            },
+        ];
+        data.extend_from_slice(&self.config_obus);
+        data
+    }
+}
+
+/// The parts of an AV1 sequence header OBU needed to describe the video track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1SequenceHeader {
+    pub seq_profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub colour_description: Option<ColourDescription>,
+
+    pub num_units_in_display_tick: u32,
+    pub time_scale: u32,
+    pub num_ticks_per_picture: u32,
+}
+
+impl Av1SequenceHeader {
+    /// Parses the payload of a sequence header OBU, i.e. without OBU header and size.
+    pub fn parse(payload: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BitReader::new(payload);
+        let seq_profile = reader.read_bits(3)? as u8;
+        if seq_profile > 2 {
+            return Err(format!("Invalid AV1 seq_profile {}.", seq_profile).into());
+        }
+        let still_picture = reader.read_bit()?;
+        let reduced_still_picture_header = reader.read_bit()?;
+
+        let mut header = Self {
+            seq_profile,
+            still_picture,
+            reduced_still_picture_header,
+            seq_level_idx_0: 0,
+            seq_tier_0: false,
+            max_frame_width: 0,
+            max_frame_height: 0,
+            bit_depth: 8,
+            monochrome: false,
+            chroma_subsampling_x: true,
+            chroma_subsampling_y: true,
+            colour_description: None,
+            num_units_in_display_tick: 0,
+            time_scale: 0,
+            num_ticks_per_picture: 1,
+        };
+
+        if reduced_still_picture_header {
+            header.seq_level_idx_0 = reader.read_bits(5)? as u8;
+        } else {
+            let mut buffer_delay_length = 0;
+            // timing_info_present_flag
+            let decoder_model_info_present = if reader.read_bit()? {
+                header.num_units_in_display_tick = reader.read_bits(32)?;
+                header.time_scale = reader.read_bits(32)?;
+                // equal_picture_interval
+                if reader.read_bit()? {
+                    header.num_ticks_per_picture = Self::read_uvlc(&mut reader)?.saturating_add(1);
+                }
+                // decoder_model_info_present_flag
+                let present = reader.read_bit()?;
+                if present {
+                    buffer_delay_length = reader.read_bits(5)? + 1;
+                    // num_units_in_decoding_tick, buffer_removal_time_length_minus_1, frame_presentation_time_length_minus_1
+                    reader.skip_bits(32 + 5 + 5)?;
+                }
+                present
+            } else {
+                false
+            };
+            let initial_display_delay_present = reader.read_bit()?;
+            let operating_points = reader.read_bits(5)? + 1;
+            for index in 0..operating_points {
+                // operating_point_idc
+                reader.skip_bits(12)?;
+                let seq_level_idx = reader.read_bits(5)? as u8;
+                let seq_tier = seq_level_idx > 7 && reader.read_bit()?;
+                if index == 0 {
+                    header.seq_level_idx_0 = seq_level_idx;
+                    header.seq_tier_0 = seq_tier;
+                }
+                // decoder_model_present_for_this_op
+                if decoder_model_info_present && reader.read_bit()? {
+                    // decoder_buffer_delay, encoder_buffer_delay, low_delay_mode_flag
+                    reader.skip_bits(2 * buffer_delay_length as usize + 1)?;
+                }
+                // initial_display_delay_present_for_this_op
+                if initial_display_delay_present && reader.read_bit()? {
+                    reader.skip_bits(4)?;
+                }
+            }
+        }
+
+        let frame_width_bits = reader.read_bits(4)? + 1;
+        let frame_height_bits = reader.read_bits(4)? + 1;
+        header.max_frame_width = reader.read_bits(frame_width_bits)? + 1;
+        header.max_frame_height = reader.read_bits(frame_height_bits)? + 1;
+
+        // frame_id_numbers_present_flag
+        if !reduced_still_picture_header && reader.read_bit()? {
+            // delta_frame_id_length_minus_2, additional_frame_id_length_minus_1
+            reader.skip_bits(7)?;
+        }
+        // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+        reader.skip_bits(3)?;
+        if !reduced_still_picture_header {
+            // enable_interintra_compound, enable_masked_compound, enable_warped_motion, enable_dual_filter
+            reader.skip_bits(4)?;
+            let enable_order_hint = reader.read_bit()?;
+            if enable_order_hint {
+                // enable_jnt_comp, enable_ref_frame_mvs
+                reader.skip_bits(2)?;
+            }
+            // seq_choose_screen_content_tools
+            let seq_force_screen_content_tools = if reader.read_bit()? { 2 } else { reader.read_bits(1)? };
+            // seq_choose_integer_mv
+            if seq_force_screen_content_tools > 0 && !reader.read_bit()? {
+                // seq_force_integer_mv
+                reader.skip_bits(1)?;
+            }
+            if enable_order_hint {
+                // order_hint_bits_minus_1
+                reader.skip_bits(3)?;
+            }
+        }
+        // enable_superres, enable_cdef, enable_restoration
+        reader.skip_bits(3)?;
+
+        header.parse_color_config(&mut reader)?;
+        Ok(header)
+    }
+
+    fn parse_color_config(&mut self, reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        let high_bitdepth = reader.read_bit()?;
+        self.bit_depth = if self.seq_profile == 2 && high_bitdepth {
+            if reader.read_bit()? { 12 } else { 10 }
+        } else if high_bitdepth {
+            10
+        } else {
+            8
+        };
+        self.monochrome = self.seq_profile != 1 && reader.read_bit()?;
+
+        // color_description_present_flag
+        let (colour_primaries, transfer_characteristics, matrix_coefficients) = if reader.read_bit()? {
+            (reader.read_bits(8)? as u8, reader.read_bits(8)? as u8, reader.read_bits(8)? as u8)
+        } else {
+            // unspecified
+            (2, 2, 2)
+        };
+
+        let full_range;
+        if self.monochrome {
+            full_range = reader.read_bit()?;
+        } else if colour_primaries == 1 && transfer_characteristics == 13 && matrix_coefficients == 0 {
+            // sRGB
+            full_range = true;
+            (self.chroma_subsampling_x, self.chroma_subsampling_y) = (false, false);
+        } else {
+            full_range = reader.read_bit()?;
+            (self.chroma_subsampling_x, self.chroma_subsampling_y) = match self.seq_profile {
+                0 => (true, true),
+                1 => (false, false),
+                _ if self.bit_depth == 12 => {
+                    let x = reader.read_bit()?;
+                    (x, x && reader.read_bit()?)
+                }
+                _ => (true, false),
+            };
+        }
+
+        if colour_primaries != 2 || transfer_characteristics != 2 || matrix_coefficients != 2 {
+            self.colour_description = Some(
+                ColourDescription {
+                    colour_primaries,
+                    transfer_characteristics,
+                    matrix_coefficients,
+                    full_range,
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Variable length unsigned code, uvlc() (AV1 spec, 4.10.3).
+    fn read_uvlc(reader: &mut BitReader) -> Result<u32, Box<dyn std::error::Error>> {
+        let mut leading_zeros = 0;
+        while !reader.read_bit()? {
+            leading_zeros += 1;
+        }
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+        Ok(reader.read_bits(leading_zeros)? + ((1u64 << leading_zeros) - 1) as u32)
+    }
+
+    /// Frames per second from the timing info, if present.
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_display_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+        Some(self.time_scale as f64 / (self.num_units_in_display_tick as f64 * self.num_ticks_per_picture as f64))
+    }
+}
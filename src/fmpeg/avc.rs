@@ -0,0 +1,744 @@
+use crate::io::bit::{remove_emulation_prevention, BitReader};
+
+/// Profiles whose configuration record may carry the chroma format and bit depth extension.
+const HIGH_PROFILES: [u8; 4] = [100, 110, 122, 144];
+
+/// Extension appended to the record for High profiles (ISO/IEC 14496-15, 5.3.3.1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvcHighProfileExtension {
+    pub chroma_format: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub sequence_parameter_set_ext: Vec<Vec<u8>>,
+}
+
+/// The payload of an AVC sequence header tag, i.e. the content of the `avcC` box.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvcDecoderConfigurationRecord {
+    pub configuration_version: u8,
+    pub avc_profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub avc_level_indication: u8,
+    pub length_size_minus_one: u8,
+
+    pub sequence_parameter_sets: Vec<Vec<u8>>,
+    pub picture_parameter_sets: Vec<Vec<u8>>,
+
+    pub high_profile_extension: Option<AvcHighProfileExtension>,
+}
+
+struct RecordReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn read_u8(&mut self, field: &str) -> Result<u8, Box<dyn std::error::Error>> {
+        Ok(self.read_bytes(1, field)?[0])
+    }
+
+    fn read_u16(&mut self, field: &str) -> Result<u16, Box<dyn std::error::Error>> {
+        let bytes = self.read_bytes(2, field)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_bytes(&mut self, size: usize, field: &str) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        if self.remaining() < size {
+            return Err(format!("AVC decoder configuration record truncated at {}.", field).into());
+        }
+        let bytes = &self.data[self.position..self.position + size];
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn read_parameter_sets(&mut self, count: usize, field: &str) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut sets = Vec::with_capacity(count);
+        for _ in 0..count {
+            let size = self.read_u16(field)? as usize;
+            if size == 0 {
+                return Err(format!("AVC decoder configuration record has an empty {}.", field).into());
+            }
+            sets.push(self.read_bytes(size, field)?.to_vec());
+        }
+        Ok(sets)
+    }
+
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+}
+
+impl AvcDecoderConfigurationRecord {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = RecordReader {
+            data,
+            position: 0,
+        };
+
+        let configuration_version = reader.read_u8("configurationVersion")?;
+        if configuration_version != 1 {
+            return Err(format!("Unsupported AVC configurationVersion {}.", configuration_version).into());
+        }
+        let avc_profile_indication = reader.read_u8("AVCProfileIndication")?;
+        let profile_compatibility = reader.read_u8("profile_compatibility")?;
+        let avc_level_indication = reader.read_u8("AVCLevelIndication")?;
+
+        let length_size_minus_one = reader.read_u8("lengthSizeMinusOne")? & 0x03;
+        if length_size_minus_one == 2 {
+            return Err("Invalid AVC NAL unit length size 3.".into());
+        }
+
+        let sps_count = (reader.read_u8("numOfSequenceParameterSets")? & 0x1F) as usize;
+        if sps_count == 0 {
+            return Err("AVC decoder configuration record has no SPS.".into());
+        }
+        let sequence_parameter_sets = reader.read_parameter_sets(sps_count, "SPS")?;
+
+        let pps_count = reader.read_u8("numOfPictureParameterSets")? as usize;
+        if pps_count == 0 {
+            return Err("AVC decoder configuration record has no PPS.".into());
+        }
+        let picture_parameter_sets = reader.read_parameter_sets(pps_count, "PPS")?;
+
+        // plenty of encoders leave the extension out even for high profiles.
+        let high_profile_extension = if HIGH_PROFILES.contains(&avc_profile_indication) && reader.remaining() >= 4 {
+            let chroma_format = reader.read_u8("chroma_format")? & 0x03;
+            let bit_depth_luma_minus8 = reader.read_u8("bit_depth_luma_minus8")? & 0x07;
+            let bit_depth_chroma_minus8 = reader.read_u8("bit_depth_chroma_minus8")? & 0x07;
+            let sps_ext_count = reader.read_u8("numOfSequenceParameterSetExt")? as usize;
+            Some(
+                AvcHighProfileExtension {
+                    chroma_format,
+                    bit_depth_luma_minus8,
+                    bit_depth_chroma_minus8,
+                    sequence_parameter_set_ext: reader.read_parameter_sets(sps_ext_count, "SPSExt")?,
+                }
+            )
+        } else {
+            None
+        };
+
+        Ok(
+            Self {
+                configuration_version,
+                avc_profile_indication,
+                profile_compatibility,
+                avc_level_indication,
+                length_size_minus_one,
+                sequence_parameter_sets,
+                picture_parameter_sets,
+                high_profile_extension,
+            }
+        )
+    }
+
+    /// Size in bytes of the length prefix of every NAL unit in the samples.
+    #[inline]
+    pub fn nal_length_size(&self) -> u8 {
+        self.length_size_minus_one + 1
+    }
+
+    /// Writes the record back, with reserved bits set to 1 as required.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.configuration_version,
+            self.avc_profile_indication,
+            self.profile_compatibility,
+            self.avc_level_indication,
+            0xFC | self.length_size_minus_one,
+            0xE0 | self.sequence_parameter_sets.len() as u8,
+        ];
+        Self::write_parameter_sets(&mut data, &self.sequence_parameter_sets);
+        data.push(self.picture_parameter_sets.len() as u8);
+        Self::write_parameter_sets(&mut data, &self.picture_parameter_sets);
+
+        if let Some(ref extension) = self.high_profile_extension {
+            data.push(0xFC | extension.chroma_format);
+            data.push(0xF8 | extension.bit_depth_luma_minus8);
+            data.push(0xF8 | extension.bit_depth_chroma_minus8);
+            data.push(extension.sequence_parameter_set_ext.len() as u8);
+            Self::write_parameter_sets(&mut data, &extension.sequence_parameter_set_ext);
+        }
+        data
+    }
+
+    fn write_parameter_sets(data: &mut Vec<u8>, sets: &[Vec<u8>]) {
+        for set in sets {
+            data.extend_from_slice(&(set.len() as u16).to_be_bytes());
+            data.extend_from_slice(set);
+        }
+    }
+}
+
+/// Profiles whose SPS carries chroma_format_idc, bit depths and scaling matrices (ITU-T H.264, 7.3.2.1.1).
+const CHROMA_PROFILES: [u8; 13] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135];
+
+/// Sample aspect ratios for aspect_ratio_idc 1..=16 (ITU-T H.264, Table E-1).
+pub(crate) const SAR_TABLE: [(u32, u32); 16] = [
+    (1, 1), (12, 11), (10, 11), (16, 11),
+    (40, 33), (24, 11), (20, 11), (32, 11),
+    (80, 33), (18, 11), (15, 11), (64, 33),
+    (160, 99), (4, 3), (3, 2), (2, 1),
+];
+
+pub(crate) const EXTENDED_SAR: u8 = 255;
+
+/// Colour description from the VUI video signal type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColourDescription {
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub full_range: bool,
+}
+
+/// The parts of an H.264 sequence parameter set needed to describe the video track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceParameterSet {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u32,
+
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+
+    /// Size in macroblocks, before cropping.
+    pub coded_width: u32,
+    pub coded_height: u32,
+    /// Size after cropping, i.e. what is displayed (in samples, not accounting for the SAR).
+    pub width: u32,
+    pub height: u32,
+    pub interlaced: bool,
+
+    pub sar_width: u32,
+    pub sar_height: u32,
+
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate: bool,
+
+    pub colour_description: Option<ColourDescription>,
+}
+
+impl SequenceParameterSet {
+    /// Parses a SPS NAL unit, including its one byte NAL header.
+    pub fn parse(nal_unit: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let rbsp = remove_emulation_prevention(nal_unit);
+        let mut reader = BitReader::new(&rbsp);
+        let nal_unit_type = reader.read_bits(8)? & 0x1F;
+        if nal_unit_type != 7 {
+            return Err(format!("NAL unit type {} is not a SPS.", nal_unit_type).into());
+        }
+
+        let profile_idc = reader.read_bits(8)? as u8;
+        let constraint_flags = reader.read_bits(8)? as u8;
+        let level_idc = reader.read_bits(8)? as u8;
+        let seq_parameter_set_id = reader.read_ue()?;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+        if CHROMA_PROFILES.contains(&profile_idc) {
+            chroma_format_idc = reader.read_ue()?;
+            if chroma_format_idc > 3 {
+                return Err(format!("Invalid chroma_format_idc {}.", chroma_format_idc).into());
+            }
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_bit()?;
+            }
+            bit_depth_luma = 8 + reader.read_ue()? as u8;
+            bit_depth_chroma = 8 + reader.read_ue()? as u8;
+            // qpprime_y_zero_transform_bypass_flag
+            reader.skip_bits(1)?;
+            if reader.read_bit()? {
+                let count = if chroma_format_idc != 3 { 8 } else { 12 };
+                for i in 0..count {
+                    if reader.read_bit()? {
+                        Self::skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        // log2_max_frame_num_minus4
+        reader.read_ue()?;
+        let pic_order_cnt_type = reader.read_ue()?;
+        if pic_order_cnt_type == 0 {
+            // log2_max_pic_order_cnt_lsb_minus4
+            reader.read_ue()?;
+        } else if pic_order_cnt_type == 1 {
+            // delta_pic_order_always_zero_flag
+            reader.skip_bits(1)?;
+            // offset_for_non_ref_pic, offset_for_top_to_bottom_field
+            reader.read_se()?;
+            reader.read_se()?;
+            let num_ref_frames_in_pic_order_cnt_cycle = reader.read_ue()?;
+            for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
+                reader.read_se()?;
+            }
+        }
+
+        // max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+        reader.read_ue()?;
+        reader.skip_bits(1)?;
+
+        let pic_width_in_mbs = reader.read_ue()? + 1;
+        let pic_height_in_map_units = reader.read_ue()? + 1;
+        let frame_mbs_only = reader.read_bit()?;
+        if !frame_mbs_only {
+            // mb_adaptive_frame_field_flag
+            reader.skip_bits(1)?;
+        }
+        // direct_8x8_inference_flag
+        reader.skip_bits(1)?;
+
+        let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+        if reader.read_bit()? {
+            crop_left = reader.read_ue()?;
+            crop_right = reader.read_ue()?;
+            crop_top = reader.read_ue()?;
+            crop_bottom = reader.read_ue()?;
+        }
+
+        let frame_height_factor = if frame_mbs_only { 1 } else { 2 };
+        let (crop_unit_x, crop_unit_y) = if chroma_format_idc == 0 || separate_colour_plane {
+            (1, frame_height_factor)
+        } else {
+            let sub_width_c = if chroma_format_idc == 3 { 1 } else { 2 };
+            let sub_height_c = if chroma_format_idc == 1 { 2 } else { 1 };
+            (sub_width_c, sub_height_c * frame_height_factor)
+        };
+
+        let coded_width = pic_width_in_mbs * 16;
+        let coded_height = frame_height_factor * pic_height_in_map_units * 16;
+        let crop_x = crop_unit_x * (crop_left + crop_right);
+        let crop_y = crop_unit_y * (crop_top + crop_bottom);
+        if crop_x >= coded_width || crop_y >= coded_height {
+            return Err("SPS cropping exceeds the coded size.".into());
+        }
+
+        let mut sps = Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            seq_parameter_set_id,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+            coded_width,
+            coded_height,
+            width: coded_width - crop_x,
+            height: coded_height - crop_y,
+            interlaced: !frame_mbs_only,
+            sar_width: 1,
+            sar_height: 1,
+            num_units_in_tick: 0,
+            time_scale: 0,
+            fixed_frame_rate: false,
+            colour_description: None,
+        };
+
+        if reader.read_bit()? {
+            sps.parse_vui(&mut reader)?;
+        }
+        Ok(sps)
+    }
+
+    fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let mut last_scale = 8;
+        let mut next_scale = 8;
+        for _ in 0..size {
+            if next_scale != 0 {
+                let delta_scale = reader.read_se()?;
+                next_scale = (last_scale + delta_scale + 256) % 256;
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_vui(&mut self, reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        // aspect_ratio_info_present_flag
+        if reader.read_bit()? {
+            let aspect_ratio_idc = reader.read_bits(8)? as u8;
+            if aspect_ratio_idc == EXTENDED_SAR {
+                self.sar_width = reader.read_bits(16)?;
+                self.sar_height = reader.read_bits(16)?;
+            } else if (1..=16).contains(&aspect_ratio_idc) {
+                (self.sar_width, self.sar_height) = SAR_TABLE[aspect_ratio_idc as usize - 1];
+            }
+            // 0 is unspecified, the rest is reserved; stay at 1:1 for those.
+            if self.sar_width == 0 || self.sar_height == 0 {
+                (self.sar_width, self.sar_height) = (1, 1);
+            }
+        }
+
+        // overscan_info_present_flag
+        if reader.read_bit()? {
+            // overscan_appropriate_flag
+            reader.skip_bits(1)?;
+        }
+
+        // video_signal_type_present_flag
+        if reader.read_bit()? {
+            // video_format
+            reader.skip_bits(3)?;
+            let full_range = reader.read_bit()?;
+            if reader.read_bit()? {
+                self.colour_description = Some(
+                    ColourDescription {
+                        colour_primaries: reader.read_bits(8)? as u8,
+                        transfer_characteristics: reader.read_bits(8)? as u8,
+                        matrix_coefficients: reader.read_bits(8)? as u8,
+                        full_range,
+                    }
+                );
+            }
+        }
+
+        // chroma_loc_info_present_flag
+        if reader.read_bit()? {
+            reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        // timing_info_present_flag
+        if reader.read_bit()? {
+            self.num_units_in_tick = reader.read_bits(32)?;
+            self.time_scale = reader.read_bits(32)?;
+            self.fixed_frame_rate = reader.read_bit()?;
+        }
+        Ok(())
+    }
+
+    /// Frames per second from the VUI timing info, if present.
+    /// A frame spans two ticks of `num_units_in_tick`.
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+        Some(self.time_scale as f64 / (2.0 * self.num_units_in_tick as f64))
+    }
+
+    /// Width the picture should be displayed at once the SAR is applied.
+    pub fn display_width(&self) -> f64 {
+        self.width as f64 * self.sar_width as f64 / self.sar_height as f64
+    }
+}
+
+/// Length prefix written into MP4 samples; the `avcC` box is rewritten to match.
+pub const AVCC_LENGTH_SIZE: u8 = 4;
+
+/// NAL units of a sample, and how many malformed ones were dropped.
+pub type AvccUnits<'a> = (Vec<&'a [u8]>, usize);
+
+/// Splits a length-prefixed (AVCC) payload into NAL units, along with the number of units dropped.
+/// Like `NaluIter`, empty units are skipped and a truncated one ends the payload; the caller may warn.
+/// Fails only on an invalid length size.
+pub fn split_avcc(payload: &[u8], length_size: u8) -> Result<AvccUnits<'_>, Box<dyn std::error::Error>> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(format!("Invalid NAL unit length size {}.", length_size).into());
+    }
+    let length_size = length_size as usize;
+    let mut units = vec![];
+    let mut dropped = 0;
+    let mut position = 0;
+    while position < payload.len() {
+        if payload.len() - position < length_size {
+            dropped += 1;
+            break;
+        }
+        let length = payload[position..position + length_size]
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        position += length_size;
+        if length == 0 {
+            dropped += 1;
+            continue;
+        }
+        if payload.len() - position < length {
+            dropped += 1;
+            break;
+        }
+        units.push(&payload[position..position + length]);
+        position += length;
+    }
+    Ok((units, dropped))
+}
+
+/// Splits an Annex-B byte stream at its 3 or 4 byte start codes.
+/// Trailing zero bytes before a start code are dropped.
+pub fn split_annex_b(payload: &[u8]) -> Vec<&[u8]> {
+    let mut units = vec![];
+    let mut start = None;
+    let mut i = 0;
+    while i + 2 < payload.len() {
+        if payload[i] == 0 && payload[i + 1] == 0 && payload[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&payload[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(trim_trailing_zeros(&payload[start..]));
+    }
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+#[inline]
+fn trim_trailing_zeros(unit: &[u8]) -> &[u8] {
+    let end = unit.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    &unit[..end]
+}
+
+/// Whether the payload uses start codes rather than length prefixes.
+/// A length-prefixed payload may also begin with 0x000001 (e.g. a 4-byte length of 256..511),
+/// so it only counts as Annex-B if it cannot be read cleanly with the given length size.
+pub fn is_annex_b(payload: &[u8], length_size: u8) -> bool {
+    let has_start_code = payload.starts_with(&[0, 0, 1]) || payload.starts_with(&[0, 0, 0, 1]);
+    has_start_code && !matches!(split_avcc(payload, length_size), Ok((_, 0)))
+}
+
+fn write_avcc(units: &[&[u8]]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(units.iter().map(|unit| unit.len() + 4).sum());
+    for unit in units {
+        data.extend_from_slice(&(unit.len() as u32).to_be_bytes());
+        data.extend_from_slice(unit);
+    }
+    data
+}
+
+/// Converts a sample to 4-byte length-prefixed AVCC as MP4 needs it, along with the number of
+/// empty or truncated units dropped on the way.
+/// Annex-B payloads are detected and converted; AVCC ones are re-prefixed from `length_size`.
+pub fn to_avcc(payload: &[u8], length_size: u8) -> Result<(Vec<u8>, usize), Box<dyn std::error::Error>> {
+    if is_annex_b(payload, length_size) {
+        return Ok((write_avcc(&split_annex_b(payload)), 0));
+    }
+    let (units, dropped) = split_avcc(payload, length_size)?;
+    if length_size == AVCC_LENGTH_SIZE && dropped == 0 {
+        return Ok((payload.to_vec(), 0));
+    }
+    Ok((write_avcc(&units), dropped))
+}
+
+/// Converts a length-prefixed sample to an Annex-B byte stream with 4-byte start codes.
+/// Empty or truncated units are dropped.
+pub fn to_annex_b(payload: &[u8], length_size: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (units, _) = split_avcc(payload, length_size)?;
+    let mut data = Vec::with_capacity(payload.len() + units.len() * 4);
+    for unit in units {
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(unit);
+    }
+    Ok(data)
+}
+
+/// NAL unit types relevant to remuxing (ITU-T H.264, Table 7-1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NaluType {
+    NonIdrSlice,
+    SliceDataPartition,
+    IdrSlice,
+    Sei,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfStream,
+    Filler,
+    SpsExtension,
+    Other(u8),
+}
+
+impl From<u8> for NaluType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => NaluType::NonIdrSlice,
+            2..=4 => NaluType::SliceDataPartition,
+            5 => NaluType::IdrSlice,
+            6 => NaluType::Sei,
+            7 => NaluType::Sps,
+            8 => NaluType::Pps,
+            9 => NaluType::AccessUnitDelimiter,
+            10 => NaluType::EndOfSequence,
+            11 => NaluType::EndOfStream,
+            12 => NaluType::Filler,
+            13 => NaluType::SpsExtension,
+            _ => NaluType::Other(value),
+        }
+    }
+}
+
+/// One NAL unit of a sample, including its header byte.
+#[derive(Debug, Clone, Copy)]
+pub struct Nalu<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Nalu<'a> {
+    #[inline]
+    pub fn nalu_type(&self) -> NaluType {
+        NaluType::from(self.data[0] & 0x1F)
+    }
+
+    #[inline]
+    pub fn nal_ref_idc(&self) -> u8 {
+        (self.data[0] >> 5) & 0x03
+    }
+
+    #[inline]
+    pub fn is_idr(&self) -> bool {
+        self.nalu_type() == NaluType::IdrSlice
+    }
+
+    #[inline]
+    pub fn is_sei(&self) -> bool {
+        self.nalu_type() == NaluType::Sei
+    }
+
+    #[inline]
+    pub fn is_aud(&self) -> bool {
+        self.nalu_type() == NaluType::AccessUnitDelimiter
+    }
+
+    #[inline]
+    pub fn is_parameter_set(&self) -> bool {
+        matches!(self.nalu_type(), NaluType::Sps | NaluType::Pps | NaluType::SpsExtension)
+    }
+
+    #[inline]
+    pub fn is_filler(&self) -> bool {
+        self.nalu_type() == NaluType::Filler
+    }
+
+    #[inline]
+    pub fn is_end_of_sequence(&self) -> bool {
+        self.nalu_type() == NaluType::EndOfSequence
+    }
+}
+
+/// Iterates over the NAL units of a length-prefixed sample.
+/// Empty units are skipped and iteration stops at a truncated one; `split_avcc` counts them.
+pub struct NaluIter<'a> {
+    payload: &'a [u8],
+    position: usize,
+    length_size: usize,
+}
+
+impl<'a> NaluIter<'a> {
+    pub fn new(payload: &'a [u8], length_size: u8) -> NaluIter<'a> {
+        NaluIter {
+            payload,
+            position: 0,
+            length_size: length_size as usize,
+        }
+    }
+}
+
+impl<'a> Iterator for NaluIter<'a> {
+    type Item = Nalu<'a>;
+
+    fn next(&mut self) -> Option<Nalu<'a>> {
+        loop {
+            let remaining = self.payload.len() - self.position;
+            if self.length_size == 0 || remaining < self.length_size {
+                return None;
+            }
+            let length = self.payload[self.position..self.position + self.length_size]
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            if length == 0 {
+                self.position += self.length_size;
+                continue;
+            }
+            if remaining - self.length_size < length {
+                self.position = self.payload.len();
+                return None;
+            }
+            let start = self.position + self.length_size;
+            self.position = start + length;
+            return Some(Nalu { data: &self.payload[start..start + length] });
+        }
+    }
+}
+
+/// Whether a 4-byte length-prefixed sample contains an IDR slice, i.e. can be decoded on its own.
+pub fn contains_idr(payload: &[u8]) -> bool {
+    NaluIter::new(payload, AVCC_LENGTH_SIZE).any(|nalu| nalu.is_idr())
+}
+
+/// Drops NAL units that MP4 does not need from 4-byte length-prefixed samples.
+/// Nothing is stripped by default.
+#[derive(Debug, Clone, Default)]
+pub struct NaluFilter {
+    pub strip_aud: bool,
+    pub strip_filler: bool,
+    /// Only in-band SPS/PPS identical to the ones in the `avcC` are dropped; new ones are kept.
+    pub strip_redundant_parameter_sets: bool,
+}
+
+impl NaluFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn strip_aud(mut self, flag: bool) -> Self {
+        self.strip_aud = flag;
+        self
+    }
+
+    pub fn strip_filler(mut self, flag: bool) -> Self {
+        self.strip_filler = flag;
+        self
+    }
+
+    pub fn strip_redundant_parameter_sets(mut self, flag: bool) -> Self {
+        self.strip_redundant_parameter_sets = flag;
+        self
+    }
+
+    #[inline]
+    pub fn is_passthrough(&self) -> bool {
+        !(self.strip_aud || self.strip_filler || self.strip_redundant_parameter_sets)
+    }
+
+    fn keeps(&self, nalu: &Nalu, record: Option<&AvcDecoderConfigurationRecord>) -> bool {
+        match nalu.nalu_type() {
+            NaluType::AccessUnitDelimiter => !self.strip_aud,
+            NaluType::Filler => !self.strip_filler,
+            NaluType::Sps | NaluType::Pps if self.strip_redundant_parameter_sets => {
+                match record {
+                    Some(record) => !record.sequence_parameter_sets
+                        .iter()
+                        .chain(record.picture_parameter_sets.iter())
+                        .any(|set| set.as_slice() == nalu.data),
+                    None => true
+                }
+            }
+            _ => true
+        }
+    }
+
+    pub fn apply(&self, payload: Vec<u8>, record: Option<&AvcDecoderConfigurationRecord>) -> Vec<u8> {
+        if self.is_passthrough() {
+            return payload;
+        }
+        let units: Vec<&[u8]> = NaluIter::new(&payload, AVCC_LENGTH_SIZE)
+            .filter(|nalu| self.keeps(nalu, record))
+            .map(|nalu| nalu.data)
+            .collect();
+        write_avcc(&units)
+    }
+}
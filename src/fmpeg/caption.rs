@@ -0,0 +1,539 @@
+use crate::fmpeg::avc::NaluIter;
+use crate::io::bit::remove_emulation_prevention;
+use std::collections::BTreeMap;
+
+pub const SEI_USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+
+const T35_COUNTRY_CODE_USA: u8 = 0xB5;
+const T35_PROVIDER_CODE_ATSC: u16 = 0x0031;
+const ATSC_USER_IDENTIFIER: &[u8; 4] = b"GA94";
+const ATSC_USER_DATA_TYPE_CC: u8 = 0x03;
+
+pub const CC_TYPE_NTSC_FIELD_1: u8 = 0;
+pub const CC_TYPE_NTSC_FIELD_2: u8 = 1;
+pub const CC_TYPE_DTVCC_PACKET_DATA: u8 = 2;
+pub const CC_TYPE_DTVCC_PACKET_START: u8 = 3;
+
+/// One message of an SEI NAL unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeiMessage {
+    pub payload_type: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Splits an SEI NAL unit (header byte included) into its messages (ITU-T H.264, 7.3.2.3).
+pub fn parse_sei(nal_unit: &[u8]) -> Result<Vec<SeiMessage>, Box<dyn std::error::Error>> {
+    if nal_unit.is_empty() {
+        return Err("Empty SEI NAL unit.".into());
+    }
+    let rbsp = remove_emulation_prevention(&nal_unit[1..]);
+    let mut messages = vec![];
+    let mut position = 0;
+    // stop at rbsp_trailing_bits.
+    while position < rbsp.len() && !(position == rbsp.len() - 1 && rbsp[position] == 0x80) {
+        let payload_type = read_sei_value(&rbsp, &mut position)?;
+        let payload_size = read_sei_value(&rbsp, &mut position)? as usize;
+        if rbsp.len() - position < payload_size {
+            return Err("Truncated SEI message.".into());
+        }
+        messages.push(
+            SeiMessage {
+                payload_type,
+                payload: rbsp[position..position + payload_size].to_vec(),
+            }
+        );
+        position += payload_size;
+    }
+    Ok(messages)
+}
+
+/// payloadType and payloadSize are coded as a run of 0xFF bytes plus a final byte.
+fn read_sei_value(rbsp: &[u8], position: &mut usize) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut value = 0u32;
+    loop {
+        let Some(&byte) = rbsp.get(*position) else {
+            return Err("Truncated SEI message header.".into());
+        };
+        *position += 1;
+        value += byte as u32;
+        if byte != 0xFF {
+            return Ok(value);
+        }
+    }
+}
+
+/// One cc_data construct: a CEA-608 byte pair or a chunk of a CEA-708 DTVCC packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CcTriplet {
+    pub cc_valid: bool,
+    pub cc_type: u8,
+    pub data: [u8; 2],
+}
+
+/// Reads the ATSC A/53 cc_data out of a `user_data_registered_itu_t_t35` payload.
+/// Returns `None` for any other kind of registered user data.
+pub fn parse_cc_data(payload: &[u8]) -> Option<Vec<CcTriplet>> {
+    let mut position = 0;
+    if *payload.first()? != T35_COUNTRY_CODE_USA {
+        return None;
+    }
+    position += 1;
+    if u16::from_be_bytes([*payload.get(position)?, *payload.get(position + 1)?]) != T35_PROVIDER_CODE_ATSC {
+        return None;
+    }
+    position += 2;
+    if payload.get(position..position + 4)? != ATSC_USER_IDENTIFIER || *payload.get(position + 4)? != ATSC_USER_DATA_TYPE_CC {
+        return None;
+    }
+    position += 5;
+
+    let flags = *payload.get(position)?;
+    // process_cc_data_flag
+    if flags & 0x40 == 0 {
+        return Some(vec![]);
+    }
+    let cc_count = (flags & 0x1F) as usize;
+    // skip em_data
+    position += 2;
+
+    let constructs = payload.get(position..position + cc_count * 3)?;
+    Some(
+        constructs
+            .chunks_exact(3)
+            .map(|construct| CcTriplet {
+                cc_valid: construct[0] & 0x04 != 0,
+                cc_type: construct[0] & 0x03,
+                data: [construct[1], construct[2]],
+            })
+            .collect()
+    )
+}
+
+/// Collects the cc_data triplets of every SEI NAL unit in a length-prefixed sample.
+/// CEA-708 (DTVCC) triplets are returned as they are; only CEA-608 is decoded further.
+pub fn extract_cc_data(payload: &[u8], length_size: u8) -> Vec<CcTriplet> {
+    let mut triplets = vec![];
+    for nalu in NaluIter::new(payload, length_size).filter(|nalu| nalu.is_sei()) {
+        let Ok(messages) = parse_sei(nalu.data) else {
+            continue;
+        };
+        for message in messages.iter().filter(|message| message.payload_type == SEI_USER_DATA_REGISTERED_ITU_T_T35) {
+            if let Some(mut cc_data) = parse_cc_data(&message.payload) {
+                triplets.append(&mut cc_data);
+            }
+        }
+    }
+    triplets
+}
+
+/// A caption as it was on screen between two times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptionEvent {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
+const CC_ROWS: usize = 15;
+const CC_COLUMNS: usize = 32;
+
+/// Rows addressed by a preamble address code, indexed by the first byte (channel bit cleared).
+const PAC_ROWS: [[usize; 2]; 8] = [
+    [11, 11], [1, 2], [3, 4], [12, 13],
+    [14, 15], [5, 6], [7, 8], [9, 10],
+];
+
+const SPECIAL_CHARACTERS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪',
+    'à', ' ', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_CHARACTERS_SPANISH_FRENCH: [char; 32] = [
+    'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡',
+    '*', '\'', '—', '©', '℠', '•', '“', '”',
+    'À', 'Â', 'Ç', 'È', 'Ê', 'Ë', 'ë', 'Î',
+    'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+];
+
+const EXTENDED_CHARACTERS_PORTUGUESE_GERMAN: [char; 32] = [
+    'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ',
+    'õ', '{', '}', '\\', '^', '_', '|', '~',
+    'Ä', 'ä', 'Ö', 'ö', 'ß', '¥', '¤', '│',
+    'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+];
+
+/// The basic character set, which is ASCII apart from a few codes.
+fn basic_character(code: u8) -> char {
+    match code {
+        0x2A => 'á',
+        0x5C => 'é',
+        0x5E => 'í',
+        0x5F => 'ó',
+        0x60 => 'ú',
+        0x7B => 'ç',
+        0x7C => '÷',
+        0x7D => 'Ñ',
+        0x7E => 'ñ',
+        0x7F => '█',
+        _ => code as char,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CaptionMode {
+    PopOn,
+    RollUp(usize),
+    PaintOn,
+    Text,
+}
+
+/// One caption memory: 15 rows of 32 columns.
+#[derive(Debug, Clone)]
+struct CaptionMemory {
+    rows: Vec<Vec<char>>,
+}
+
+impl CaptionMemory {
+    fn new() -> Self {
+        Self { rows: vec![vec![]; CC_ROWS] }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| row.iter().all(|c| *c == ' '))
+    }
+
+    fn clear(&mut self) {
+        self.rows.iter_mut().for_each(|row| row.clear());
+    }
+
+    fn write(&mut self, row: usize, column: usize, character: char) {
+        let row = &mut self.rows[row];
+        if row.len() <= column {
+            row.resize(column + 1, ' ');
+        }
+        row[column] = character;
+    }
+
+    fn backspace(&mut self, row: usize, column: usize) {
+        self.rows[row].truncate(column);
+    }
+
+    fn text(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Decodes the CC1 service of CEA-608 byte pairs into caption events.
+/// Positioning and styling are dropped; only what is on screen and when is kept.
+pub struct Cea608Decoder {
+    mode: CaptionMode,
+    displayed: CaptionMemory,
+    non_displayed: CaptionMemory,
+    row: usize,
+    column: usize,
+
+    /// Whether the last data channel selected by a control code is channel 1.
+    channel_1: bool,
+    last_control: Option<[u8; 2]>,
+    displayed_since: Option<u64>,
+
+    events: Vec<CaptionEvent>,
+}
+
+impl Cea608Decoder {
+    pub fn new() -> Self {
+        Self {
+            mode: CaptionMode::PopOn,
+            displayed: CaptionMemory::new(),
+            non_displayed: CaptionMemory::new(),
+            row: CC_ROWS - 1,
+            column: 0,
+            channel_1: true,
+            last_control: None,
+            displayed_since: None,
+            events: vec![],
+        }
+    }
+
+    /// Feeds one field 1 byte pair shown at `time_ms`, parity bits included.
+    pub fn decode(&mut self, time_ms: u64, data: [u8; 2]) {
+        let (first, second) = (data[0] & 0x7F, data[1] & 0x7F);
+        if first == 0 && second == 0 {
+            return;
+        }
+
+        if (0x10..=0x1F).contains(&first) {
+            // control codes are sent twice; the repetition is dropped.
+            if self.last_control == Some([first, second]) {
+                self.last_control = None;
+                return;
+            }
+            self.last_control = Some([first, second]);
+            self.channel_1 = first & 0x08 == 0;
+            if self.channel_1 {
+                self.decode_control(time_ms, first & !0x08, second);
+            }
+            return;
+        }
+
+        self.last_control = None;
+        if !self.channel_1 || first < 0x20 {
+            return;
+        }
+        self.write(time_ms, basic_character(first));
+        if second >= 0x20 {
+            self.write(time_ms, basic_character(second));
+        }
+    }
+
+    fn decode_control(&mut self, time_ms: u64, first: u8, second: u8) {
+        match (first, second) {
+            (0x14, 0x20) => self.set_mode(time_ms, CaptionMode::PopOn),
+            // backspace
+            (0x14, 0x21) if self.column > 0 => {
+                self.column -= 1;
+                let (row, column) = (self.row, self.column);
+                self.memory().backspace(row, column);
+            }
+            // delete to end of row
+            (0x14, 0x24) => {
+                let (row, column) = (self.row, self.column);
+                self.memory().backspace(row, column);
+            }
+            (0x14, 0x25..=0x27) => self.set_mode(time_ms, CaptionMode::RollUp((second - 0x23) as usize)),
+            (0x14, 0x29) => self.set_mode(time_ms, CaptionMode::PaintOn),
+            (0x14, 0x2A | 0x2B) => self.mode = CaptionMode::Text,
+            // erase displayed memory
+            (0x14, 0x2C) => {
+                self.commit(time_ms);
+                self.displayed.clear();
+                self.displayed_since = None;
+            }
+            // carriage return
+            (0x14, 0x2D) => {
+                if let CaptionMode::RollUp(rows) = self.mode {
+                    self.commit(time_ms);
+                    let top = (self.row + 1).saturating_sub(rows);
+                    for row in top..self.row {
+                        self.displayed.rows[row] = self.displayed.rows[row + 1].clone();
+                    }
+                    self.displayed.rows[self.row].clear();
+                    self.column = 0;
+                    if self.displayed.is_empty() {
+                        self.displayed_since = None;
+                    }
+                }
+            }
+            // erase non-displayed memory
+            (0x14, 0x2E) => self.non_displayed.clear(),
+            // end of caption: flip memories
+            (0x14, 0x2F) => {
+                self.commit(time_ms);
+                std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.displayed_since = if self.displayed.is_empty() { None } else { Some(time_ms) };
+                self.mode = CaptionMode::PopOn;
+            }
+            // tab offsets
+            (0x17, 0x21..=0x23) => {
+                self.column = (self.column + (second - 0x20) as usize).min(CC_COLUMNS - 1);
+            }
+            // mid-row codes take up a space
+            (0x11, 0x20..=0x2F) => self.write(time_ms, ' '),
+            (0x11, 0x30..=0x3F) => self.write(time_ms, SPECIAL_CHARACTERS[(second - 0x30) as usize]),
+            // extended characters replace the standard character sent before them as fallback.
+            (0x12 | 0x13, 0x20..=0x3F) => {
+                let table = if first == 0x12 { &EXTENDED_CHARACTERS_SPANISH_FRENCH } else { &EXTENDED_CHARACTERS_PORTUGUESE_GERMAN };
+                self.column = self.column.saturating_sub(1);
+                self.write(time_ms, table[(second - 0x20) as usize]);
+            }
+            // preamble address code
+            (0x10..=0x17, 0x40..=0x7F) => {
+                let row = PAC_ROWS[(first - 0x10) as usize][((second & 0x20) >> 5) as usize] - 1;
+                let indent = if second & 0x10 != 0 { ((second & 0x0E) >> 1) as usize * 4 } else { 0 };
+                if !matches!(self.mode, CaptionMode::RollUp(_)) {
+                    self.row = row;
+                }
+                self.column = indent;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_mode(&mut self, time_ms: u64, mode: CaptionMode) {
+        let was_roll_up = matches!(self.mode, CaptionMode::RollUp(_));
+        if let CaptionMode::RollUp(_) = mode {
+            if !was_roll_up {
+                self.commit(time_ms);
+                self.displayed.clear();
+                self.non_displayed.clear();
+                self.displayed_since = None;
+                self.row = CC_ROWS - 1;
+                self.column = 0;
+            }
+        } else if was_roll_up {
+            self.commit(time_ms);
+            self.displayed.clear();
+            self.displayed_since = None;
+        }
+        self.mode = mode;
+    }
+
+    #[inline]
+    fn memory(&mut self) -> &mut CaptionMemory {
+        match self.mode {
+            CaptionMode::PopOn | CaptionMode::Text => &mut self.non_displayed,
+            CaptionMode::RollUp(_) | CaptionMode::PaintOn => &mut self.displayed,
+        }
+    }
+
+    fn write(&mut self, time_ms: u64, character: char) {
+        if self.mode == CaptionMode::Text {
+            return;
+        }
+        let (row, column) = (self.row, self.column);
+        self.memory().write(row, column, character);
+        self.column = (self.column + 1).min(CC_COLUMNS - 1);
+        if self.mode != CaptionMode::PopOn {
+            // paint-on and roll-up write straight to the screen.
+            self.displayed_since.get_or_insert(time_ms);
+        }
+    }
+
+    /// Closes the event for what is on screen now; whatever stays displayed starts a new one.
+    fn commit(&mut self, time_ms: u64) {
+        if let Some(start_ms) = self.displayed_since {
+            let text = self.displayed.text();
+            if !text.is_empty() && time_ms > start_ms {
+                self.events.push(CaptionEvent { start_ms, end_ms: time_ms, text });
+            }
+        }
+        self.displayed_since = if self.displayed.is_empty() { None } else { Some(time_ms) };
+    }
+
+    /// Ends whatever is still on screen at `time_ms`.
+    pub fn flush(&mut self, time_ms: u64) {
+        self.commit(time_ms);
+        self.displayed_since = None;
+    }
+
+    pub fn take_events(&mut self) -> Vec<CaptionEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+impl Default for Cea608Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns the cc_data of video samples into caption events.
+/// cc_data follows presentation order, so samples are held back until no earlier one can arrive.
+pub struct CaptionExtractor {
+    pending: BTreeMap<u64, Vec<CcTriplet>>,
+    decoder: Cea608Decoder,
+    last_time_ms: u64,
+}
+
+impl CaptionExtractor {
+    pub fn new() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            decoder: Cea608Decoder::new(),
+            last_time_ms: 0,
+        }
+    }
+
+    /// Must be called for every video sample, captioned or not, in decode order.
+    pub fn push(&mut self, decode_time_ms: u64, presentation_time_ms: u64, triplets: Vec<CcTriplet>) {
+        if !triplets.is_empty() {
+            self.pending.entry(presentation_time_ms).or_default().extend(triplets);
+        }
+        // samples still to come are presented no earlier than this one is decoded.
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() >= decode_time_ms {
+                break;
+            }
+            let (time_ms, triplets) = entry.remove_entry();
+            self.decode(time_ms, &triplets);
+        }
+    }
+
+    fn decode(&mut self, time_ms: u64, triplets: &[CcTriplet]) {
+        self.last_time_ms = time_ms;
+        for triplet in triplets.iter().filter(|triplet| triplet.cc_valid && triplet.cc_type == CC_TYPE_NTSC_FIELD_1) {
+            self.decoder.decode(time_ms, triplet.data);
+        }
+    }
+
+    /// Decodes everything held back and ends the caption on screen, e.g. at the end of the stream.
+    pub fn flush(&mut self) {
+        while let Some((time_ms, triplets)) = self.pending.pop_first() {
+            self.decode(time_ms, &triplets);
+        }
+        self.decoder.flush(self.last_time_ms);
+    }
+
+    /// Drops all state, e.g. after a seek.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn take_events(&mut self) -> Vec<CaptionEvent> {
+        self.decoder.take_events()
+    }
+}
+
+impl Default for CaptionExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn format_timestamp(time_ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        time_ms / 3_600_000,
+        time_ms / 60_000 % 60,
+        time_ms / 1000 % 60,
+        separator,
+        time_ms % 1000
+    )
+}
+
+/// Cue text must not contain markup or `-->`.
+fn escape_webvtt(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub fn to_webvtt(events: &[CaptionEvent]) -> String {
+    let mut result = String::from("WEBVTT\n\n");
+    for event in events {
+        result.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(event.start_ms, '.'),
+            format_timestamp(event.end_ms, '.'),
+            escape_webvtt(&event.text)
+        ));
+    }
+    result
+}
+
+pub fn to_srt(events: &[CaptionEvent]) -> String {
+    let mut result = String::new();
+    for (index, event) in events.iter().enumerate() {
+        result.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            format_timestamp(event.start_ms, ','),
+            format_timestamp(event.end_ms, ','),
+            event.text
+        ));
+    }
+    result
+}
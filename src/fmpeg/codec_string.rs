@@ -0,0 +1,119 @@
+use crate::fmpeg::aac::AudioSpecificConfig;
+use crate::fmpeg::av1::Av1CodecConfigurationRecord;
+use crate::fmpeg::avc::AvcDecoderConfigurationRecord;
+use crate::fmpeg::hevc::HevcDecoderConfigurationRecord;
+use crate::fmpeg::parser::{Mp3Version, PcmFormat};
+use crate::fmpeg::remux_context::AudioCodecType;
+use crate::fmpeg::vp9::Vp9CodecConfigurationRecord;
+
+/// `esds` objectTypeIndication of MPEG-1 audio (ISO/IEC 11172-3), i.e. MP3 at 32 to 48 kHz.
+pub const OBJECT_TYPE_MPEG1_AUDIO: u8 = 0x6B;
+/// `esds` objectTypeIndication of MPEG-2 audio (ISO/IEC 13818-3), which also covers MPEG-2.5.
+pub const OBJECT_TYPE_MPEG2_AUDIO: u8 = 0x69;
+
+/// MP3 signalled as MPEG-4 audio object type 34 (Layer 3) instead.
+pub const MP3_MPEG4: &str = "mp4a.40.34";
+pub const OPUS: &str = "opus";
+pub const FLAC: &str = "flac";
+pub const AC3: &str = "ac-3";
+pub const EAC3: &str = "ec-3";
+
+/// `avc1.PPCCLL`, each byte as two hex digits (ISO/IEC 14496-15, E.2).
+pub fn avc(profile_indication: u8, profile_compatibility: u8, level_indication: u8) -> String {
+    format!("avc1.{:02x}{:02x}{:02x}", profile_indication, profile_compatibility, level_indication)
+}
+
+pub fn avc_record(record: &AvcDecoderConfigurationRecord) -> String {
+    avc(record.avc_profile_indication, record.profile_compatibility, record.avc_level_indication)
+}
+
+/// E.g. `hvc1.1.6.L93.B0` (ISO/IEC 14496-15, E.3).
+pub fn hevc(record: &HevcDecoderConfigurationRecord) -> String {
+    let profile_space = match record.general_profile_space {
+        1 => "A",
+        2 => "B",
+        3 => "C",
+        _ => "",
+    };
+    let mut codec = format!(
+        "{}.{}{}.{:X}.{}{}",
+        record.sample_entry_type().iter().collect::<String>(),
+        profile_space,
+        record.general_profile_idc,
+        record.general_profile_compatibility_flags.reverse_bits(),
+        if record.general_tier_flag { 'H' } else { 'L' },
+        record.general_level_idc
+    );
+    let constraints = record.general_constraint_indicator_flags.to_be_bytes();
+    // only the low 48 bits are used; trailing zero bytes are left out.
+    let constraints = &constraints[2..];
+    let end = constraints.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    for byte in &constraints[..end] {
+        codec.push_str(&format!(".{:X}", byte));
+    }
+    codec
+}
+
+/// `av01.P.LLT.DD` (AV1-ISOBMFF, Annex A).
+pub fn av1(record: &Av1CodecConfigurationRecord) -> String {
+    format!(
+        "av01.{}.{:02}{}.{:02}",
+        record.seq_profile,
+        record.seq_level_idx_0,
+        if record.seq_tier_0 { 'H' } else { 'M' },
+        record.bit_depth()
+    )
+}
+
+/// `vp09.PP.LL.DD` (VP Codec ISO Media File Format Binding, Codecs Parameter String).
+pub fn vp9(record: &Vp9CodecConfigurationRecord) -> String {
+    format!("vp09.{:02}.{:02}.{:02}", record.profile, record.level, record.bit_depth)
+}
+
+/// `mp4a.40.AOT`, with 5 for HE-AAC and 29 for HE-AAC v2 (RFC 6381, 3.3).
+pub fn aac(config: &AudioSpecificConfig) -> String {
+    aac_object_type(config.codec_object_type())
+}
+
+pub fn aac_object_type(audio_object_type: u8) -> String {
+    format!("mp4a.40.{}", audio_object_type)
+}
+
+/// `mp4a.6B` or `mp4a.69`, from the objectTypeIndication of the `esds` box.
+pub fn mp3(object_type_indication: u8) -> String {
+    format!("mp4a.{:02X}", object_type_indication)
+}
+
+pub fn mp3_object_type(version: Mp3Version) -> u8 {
+    match version {
+        Mp3Version::Mp10 => OBJECT_TYPE_MPEG1_AUDIO,
+        _ => OBJECT_TYPE_MPEG2_AUDIO,
+    }
+}
+
+/// Uncompressed audio has no parameters; the sample entry type is used as is.
+pub fn pcm(format: PcmFormat) -> String {
+    format.sample_entry_type().iter().collect()
+}
+
+/// `None` when the codec is unknown.
+pub fn audio(codec_type: &AudioCodecType, object_type: u8) -> Option<String> {
+    match codec_type {
+        AudioCodecType::Aac => Some(aac_object_type(object_type)),
+        AudioCodecType::Mp3 => Some(mp3(object_type)),
+        AudioCodecType::Opus => Some(OPUS.to_string()),
+        AudioCodecType::Pcm(format) => Some(pcm(*format)),
+        AudioCodecType::None => None,
+    }
+}
+
+/// The type to pass to `MediaSource.isTypeSupported`, e.g. `video/mp4; codecs="avc1.64001f, mp4a.40.2"`.
+/// `audio/mp4` when there is no video.
+pub fn mime_type(video: Option<&str>, audio: Option<&str>) -> String {
+    let codecs: Vec<&str> = [video, audio].into_iter().flatten().collect();
+    let media_type = if video.is_some() { "video/mp4" } else { "audio/mp4" };
+    if codecs.is_empty() {
+        return media_type.to_string();
+    }
+    format!("{}; codecs=\"{}\"", media_type, codecs.join(", "))
+}
@@ -0,0 +1,111 @@
+use crate::fmpeg::timestamp::Timestamp;
+use crate::fmpeg::remux_context::SampleContext;
+
+/// Timestamps are rounded to milliseconds in FLV, so this much jitter is always tolerated.
+const JITTER_MS: u32 = 2;
+
+/// A sample waiting for the next one on its track to learn its duration.
+pub struct PendingSample {
+    pub sample_ctx: SampleContext,
+    pub payload: Vec<u8>,
+}
+
+/// Decode time where a sample was expected, and the later one it actually had.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleGap {
+    pub expected: u64,
+    pub decode_time: u64,
+}
+
+impl SampleGap {
+    pub fn duration(&self) -> u64 {
+        self.decode_time - self.expected
+    }
+}
+
+/// Holds back one sample per track and sets its duration from the decode time of the next one,
+/// so that variable frame rates and dropped frames don't drift from the FLV timestamps.
+///
+/// A decode time within the jitter tolerance of the nominal one is snapped to it; further off,
+/// the real one is kept, and when a whole nominal duration or more is missing a gap is reported.
+/// Snapping never drifts further than the tolerance, as it is measured against the real time.
+#[derive(Default)]
+pub struct DurationTracker {
+    pending: Option<PendingSample>,
+    // duration the pending sample would have on its own; 0 when unknown.
+    nominal: u32,
+    last_duration: Option<u32>,
+    gaps: u32,
+}
+
+impl DurationTracker {
+    pub fn new() -> Self {
+        Self { pending: None, nominal: 0, last_duration: None, gaps: 0 }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Number of gaps reported so far.
+    pub fn gaps(&self) -> u32 {
+        self.gaps
+    }
+
+    fn tolerance(nominal: u32, timescale: u32) -> u64 {
+        (Timestamp::from_millis(JITTER_MS as i64).ticks(timescale) as u64).max(nominal as u64 / 10)
+    }
+
+    /// Holds `sample_ctx` and releases the previous sample, with its duration set.
+    /// `nominal` is the duration the codec or frame rate gives for the new sample, 0 if unknown,
+    /// in ticks of `timescale`.
+    pub fn push(&mut self, mut sample_ctx: SampleContext, payload: Vec<u8>, nominal: u32, timescale: u32) -> (Option<PendingSample>, Option<SampleGap>) {
+        let mut gap = None;
+        let released = self.pending.take().map(|mut previous| {
+            let decode_time = sample_ctx.decode_time;
+            let start = previous.sample_ctx.decode_time;
+            let duration = if decode_time <= start {
+                // out of order; nothing better than the nominal duration is known.
+                self.nominal.max(1)
+            } else if self.nominal == 0 {
+                (decode_time - start) as u32
+            } else {
+                let expected = start + self.nominal as u64;
+                let tolerance = Self::tolerance(self.nominal, timescale);
+                if decode_time.abs_diff(expected) <= tolerance {
+                    sample_ctx.decode_time = expected;
+                    self.nominal
+                } else {
+                    if decode_time > expected + tolerance.max(self.nominal as u64) {
+                        self.gaps += 1;
+                        gap = Some(SampleGap { expected, decode_time });
+                    }
+                    (decode_time - start) as u32
+                }
+            };
+            previous.sample_ctx.sample_duration = duration;
+            self.last_duration = Some(duration);
+            previous
+        });
+        self.pending = Some(PendingSample { sample_ctx, payload });
+        self.nominal = nominal;
+        (released, gap)
+    }
+
+    /// Releases the held sample with its nominal duration, or the last one seen if that is unknown.
+    pub fn flush(&mut self) -> Option<PendingSample> {
+        let mut pending = self.pending.take()?;
+        pending.sample_ctx.sample_duration = match self.nominal {
+            0 => self.last_duration.unwrap_or(1),
+            nominal => nominal,
+        };
+        Some(pending)
+    }
+
+    /// Drops the held sample, e.g. on seek.
+    pub fn clear(&mut self) {
+        self.pending = None;
+        self.nominal = 0;
+        self.last_duration = None;
+    }
+}
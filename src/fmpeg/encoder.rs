@@ -1,13 +1,16 @@
 use crate::fmpeg::mp4frag::{MovieDataBox, MovieFragmentBox, SampleDependencyTableBoxBuilder, SampleFlagBuilder, TrackFragmentBox, TrackFragmentBoxBuilder, TrackRunBoxBuilder};
 use crate::fmpeg::mp4head;
 use crate::fmpeg::mp4head::aac_utils::AacAudioSpecConfLike;
-use crate::fmpeg::mp4head::{AudioMediaHandlerBox, FileTypeBox, FixedPoint32, HandlerType, MediaBox, MovieBox, MovieHeaderBox, SampleBoxTableBox, VideoMediaHandlerBox, XMediaHandlerBox};
-use crate::fmpeg::remux_context::{AudioCodecType, SampleContext, RemuxContext, TrackContext, TrackType, VideoCodecType, TIME_SCALE};
+use crate::fmpeg::mp4head::{AudioMediaHandlerBox, FileTypeBox, NullMediaHandlerBox, FixedPoint32, HandlerType, MediaBox, MovieBox, MovieHeaderBox, SampleBoxTableBox, VideoMediaHandlerBox, XMediaHandlerBox};
+use crate::fmpeg::remux_context::{AudioCodecType, SampleContext, RemuxContext, TrackContext, TrackType, VideoCodecType};
+use crate::fmpeg::timestamp::{Timestamp, MILLISECONDS};
+use crate::fmpeg::parser::PcmFormat;
 
 pub struct Encoder;
 
 pub const DEFAULT_VIDEO_TRACK_ID: u32 = 1;
 pub const DEFAULT_AUDIO_TRACK_ID: u32 = 2;
+pub const DEFAULT_CAPTION_TRACK_ID: u32 = 3;
 
 impl Encoder {
     pub fn encode_ftyp(ctx: &RemuxContext) -> FileTypeBox {
@@ -21,12 +24,16 @@ impl Encoder {
     }
 
     pub fn encode_moov(ctx: &RemuxContext) -> MovieBox {
-        let moov = mp4head::MovieBoxBuilder::new()
+        let mut moov = mp4head::MovieBoxBuilder::new()
             .movie_header_box(Self::encode_mhdv(ctx))
-            .track(Self::encode_trak(ctx, DEFAULT_VIDEO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Video)))
-            .track(Self::encode_trak(ctx, DEFAULT_AUDIO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Audio)))
-            .build();
-        moov
+            .track(Self::encode_video_trak(ctx));
+        if ctx.audio_track {
+            moov = moov.track(Self::encode_trak(ctx, DEFAULT_AUDIO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Audio)));
+        }
+        if ctx.caption_track {
+            moov = moov.track(Self::encode_trak(ctx, DEFAULT_CAPTION_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Text)));
+        }
+        moov.build()
     }
 
     pub fn encode_mhdv(ctx: &RemuxContext) -> MovieHeaderBox {
@@ -34,8 +41,8 @@ impl Encoder {
             .creation_time(0)
             .modification_time(0)
             .duration(ctx.duration_ms)
-            .timescale(TIME_SCALE)
-            .next_track_id(3)
+            .timescale(MILLISECONDS)
+            .next_track_id(if ctx.caption_track { DEFAULT_CAPTION_TRACK_ID + 1 } else { DEFAULT_AUDIO_TRACK_ID + 1 })
             .rate(1.0)
             .volume(1.0)
             .build();
@@ -51,7 +58,7 @@ impl Encoder {
                     .duration(ctx.duration_ms)
                     .creation_time(0)
                     .modification_time(0)
-                    .width(FixedPoint32::from(ctx.width))
+                    .width(FixedPoint32::from(ctx.display_width()))
                     .height(FixedPoint32::from(ctx.height))
                     .build()
             ),
@@ -61,9 +68,22 @@ impl Encoder {
         trak
     }
 
+    /// With B-frames the first sample is presented after its decode time; the edit list skips to it,
+    /// leaving the composition offsets in the fragments as they were in the FLV tags.
+    pub fn encode_video_trak(ctx: &RemuxContext) -> mp4head::TrackBox {
+        let trak = Self::encode_trak(ctx, DEFAULT_VIDEO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Video));
+        match ctx.video_composition_shift {
+            Some(shift) if shift > 0 => {
+                let media_time = Timestamp::from_millis(shift as i64).ticks(ctx.video_timescale) as i32;
+                trak.with_edit_box(mp4head::EditBox::new(mp4head::EditListBox::new(ctx.duration_ms, media_time)))
+            }
+            _ => trak,
+        }
+    }
+
     pub fn encode_mdia(ctx: &RemuxContext, handler_type: HandlerType) -> MediaBox {
         let mdia = mp4head::MediaBox::new(
-            Self::encode_mdhd(ctx),
+            Self::encode_mdhd(ctx, handler_type.clone()),
             Self::encode_hdlr(ctx, handler_type.clone()),
             Self::encode_minf(ctx, handler_type),
         );
@@ -71,12 +91,19 @@ impl Encoder {
         mdia
     }
 
-    pub fn encode_mdhd(ctx: &RemuxContext) -> mp4head::MediaHeaderBoxV0 {
+    /// Each track counts in its own timescale; the duration is converted from the movie's milliseconds.
+    pub fn encode_mdhd(ctx: &RemuxContext, handler_type: HandlerType) -> mp4head::MediaHeaderBoxV0 {
+        let timescale = match handler_type {
+            HandlerType::Video => ctx.video_timescale,
+            HandlerType::Audio => ctx.audio_timescale,
+            HandlerType::Text => MILLISECONDS,
+        };
+        let duration = Timestamp::from_millis(ctx.duration_ms as i64).ticks(timescale);
         let mdhd = mp4head::MediaHeaderBoxV0Builder::new()
             .creation_time(0)
             .modification_time(0)
-            .timescale(TIME_SCALE)
-            .duration(ctx.duration_ms)
+            .timescale(timescale)
+            .duration(u32::try_from(duration).unwrap_or(u32::MAX))
             .build();
         // dbg!(&mdhd);
         mdhd
@@ -96,6 +123,9 @@ impl Encoder {
             HandlerType::Audio => {
                 XMediaHandlerBox::Audio(AudioMediaHandlerBox::new())
             }
+            HandlerType::Text => {
+                XMediaHandlerBox::Null(NullMediaHandlerBox::new())
+            }
         };
         let dinf = mp4head::DataInformationBox::new();
         let stsd = mp4head::SampleDescriptionTableBoxBuilder::new()
@@ -111,6 +141,30 @@ impl Encoder {
                                     // todo: here is the place to add video configuration
                                     .build()
                             )
+                        } else if let (VideoCodecType::Hevc, Some(record)) = (&ctx.video_codec_type, ctx.video_hevc_config.as_ref()) {
+                            mp4head::SubSampleDescriptionTableBox::Visual(
+                                mp4head::VisualSampleEntryBoxBuilder::new(record.sample_entry_type())
+                                    .set_width(ctx.width as u16)
+                                    .set_height(ctx.height as u16)
+                                    .config_box(['h', 'v', 'c', 'C'], ctx.video_hvcc_info())
+                                    .build()
+                            )
+                        } else if let VideoCodecType::Av1 = ctx.video_codec_type {
+                            mp4head::SubSampleDescriptionTableBox::Visual(
+                                mp4head::VisualSampleEntryBoxBuilder::new(['a', 'v', '0', '1'])
+                                    .set_width(ctx.width as u16)
+                                    .set_height(ctx.height as u16)
+                                    .config_box(['a', 'v', '1', 'C'], ctx.video_av1c_info())
+                                    .build()
+                            )
+                        } else if let VideoCodecType::Vp9 = ctx.video_codec_type {
+                            mp4head::SubSampleDescriptionTableBox::Visual(
+                                mp4head::VisualSampleEntryBoxBuilder::new(['v', 'p', '0', '9'])
+                                    .set_width(ctx.width as u16)
+                                    .set_height(ctx.height as u16)
+                                    .config_box(['v', 'p', 'c', 'C'], ctx.video_vpcc_info())
+                                    .build()
+                            )
                         } else {
                             panic!("Unsupported video codec type")
                         }
@@ -127,10 +181,35 @@ impl Encoder {
                                 )
                             }
                             AudioCodecType::Mp3 => {
-                                mp4head::SubSampleDescriptionTableBox::Mp3(
-                                    mp4head::Mp3DescriptionBoxBuilder::new()
+                                // `mp4a` with an `esds`, which MSE understands unlike `.mp3`.
+                                mp4head::SubSampleDescriptionTableBox::Audio(
+                                    mp4head::AudioSampleEntryBoxBuilder::new(['m', 'p', '4', 'a'])
                                         .sample_rate(ctx.audio_sample_rate as f32)
                                         .num_audio_channels(ctx.audio_channels as u16)
+                                        .config_box(['e', 's', 'd', 's'], mp4head::es_descriptor_payload(ctx.audio_mp3_object_type, &[]))
+                                        .build()
+                                )
+                            }
+                            AudioCodecType::Opus => {
+                                mp4head::SubSampleDescriptionTableBox::Audio(
+                                    mp4head::AudioSampleEntryBoxBuilder::new(['O', 'p', 'u', 's'])
+                                        .sample_rate(ctx.audio_sample_rate as f32)
+                                        .num_audio_channels(ctx.audio_channels as u16)
+                                        .config_box(['d', 'O', 'p', 's'], ctx.audio_opus_head.as_ref().map_or(vec![], |head| head.to_dops()))
+                                        .build()
+                                )
+                            }
+                            AudioCodecType::Pcm(format) => {
+                                // G.711 entries describe the decoded 16-bit samples.
+                                let sample_size = match format {
+                                    PcmFormat::ALaw | PcmFormat::MuLaw => 16,
+                                    _ => ctx.audio_sample_size,
+                                };
+                                mp4head::SubSampleDescriptionTableBox::Audio(
+                                    mp4head::AudioSampleEntryBoxBuilder::new(format.sample_entry_type())
+                                        .sample_rate(ctx.audio_sample_rate as f32)
+                                        .num_audio_channels(ctx.audio_channels as u16)
+                                        .sample_size(sample_size)
                                         .build()
                                 )
                             }
@@ -139,6 +218,9 @@ impl Encoder {
                             }
                         }
                     }
+                    HandlerType::Text => {
+                        mp4head::SubSampleDescriptionTableBox::Wvtt(mp4head::WvttDescriptionBox::new())
+                    }
                 }
             )
             .build();
@@ -177,7 +259,7 @@ impl Encoder {
                             SampleDependencyTableBoxBuilder::VideoInterFrame
                         }
                     },
-                    TrackType::Audio => SampleDependencyTableBoxBuilder::Audio,
+                    TrackType::Audio | TrackType::Text => SampleDependencyTableBoxBuilder::Audio,
                 }
             )
             .with_track_run_box(
@@ -0,0 +1,230 @@
+use crate::flv::header::{AudioTagHeader, TagHeader};
+use crate::flv::tag::{NormalTagBody, Tag, TagBody};
+use crate::fmpeg::ogg::OggWriter;
+use crate::fmpeg::parser::SOUND_RATE_TABLE;
+use crate::fmpeg::speex::{comment_packet, SpeexHeader, SPEEX_FRAME_SIZE, SPEEX_SAMPLE_RATE};
+use crate::stage::ITagFilter;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+const SOUND_FORMAT_NELLYMOSER_16K: u8 = 4;
+const SOUND_FORMAT_NELLYMOSER_8K: u8 = 5;
+const SOUND_FORMAT_NELLYMOSER: u8 = 6;
+const SOUND_FORMAT_SPEEX: u8 = 11;
+
+/// A Nellymoser block: 64 bytes decode to 256 samples.
+const NELLYMOSER_BLOCK_SIZE: u32 = 64;
+const NELLYMOSER_BLOCK_SAMPLES: u32 = 256;
+
+const OGG_SERIAL: u32 = 0x464C5653;
+const VENDOR: &str = "flv-rs";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtractedCodec {
+    /// Written as an Ogg Speex stream.
+    Speex,
+    /// Written as the concatenated raw packets, described by the sidecar.
+    Nellymoser { sample_rate: u32, channels: u8 },
+}
+
+impl ExtractedCodec {
+    /// Whether MP4 can't carry audio of this FLV sound format, which then has to be extracted.
+    pub fn is_extracted(sound_format: u8) -> bool {
+        matches!(sound_format, SOUND_FORMAT_NELLYMOSER_16K | SOUND_FORMAT_NELLYMOSER_8K | SOUND_FORMAT_NELLYMOSER | SOUND_FORMAT_SPEEX)
+    }
+
+    /// The codecs MP4 can't carry; `None` for everything else.
+    pub fn from_header(header: &AudioTagHeader) -> Option<Self> {
+        let channels = if header.sound_type { 2 } else { 1 };
+        match header.sound_format {
+            SOUND_FORMAT_SPEEX => Some(ExtractedCodec::Speex),
+            SOUND_FORMAT_NELLYMOSER_16K => Some(ExtractedCodec::Nellymoser { sample_rate: 16000, channels: 1 }),
+            SOUND_FORMAT_NELLYMOSER_8K => Some(ExtractedCodec::Nellymoser { sample_rate: 8000, channels: 1 }),
+            SOUND_FORMAT_NELLYMOSER => Some(ExtractedCodec::Nellymoser {
+                sample_rate: SOUND_RATE_TABLE[header.sound_rate as usize],
+                channels,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExtractedCodec::Speex => "spx",
+            ExtractedCodec::Nellymoser { .. } => "nelly",
+        }
+    }
+}
+
+/// The standalone audio file being built from the extracted tags.
+/// The codec is fixed by the first extracted tag; tags of another codec are skipped.
+pub struct AudioExtraction {
+    codec: Option<ExtractedCodec>,
+    ogg: OggWriter,
+    first_timestamp: u32,
+    granule_position: u64,
+    data: Vec<u8>,
+    offset: u64,
+    sidecar: String,
+    packets: u32,
+    skipped: u32,
+    finished: bool,
+}
+
+impl AudioExtraction {
+    pub fn new() -> Self {
+        Self {
+            codec: None,
+            ogg: OggWriter::new(OGG_SERIAL),
+            first_timestamp: 0,
+            granule_position: 0,
+            data: vec![],
+            offset: 0,
+            sidecar: String::new(),
+            packets: 0,
+            skipped: 0,
+            finished: false,
+        }
+    }
+
+    pub fn push(&mut self, codec: ExtractedCodec, timestamp: u32, packet: &[u8]) {
+        if self.finished {
+            return;
+        }
+        match self.codec {
+            None => self.start(codec, timestamp),
+            Some(current) if current != codec => {
+                self.skipped += 1;
+                return;
+            }
+            _ => {}
+        }
+
+        match codec {
+            ExtractedCodec::Speex => {
+                // granule positions count samples at the end of each packet, and must not go backwards.
+                let elapsed = timestamp.saturating_sub(self.first_timestamp) as u64 * (SPEEX_SAMPLE_RATE / 1000) as u64;
+                self.granule_position = (elapsed + SPEEX_FRAME_SIZE as u64).max(self.granule_position + SPEEX_FRAME_SIZE as u64);
+                let mut pages = self.ogg.write_packet(packet, self.granule_position);
+                self.data.append(&mut pages);
+            }
+            ExtractedCodec::Nellymoser { .. } => {
+                let _ = writeln!(self.sidecar, "{} {} {}", self.offset, packet.len(), timestamp);
+                self.offset += packet.len() as u64;
+                self.data.extend_from_slice(packet);
+            }
+        }
+        self.packets += 1;
+    }
+
+    fn start(&mut self, codec: ExtractedCodec, timestamp: u32) {
+        self.codec = Some(codec);
+        self.first_timestamp = timestamp;
+        match codec {
+            ExtractedCodec::Speex => {
+                // both header packets sit on pages of their own.
+                let mut pages = self.ogg.write_packet(&SpeexHeader::flv().serialize(), 0);
+                pages.append(&mut self.ogg.flush());
+                pages.append(&mut self.ogg.write_packet(&comment_packet(VENDOR), 0));
+                pages.append(&mut self.ogg.flush());
+                self.data.append(&mut pages);
+            }
+            ExtractedCodec::Nellymoser { sample_rate, channels } => {
+                let _ = writeln!(self.sidecar, "codec=nellymoser");
+                let _ = writeln!(self.sidecar, "sample_rate={}", sample_rate);
+                let _ = writeln!(self.sidecar, "channels={}", channels);
+                let _ = writeln!(self.sidecar, "block_size={}", NELLYMOSER_BLOCK_SIZE);
+                let _ = writeln!(self.sidecar, "block_samples={}", NELLYMOSER_BLOCK_SAMPLES);
+                let _ = writeln!(self.sidecar, "# offset size timestamp_ms");
+            }
+        }
+    }
+
+    /// Closes the Ogg stream; nothing is extracted afterwards.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        if let Some(ExtractedCodec::Speex) = self.codec {
+            let mut pages = self.ogg.finish();
+            self.data.append(&mut pages);
+        }
+    }
+
+    pub fn codec(&self) -> Option<ExtractedCodec> {
+        self.codec
+    }
+
+    /// Drains the file content written so far, so that it can be streamed out.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    /// The description written next to raw Nellymoser output.
+    pub fn sidecar(&self) -> Option<&str> {
+        match self.codec {
+            Some(ExtractedCodec::Nellymoser { .. }) => Some(&self.sidecar),
+            _ => None,
+        }
+    }
+
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+
+    /// Tags dropped because their codec differs from the first one.
+    pub fn skipped(&self) -> u32 {
+        self.skipped
+    }
+}
+
+impl Default for AudioExtraction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Takes Speex and Nellymoser tags out of the pipeline, which the remuxer would reject,
+/// and writes them into a shared `AudioExtraction`. Other tags pass through.
+pub struct AudioExtractFilter {
+    extraction: Arc<Mutex<AudioExtraction>>,
+}
+
+impl AudioExtractFilter {
+    pub fn new() -> Self {
+        Self { extraction: Arc::new(Mutex::new(AudioExtraction::new())) }
+    }
+
+    /// Handle to read the output from, and to `finish` once the stream has ended.
+    pub fn extraction(&self) -> Arc<Mutex<AudioExtraction>> {
+        self.extraction.clone()
+    }
+}
+
+impl Default for AudioExtractFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ITagFilter for AudioExtractFilter {
+    fn filter_tag(&mut self, tag: Tag) -> Option<Tag> {
+        let codec = match tag.tag_header {
+            TagHeader::Audio(ref header) => ExtractedCodec::from_header(header),
+            _ => None,
+        };
+        let codec = match codec {
+            Some(codec) => codec,
+            None => return Some(tag),
+        };
+        if let TagBody::Normal(NormalTagBody::Audio(ref body)) = tag.tag_body {
+            let (front, back) = body.as_slices();
+            let packet = [front, back].concat();
+            if let Ok(mut extraction) = self.extraction.lock() {
+                extraction.push(codec, tag.timestamp, &packet);
+            }
+        }
+        None
+    }
+}
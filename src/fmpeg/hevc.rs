@@ -0,0 +1,538 @@
+use crate::fmpeg::codec_string;
+use crate::fmpeg::avc::{ColourDescription, NaluIter, AVCC_LENGTH_SIZE, EXTENDED_SAR, SAR_TABLE};
+use crate::io::bit::{remove_emulation_prevention, BitReader};
+
+/// HEVC NAL unit types relevant to remuxing (ITU-T H.265, Table 7-1).
+pub const NAL_UNIT_BLA_W_LP: u8 = 16;
+pub const NAL_UNIT_RSV_IRAP_VCL23: u8 = 23;
+pub const NAL_UNIT_VPS: u8 = 32;
+pub const NAL_UNIT_SPS: u8 = 33;
+pub const NAL_UNIT_PPS: u8 = 34;
+pub const NAL_UNIT_AUD: u8 = 35;
+pub const NAL_UNIT_PREFIX_SEI: u8 = 39;
+
+/// Type of a NAL unit from its two byte header.
+#[inline]
+pub fn nal_unit_type(nal_unit: &[u8]) -> u8 {
+    (nal_unit[0] >> 1) & 0x3F
+}
+
+/// BLA, IDR and CRA pictures, i.e. the ones decoding can start at.
+#[inline]
+pub fn is_irap(nal_unit_type: u8) -> bool {
+    (NAL_UNIT_BLA_W_LP..=NAL_UNIT_RSV_IRAP_VCL23).contains(&nal_unit_type)
+}
+
+/// Whether a 4-byte length-prefixed sample contains an IRAP picture.
+pub fn contains_irap(payload: &[u8]) -> bool {
+    NaluIter::new(payload, AVCC_LENGTH_SIZE).any(|nalu| is_irap(nal_unit_type(nalu.data)))
+}
+
+/// The NAL units of one type carried by the record, e.g. all VPS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcNaluArray {
+    /// Set when all NAL units of this type are in the record and none are in the samples.
+    pub array_completeness: bool,
+    pub nal_unit_type: u8,
+    pub nal_units: Vec<Vec<u8>>,
+}
+
+/// The payload of an HEVC sequence header tag, i.e. the content of the `hvcC` box (ISO/IEC 14496-15, 8.3.3.1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcDecoderConfigurationRecord {
+    pub configuration_version: u8,
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    /// 48 bits.
+    pub general_constraint_indicator_flags: u64,
+    pub general_level_idc: u8,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub length_size_minus_one: u8,
+
+    pub arrays: Vec<HevcNaluArray>,
+}
+
+impl HevcDecoderConfigurationRecord {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() < 23 {
+            return Err("HEVC decoder configuration record truncated.".into());
+        }
+        let mut reader = BitReader::new(data);
+        let configuration_version = reader.read_bits(8)? as u8;
+        if configuration_version != 1 {
+            return Err(format!("Unsupported HEVC configurationVersion {}.", configuration_version).into());
+        }
+
+        let general_profile_space = reader.read_bits(2)? as u8;
+        let general_tier_flag = reader.read_bit()?;
+        let general_profile_idc = reader.read_bits(5)? as u8;
+        let general_profile_compatibility_flags = reader.read_bits(32)?;
+        let general_constraint_indicator_flags = reader.read_bits_u64(48)?;
+        let general_level_idc = reader.read_bits(8)? as u8;
+        reader.skip_bits(4)?;
+        let min_spatial_segmentation_idc = reader.read_bits(12)? as u16;
+        reader.skip_bits(6)?;
+        let parallelism_type = reader.read_bits(2)? as u8;
+        reader.skip_bits(6)?;
+        let chroma_format_idc = reader.read_bits(2)? as u8;
+        reader.skip_bits(5)?;
+        let bit_depth_luma_minus8 = reader.read_bits(3)? as u8;
+        reader.skip_bits(5)?;
+        let bit_depth_chroma_minus8 = reader.read_bits(3)? as u8;
+        let avg_frame_rate = reader.read_bits(16)? as u16;
+        let constant_frame_rate = reader.read_bits(2)? as u8;
+        let num_temporal_layers = reader.read_bits(3)? as u8;
+        let temporal_id_nested = reader.read_bit()?;
+        let length_size_minus_one = reader.read_bits(2)? as u8;
+        if length_size_minus_one == 2 {
+            return Err("Invalid HEVC NAL unit length size 3.".into());
+        }
+
+        let num_of_arrays = reader.read_bits(8)?;
+        let mut arrays = Vec::with_capacity(num_of_arrays as usize);
+        for _ in 0..num_of_arrays {
+            let array_completeness = reader.read_bit()?;
+            reader.skip_bits(1)?;
+            let nal_unit_type = reader.read_bits(6)? as u8;
+            let num_nalus = reader.read_bits(16)?;
+            let mut nal_units = Vec::with_capacity(num_nalus as usize);
+            for _ in 0..num_nalus {
+                let length = reader.read_bits(16)? as usize;
+                if length == 0 {
+                    return Err(format!("HEVC decoder configuration record has an empty NAL unit of type {}.", nal_unit_type).into());
+                }
+                nal_units.push(reader.read_bytes(length)?.to_vec());
+            }
+            arrays.push(
+                HevcNaluArray {
+                    array_completeness,
+                    nal_unit_type,
+                    nal_units,
+                }
+            );
+        }
+
+        let record = Self {
+            configuration_version,
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_constraint_indicator_flags,
+            general_level_idc,
+            min_spatial_segmentation_idc,
+            parallelism_type,
+            chroma_format_idc,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            avg_frame_rate,
+            constant_frame_rate,
+            num_temporal_layers,
+            temporal_id_nested,
+            length_size_minus_one,
+            arrays,
+        };
+        for (nal_unit_type, name) in [(NAL_UNIT_VPS, "VPS"), (NAL_UNIT_SPS, "SPS"), (NAL_UNIT_PPS, "PPS")] {
+            if record.nal_units(nal_unit_type).next().is_none() {
+                return Err(format!("HEVC decoder configuration record has no {}.", name).into());
+            }
+        }
+        Ok(record)
+    }
+
+    /// Size in bytes of the length prefix of every NAL unit in the samples.
+    #[inline]
+    pub fn nal_length_size(&self) -> u8 {
+        self.length_size_minus_one + 1
+    }
+
+    /// All NAL units of the given type, e.g. `NAL_UNIT_SPS`.
+    pub fn nal_units(&self, nal_unit_type: u8) -> impl Iterator<Item = &Vec<u8>> {
+        self.arrays
+            .iter()
+            .filter(move |array| array.nal_unit_type == nal_unit_type)
+            .flat_map(|array| array.nal_units.iter())
+    }
+
+    /// Whether every parameter set is in the record, as `hvc1` requires; otherwise `hev1` must be used.
+    pub fn has_complete_parameter_sets(&self) -> bool {
+        self.arrays
+            .iter()
+            .filter(|array| matches!(array.nal_unit_type, NAL_UNIT_VPS | NAL_UNIT_SPS | NAL_UNIT_PPS))
+            .all(|array| array.array_completeness)
+    }
+
+    /// Sample entry type matching the record, `hvc1` or `hev1`.
+    pub fn sample_entry_type(&self) -> [char; 4] {
+        if self.has_complete_parameter_sets() {
+            ['h', 'v', 'c', '1']
+        } else {
+            ['h', 'e', 'v', '1']
+        }
+    }
+
+    /// RFC 6381 codec string, e.g. `hvc1.1.6.L93.B0`.
+    pub fn codec_string(&self) -> String {
+        codec_string::hevc(self)
+    }
+
+    /// Writes the record back, with reserved bits set to 1 as required.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.configuration_version,
+            (self.general_profile_space << 6) | ((self.general_tier_flag as u8) << 5) | self.general_profile_idc,
+        ];
+        data.extend_from_slice(&self.general_profile_compatibility_flags.to_be_bytes());
+        data.extend_from_slice(&self.general_constraint_indicator_flags.to_be_bytes()[2..]);
+        data.push(self.general_level_idc);
+        data.extend_from_slice(&(0xF000 | self.min_spatial_segmentation_idc).to_be_bytes());
+        data.push(0xFC | self.parallelism_type);
+        data.push(0xFC | self.chroma_format_idc);
+        data.push(0xF8 | self.bit_depth_luma_minus8);
+        data.push(0xF8 | self.bit_depth_chroma_minus8);
+        data.extend_from_slice(&self.avg_frame_rate.to_be_bytes());
+        data.push(
+            (self.constant_frame_rate << 6)
+                | (self.num_temporal_layers << 3)
+                | ((self.temporal_id_nested as u8) << 2)
+                | self.length_size_minus_one
+        );
+        data.push(self.arrays.len() as u8);
+        for array in self.arrays.iter() {
+            data.push(((array.array_completeness as u8) << 7) | array.nal_unit_type);
+            data.extend_from_slice(&(array.nal_units.len() as u16).to_be_bytes());
+            for nal_unit in array.nal_units.iter() {
+                data.extend_from_slice(&(nal_unit.len() as u16).to_be_bytes());
+                data.extend_from_slice(nal_unit);
+            }
+        }
+        data
+    }
+}
+
+/// The parts of an HEVC sequence parameter set needed to describe the video track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcSequenceParameterSet {
+    pub video_parameter_set_id: u8,
+    pub max_sub_layers: u8,
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_level_idc: u8,
+    pub seq_parameter_set_id: u32,
+
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+
+    /// Size in luma samples, before the conformance window is applied.
+    pub coded_width: u32,
+    pub coded_height: u32,
+    /// Size after cropping to the conformance window (in samples, not accounting for the SAR).
+    pub width: u32,
+    pub height: u32,
+
+    pub sar_width: u32,
+    pub sar_height: u32,
+
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+
+    pub colour_description: Option<ColourDescription>,
+}
+
+impl HevcSequenceParameterSet {
+    /// Parses a SPS NAL unit, including its two byte NAL header.
+    pub fn parse(nal_unit: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let rbsp = remove_emulation_prevention(nal_unit);
+        let mut reader = BitReader::new(&rbsp);
+        let nal_unit_type = (reader.read_bits(16)? >> 9) as u8 & 0x3F;
+        if nal_unit_type != NAL_UNIT_SPS {
+            return Err(format!("NAL unit type {} is not a SPS.", nal_unit_type).into());
+        }
+
+        let video_parameter_set_id = reader.read_bits(4)? as u8;
+        let max_sub_layers_minus1 = reader.read_bits(3)? as usize;
+        // sps_temporal_id_nesting_flag
+        reader.skip_bits(1)?;
+
+        // profile_tier_level(1, sps_max_sub_layers_minus1)
+        let general_profile_space = reader.read_bits(2)? as u8;
+        let general_tier_flag = reader.read_bit()?;
+        let general_profile_idc = reader.read_bits(5)? as u8;
+        // compatibility flags, constraint flags
+        reader.skip_bits(32 + 48)?;
+        let general_level_idc = reader.read_bits(8)? as u8;
+        let mut sub_layers = Vec::with_capacity(max_sub_layers_minus1);
+        for _ in 0..max_sub_layers_minus1 {
+            // sub_layer_profile_present_flag, sub_layer_level_present_flag
+            sub_layers.push((reader.read_bit()?, reader.read_bit()?));
+        }
+        if max_sub_layers_minus1 > 0 {
+            reader.skip_bits(2 * (8 - max_sub_layers_minus1))?;
+        }
+        for (profile_present, level_present) in sub_layers {
+            if profile_present {
+                reader.skip_bits(88)?;
+            }
+            if level_present {
+                reader.skip_bits(8)?;
+            }
+        }
+
+        let seq_parameter_set_id = reader.read_ue()?;
+        let chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc > 3 {
+            return Err(format!("Invalid chroma_format_idc {}.", chroma_format_idc).into());
+        }
+        let mut separate_colour_plane = false;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = reader.read_bit()?;
+        }
+        let coded_width = reader.read_ue()?;
+        let coded_height = reader.read_ue()?;
+
+        let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+        // conformance_window_flag
+        if reader.read_bit()? {
+            crop_left = reader.read_ue()?;
+            crop_right = reader.read_ue()?;
+            crop_top = reader.read_ue()?;
+            crop_bottom = reader.read_ue()?;
+        }
+        let (sub_width_c, sub_height_c) = if separate_colour_plane {
+            (1, 1)
+        } else {
+            match chroma_format_idc {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            }
+        };
+        let crop_x = sub_width_c * (crop_left + crop_right);
+        let crop_y = sub_height_c * (crop_top + crop_bottom);
+        if crop_x >= coded_width || crop_y >= coded_height {
+            return Err("SPS conformance window exceeds the coded size.".into());
+        }
+
+        let bit_depth_luma = 8 + reader.read_ue()? as u8;
+        let bit_depth_chroma = 8 + reader.read_ue()? as u8;
+        let log2_max_pic_order_cnt_lsb = reader.read_ue()? + 4;
+
+        // sps_sub_layer_ordering_info_present_flag
+        let first_sub_layer = if reader.read_bit()? { 0 } else { max_sub_layers_minus1 };
+        for _ in first_sub_layer..=max_sub_layers_minus1 {
+            // max_dec_pic_buffering_minus1, max_num_reorder_pics, max_latency_increase_plus1
+            reader.read_ue()?;
+            reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        // coding block and transform sizes, transform hierarchy depths
+        for _ in 0..6 {
+            reader.read_ue()?;
+        }
+
+        // scaling_list_enabled_flag, sps_scaling_list_data_present_flag
+        if reader.read_bit()? && reader.read_bit()? {
+            Self::skip_scaling_list_data(&mut reader)?;
+        }
+
+        // amp_enabled_flag, sample_adaptive_offset_enabled_flag
+        reader.skip_bits(2)?;
+        // pcm_enabled_flag
+        if reader.read_bit()? {
+            // pcm bit depths, sizes
+            reader.skip_bits(8)?;
+            reader.read_ue()?;
+            reader.read_ue()?;
+            // pcm_loop_filter_disabled_flag
+            reader.skip_bits(1)?;
+        }
+
+        let num_short_term_ref_pic_sets = reader.read_ue()?;
+        let mut num_delta_pocs = Vec::with_capacity(num_short_term_ref_pic_sets as usize);
+        for index in 0..num_short_term_ref_pic_sets as usize {
+            let count = Self::skip_short_term_ref_pic_set(&mut reader, index, &num_delta_pocs)?;
+            num_delta_pocs.push(count);
+        }
+
+        // long_term_ref_pics_present_flag
+        if reader.read_bit()? {
+            let num_long_term_ref_pics = reader.read_ue()?;
+            for _ in 0..num_long_term_ref_pics {
+                // lt_ref_pic_poc_lsb_sps, used_by_curr_pic_lt_sps_flag
+                reader.skip_bits(log2_max_pic_order_cnt_lsb as usize + 1)?;
+            }
+        }
+
+        // sps_temporal_mvp_enabled_flag, strong_intra_smoothing_enabled_flag
+        reader.skip_bits(2)?;
+
+        let mut sps = Self {
+            video_parameter_set_id,
+            max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_level_idc,
+            seq_parameter_set_id,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+            coded_width,
+            coded_height,
+            width: coded_width - crop_x,
+            height: coded_height - crop_y,
+            sar_width: 1,
+            sar_height: 1,
+            num_units_in_tick: 0,
+            time_scale: 0,
+            colour_description: None,
+        };
+
+        // vui_parameters_present_flag
+        if reader.read_bit()? {
+            sps.parse_vui(&mut reader)?;
+        }
+        Ok(sps)
+    }
+
+    fn skip_scaling_list_data(reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        for size_id in 0..4 {
+            let step = if size_id == 3 { 3 } else { 1 };
+            for _ in (0..6).step_by(step) {
+                // scaling_list_pred_mode_flag
+                if !reader.read_bit()? {
+                    // scaling_list_pred_matrix_id_delta
+                    reader.read_ue()?;
+                } else {
+                    let coef_num = 64.min(1 << (4 + (size_id << 1)));
+                    if size_id > 1 {
+                        // scaling_list_dc_coef_minus8
+                        reader.read_se()?;
+                    }
+                    for _ in 0..coef_num {
+                        // scaling_list_delta_coef
+                        reader.read_se()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips st_ref_pic_set(index) and returns its NumDeltaPocs, which later sets predicted from it need.
+    fn skip_short_term_ref_pic_set(reader: &mut BitReader, index: usize, num_delta_pocs: &[u32]) -> Result<u32, Box<dyn std::error::Error>> {
+        // inter_ref_pic_set_prediction_flag
+        if index != 0 && reader.read_bit()? {
+            // delta_rps_sign, abs_delta_rps_minus1
+            reader.skip_bits(1)?;
+            reader.read_ue()?;
+            // in the SPS, a set is always predicted from the one right before it.
+            let mut count = 0;
+            for _ in 0..=num_delta_pocs[index - 1] {
+                let used_by_curr_pic = reader.read_bit()?;
+                // use_delta_flag is inferred to be 1 when absent.
+                if used_by_curr_pic || reader.read_bit()? {
+                    count += 1;
+                }
+            }
+            return Ok(count);
+        }
+
+        let num_negative_pics = reader.read_ue()?;
+        let num_positive_pics = reader.read_ue()?;
+        for _ in 0..num_negative_pics + num_positive_pics {
+            // delta_poc_minus1, used_by_curr_pic_flag
+            reader.read_ue()?;
+            reader.skip_bits(1)?;
+        }
+        Ok(num_negative_pics + num_positive_pics)
+    }
+
+    fn parse_vui(&mut self, reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        // aspect_ratio_info_present_flag
+        if reader.read_bit()? {
+            let aspect_ratio_idc = reader.read_bits(8)? as u8;
+            if aspect_ratio_idc == EXTENDED_SAR {
+                self.sar_width = reader.read_bits(16)?;
+                self.sar_height = reader.read_bits(16)?;
+            } else if (1..=16).contains(&aspect_ratio_idc) {
+                (self.sar_width, self.sar_height) = SAR_TABLE[aspect_ratio_idc as usize - 1];
+            }
+            if self.sar_width == 0 || self.sar_height == 0 {
+                (self.sar_width, self.sar_height) = (1, 1);
+            }
+        }
+
+        // overscan_info_present_flag
+        if reader.read_bit()? {
+            // overscan_appropriate_flag
+            reader.skip_bits(1)?;
+        }
+
+        // video_signal_type_present_flag
+        if reader.read_bit()? {
+            // video_format
+            reader.skip_bits(3)?;
+            let full_range = reader.read_bit()?;
+            if reader.read_bit()? {
+                self.colour_description = Some(
+                    ColourDescription {
+                        colour_primaries: reader.read_bits(8)? as u8,
+                        transfer_characteristics: reader.read_bits(8)? as u8,
+                        matrix_coefficients: reader.read_bits(8)? as u8,
+                        full_range,
+                    }
+                );
+            }
+        }
+
+        // chroma_loc_info_present_flag
+        if reader.read_bit()? {
+            reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        // neutral_chroma_indication_flag, field_seq_flag, frame_field_info_present_flag
+        reader.skip_bits(3)?;
+        // default_display_window_flag
+        if reader.read_bit()? {
+            for _ in 0..4 {
+                reader.read_ue()?;
+            }
+        }
+
+        // vui_timing_info_present_flag
+        if reader.read_bit()? {
+            self.num_units_in_tick = reader.read_bits(32)?;
+            self.time_scale = reader.read_bits(32)?;
+        }
+        Ok(())
+    }
+
+    /// Frames per second from the VUI timing info, if present.
+    /// Unlike H.264, a frame spans a single tick.
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+        Some(self.time_scale as f64 / self.num_units_in_tick as f64)
+    }
+
+    /// Width the picture should be displayed at once the SAR is applied.
+    pub fn display_width(&self) -> f64 {
+        self.width as f64 * self.sar_width as f64 / self.sar_height as f64
+    }
+}
@@ -3,4 +3,19 @@ pub mod mp4head;
 pub mod mp4frag;
 pub mod remux_context;
 pub mod parser;
-pub mod encoder;
\ No newline at end of file
+pub mod encoder;
+pub mod avc;
+pub mod aac;
+pub mod caption;
+pub mod hevc;
+pub mod av1;
+pub mod vp9;
+pub mod opus;
+pub mod ogg;
+pub mod speex;
+pub mod extract;
+pub mod mp3;
+pub mod codec_string;
+pub mod duration;
+pub mod timestamp;
+pub mod timeline;
@@ -0,0 +1,255 @@
+use crate::fmpeg::parser::{
+    Channel, Mp3Layer, Mp3Version, AUDIO_BITRATE_TABLE_L1, AUDIO_BITRATE_TABLE_L2, AUDIO_BITRATE_TABLE_L3,
+    AUDIO_SAMPLE_RATE_TABLE_M10, AUDIO_SAMPLE_RATE_TABLE_M20, AUDIO_SAMPLE_RATE_TABLE_M25,
+};
+
+/// MPEG-2 and 2.5 use lower bitrates than MPEG-1 (ISO/IEC 13818-3, 2.4.2.3).
+const AUDIO_BITRATE_TABLE_V2_L1: [u32; 16] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256, 0];
+const AUDIO_BITRATE_TABLE_V2_L2_L3: [u32; 16] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0];
+
+const MP3_SYNC_WORD: u32 = 0x07FF;
+const HEADER_SIZE: usize = 4;
+const CRC_SIZE: usize = 2;
+
+/// A decoded MPEG audio frame header (ISO/IEC 11172-3, 2.4.1.3).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mp3FrameHeader {
+    pub version: Mp3Version,
+    pub layer: Mp3Layer,
+    /// Whether a CRC follows the header; the bitstream stores the inverse.
+    pub protected: bool,
+    /// In kbit/s.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channel: Channel,
+    pub channel_extended: u8,
+}
+
+impl Mp3FrameHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() < HEADER_SIZE {
+            return Err("MP3 frame header truncated.".into());
+        }
+        let header = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        if header >> 21 != MP3_SYNC_WORD {
+            return Err("MP3 sync word mismatch!".into());
+        }
+
+        let version = Mp3Version::from(((header >> 19) & 0x03) as u8);
+        let layer = Mp3Layer::from(((header >> 17) & 0x03) as u8);
+        let bitrate_index = ((header >> 12) & 0x0F) as usize;
+        let sampling_rate_index = ((header >> 10) & 0x03) as usize;
+
+        let sample_rate = match version {
+            Mp3Version::Mp10 => AUDIO_SAMPLE_RATE_TABLE_M10[sampling_rate_index],
+            Mp3Version::Mp20 => AUDIO_SAMPLE_RATE_TABLE_M20[sampling_rate_index],
+            Mp3Version::Mp25 => AUDIO_SAMPLE_RATE_TABLE_M25[sampling_rate_index],
+            Mp3Version::Reserved => return Err("Invalid mp3 version.".into()),
+        };
+        let bitrate = match (version, layer) {
+            (_, Mp3Layer::Reserved) => return Err("Invalid mp3 layer.".into()),
+            (Mp3Version::Mp10, Mp3Layer::L1) => AUDIO_BITRATE_TABLE_L1[bitrate_index],
+            (Mp3Version::Mp10, Mp3Layer::L2) => AUDIO_BITRATE_TABLE_L2[bitrate_index],
+            (Mp3Version::Mp10, Mp3Layer::L3) => AUDIO_BITRATE_TABLE_L3[bitrate_index],
+            (_, Mp3Layer::L1) => AUDIO_BITRATE_TABLE_V2_L1[bitrate_index],
+            (_, _) => AUDIO_BITRATE_TABLE_V2_L2_L3[bitrate_index],
+        };
+        if sample_rate == 0 {
+            return Err("Invalid mp3 sample rate.".into());
+        }
+        if bitrate == 0 {
+            // index 0 is free format, whose frame length can't be known from the header.
+            return Err("Free format or invalid mp3 bitrate.".into());
+        }
+
+        let channel = Channel::from(((header >> 6) & 0x03) as u8);
+        let channel_extended = if let Channel::JointStereo = channel { ((header >> 4) & 0x03) as u8 } else { 0 };
+
+        Ok(Self {
+            version,
+            layer,
+            protected: (header >> 16) & 0x01 == 0,
+            bitrate,
+            sample_rate,
+            padding: (header >> 9) & 0x01 != 0,
+            channel,
+            channel_extended,
+        })
+    }
+
+    /// Frame size in bytes, header included.
+    pub fn frame_length(&self) -> usize {
+        let bitrate = self.bitrate as usize * 1000;
+        let sample_rate = self.sample_rate as usize;
+        let padding = self.padding as usize;
+        match self.layer {
+            // Layer I counts in 4-byte slots.
+            Mp3Layer::L1 => (12 * bitrate / sample_rate + padding) * 4,
+            Mp3Layer::L3 if self.version != Mp3Version::Mp10 => 72 * bitrate / sample_rate + padding,
+            _ => 144 * bitrate / sample_rate + padding,
+        }
+    }
+
+    pub fn samples_per_frame(&self) -> u32 {
+        samples_per_frame(self.version, self.layer)
+    }
+
+    pub fn channels(&self) -> u8 {
+        match self.channel {
+            Channel::Mono => 1,
+            _ => 2,
+        }
+    }
+
+    /// Bytes after the CRC covered by it, when they can be told from the header alone.
+    /// Layer II depends on the bit allocation tables and is not covered.
+    fn protected_size(&self) -> Option<usize> {
+        let mono = self.channels() == 1;
+        match self.layer {
+            // the side information.
+            Mp3Layer::L3 => Some(match (self.version == Mp3Version::Mp10, mono) {
+                (true, true) => 17,
+                (true, false) => 32,
+                (false, true) => 9,
+                (false, false) => 17,
+            }),
+            // 4 allocation bits per subband and channel; joint stereo shares the ones above the bound.
+            Mp3Layer::L1 => {
+                let bound = match self.channel {
+                    Channel::JointStereo => 4 * (self.channel_extended as usize + 1),
+                    _ => 32,
+                };
+                let channels = self.channels() as usize;
+                Some(4 * (channels * bound + (32 - bound)) / 8)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn samples_per_frame(version: Mp3Version, layer: Mp3Layer) -> u32 {
+    match layer {
+        Mp3Layer::L1 => 384,
+        Mp3Layer::L3 if version != Mp3Version::Mp10 => 576,
+        _ => 1152,
+    }
+}
+
+/// CRC-16 of MPEG audio: polynomial 0x8005, initial value 0xFFFF, no reflection.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// A complete frame, header included.
+pub struct Mp3Frame<'a> {
+    pub header: Mp3FrameHeader,
+    pub data: &'a [u8],
+}
+
+impl Mp3Frame<'_> {
+    /// `None` when the frame has no CRC, or it covers Layer II allocation data.
+    pub fn check_crc(&self) -> Option<bool> {
+        if !self.header.protected {
+            return None;
+        }
+        let protected_size = self.header.protected_size()?;
+        let start = HEADER_SIZE + CRC_SIZE;
+        if self.data.len() < start + protected_size {
+            return Some(false);
+        }
+        // the last two header bytes, then the protected bits after the CRC.
+        let mut covered = self.data[2..HEADER_SIZE].to_vec();
+        covered.extend_from_slice(&self.data[start..start + protected_size]);
+        let expected = u16::from_be_bytes([self.data[HEADER_SIZE], self.data[HEADER_SIZE + 1]]);
+        Some(crc16(&covered) == expected)
+    }
+}
+
+/// Iterates over the complete frames of a buffer.
+/// Bytes that don't start a valid header are skipped; a trailing partial frame is left in `remainder`.
+pub struct Mp3FrameIter<'a> {
+    data: &'a [u8],
+    offset: usize,
+    skipped: usize,
+}
+
+impl<'a> Mp3FrameIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0, skipped: 0 }
+    }
+
+    /// The bytes not yet returned, i.e. the start of a frame that continues in the next tag.
+    pub fn remainder(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+
+    /// Number of bytes dropped while looking for a sync word.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl<'a> Iterator for Mp3FrameIter<'a> {
+    type Item = Mp3Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.data.len() - self.offset >= HEADER_SIZE {
+            let rest = &self.data[self.offset..];
+            let header = match Mp3FrameHeader::parse(rest) {
+                Ok(header) => header,
+                Err(_) => {
+                    self.offset += 1;
+                    self.skipped += 1;
+                    continue;
+                }
+            };
+            let frame_length = header.frame_length();
+            if rest.len() < frame_length {
+                return None;
+            }
+            self.offset += frame_length;
+            return Some(Mp3Frame { header, data: &rest[..frame_length] });
+        }
+        None
+    }
+}
+
+/// Joins frames split across tags: carries the partial frame at the end of a tag over to the next one.
+#[derive(Default)]
+pub struct Mp3FrameAssembler {
+    pending: Vec<u8>,
+}
+
+impl Mp3FrameAssembler {
+    pub fn new() -> Self {
+        Self { pending: vec![] }
+    }
+
+    /// Whether the previous tag ended inside a frame.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Appends a tag body and returns the frames completed so far, with their headers.
+    pub fn push(&mut self, body: &[u8]) -> Vec<(Mp3FrameHeader, Vec<u8>, Option<bool>)> {
+        self.pending.extend_from_slice(body);
+        let mut iter = Mp3FrameIter::new(&self.pending);
+        let frames = iter.by_ref()
+            .map(|frame| (frame.header, frame.data.to_vec(), frame.check_crc()))
+            .collect();
+        self.pending = iter.remainder().to_vec();
+        frames
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
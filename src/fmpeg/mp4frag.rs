@@ -119,8 +119,8 @@ impl TrackFragmentBoxBuilder {
         self
     }
 
-    pub fn with_media_decode_time(mut self, base_media_decode_time: u32) -> TrackFragmentBoxBuilder {
-        self.track_fragment_decode_time_box.base_media_decode_time = base_media_decode_time;
+    pub fn with_media_decode_time(mut self, base_media_decode_time: u64) -> TrackFragmentBoxBuilder {
+        self.track_fragment_decode_time_box = TrackFragmentDecodeTimeBox::new(base_media_decode_time);
         self
     }
 
@@ -230,15 +230,16 @@ pub struct TrackFragmentDecodeTimeBox {
     pub version: u8,
     pub flags: U24,
 
-    pub base_media_decode_time: u32,
+    pub base_media_decode_time: u64,
 }
 
 impl TrackFragmentDecodeTimeBox {
-    pub fn new(base_media_decode_time: u32) -> TrackFragmentDecodeTimeBox {
+    /// Version 1, with a 64-bit time, once the time no longer fits in 32 bits.
+    pub fn new(base_media_decode_time: u64) -> TrackFragmentDecodeTimeBox {
         TrackFragmentDecodeTimeBox {
             size: 0,
             box_type: ['t', 'f', 'd', 't'],
-            version: 0,
+            version: if base_media_decode_time > u32::MAX as u64 { 1 } else { 0 },
             flags: U24::from(0),
             base_media_decode_time
         }
@@ -256,13 +257,17 @@ impl ISerializable for TrackFragmentDecodeTimeBox {
         result.push(self.version);
         result.extend_from_slice(&self.flags.serialize());
 
-        result.extend_from_slice(&self.base_media_decode_time.to_be_bytes());
-        assert_eq!(result.len(), 16);
+        if self.version == 1 {
+            result.extend_from_slice(&self.base_media_decode_time.to_be_bytes());
+        } else {
+            result.extend_from_slice(&(self.base_media_decode_time as u32).to_be_bytes());
+        }
+        assert_eq!(result.len() as u32, self.size());
         result
     }
 
     fn size(&self) -> u32 {
-        16
+        if self.version == 1 { 20 } else { 16 }
     }
 }
 
@@ -427,6 +432,7 @@ impl SampleFlagBuilder {
 
 /// this is just a simple implementation
 /// which only supports one sample.
+/// Version 1 is used when the composition time offset is negative, as only it is signed.
 #[derive(Debug)]
 pub struct TrackRunBox {
     pub size: u32,
@@ -441,7 +447,7 @@ pub struct TrackRunBox {
     pub sample_size: u32,
     pub sample_flags: u16,
     pub reserved: u16,
-    pub sample_composition_time_offset: u32,
+    pub sample_composition_time_offset: i32,
 }
 
 impl TrackRunBox {
@@ -496,7 +502,7 @@ pub struct TrackRunBoxBuilder {
     sample_duration: u32,
     sample_size: u32,
     sample_flags: u16,
-    sample_composition_time_offset: u32,
+    sample_composition_time_offset: i32,
     data_offset: u32,
 
     flag: u32,
@@ -534,7 +540,7 @@ impl TrackRunBoxBuilder {
         self
     }
 
-    pub fn with_sample_composition_time_offset(mut self, offset: u32) -> TrackRunBoxBuilder {
+    pub fn with_sample_composition_time_offset(mut self, offset: i32) -> TrackRunBoxBuilder {
         self.sample_composition_time_offset = offset;
         self.flag |= 0x000800;
         self
@@ -549,7 +555,7 @@ impl TrackRunBoxBuilder {
         TrackRunBox {
             size: 0,
             box_type: ['t', 'r', 'u', 'n'],
-            version: 0,
+            version: if self.sample_composition_time_offset < 0 { 1 } else { 0 },
             flags: U24::from(self.flag & 0x00FFFFFF),
 
             sample_count: 1,
@@ -600,4 +606,39 @@ impl MovieDataBox {
         self.data.extend(data);
         self
     }
-}
\ No newline at end of file
+}
+
+/// One WebVTT sample (ISO/IEC 14496-30): a `vttc` cue with its `payl` text, or `vtte` when nothing is shown.
+pub enum WebVttSample {
+    Cue(String),
+    Empty,
+}
+
+impl ISerializable for WebVttSample {
+    fn serialize(&mut self) -> Vec<u8> {
+        let size = self.size();
+
+        let mut result: Vec<u8> = Vec::new();
+        result.extend_from_slice(&size.to_be_bytes());
+        match self {
+            WebVttSample::Cue(text) => {
+                result.extend_from_slice(b"vttc");
+                result.extend_from_slice(&(8 + text.len() as u32).to_be_bytes());
+                result.extend_from_slice(b"payl");
+                result.extend_from_slice(text.as_bytes());
+            }
+            WebVttSample::Empty => {
+                result.extend_from_slice(b"vtte");
+            }
+        }
+        assert_eq!(result.len(), size as usize);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        match self {
+            WebVttSample::Cue(text) => 16 + text.len() as u32,
+            WebVttSample::Empty => 8,
+        }
+    }
+}
@@ -360,6 +360,7 @@ impl MovieBoxBuilder {
             tracks: self.tracks,
             movie_extend_box: MovieExtendBox::new(),
         };
+        box_instance.movie_extend_box = MovieExtendBox::with_tracks(&box_instance.tracks);
         box_instance.size = box_instance.size();
         assert_ne!(box_instance.size, 0);
         // should not have zero size!
@@ -755,6 +756,7 @@ pub struct TrackBox {
     pub box_type: [char; 4],
 
     pub track_header_box: TrackHeaderBox,
+    pub edit_box: Option<EditBox>,
     pub media_box: MediaBox,
 }
 
@@ -768,6 +770,9 @@ impl ISerializable for TrackBox {
         result.extend_from_slice(&self.box_type.map(|c| c as u8));
 
         result.extend_from_slice(&self.track_header_box.serialize());
+        if let Some(edit_box) = self.edit_box.as_mut() {
+            result.extend_from_slice(&edit_box.serialize());
+        }
         result.extend_from_slice(&self.media_box.serialize());
 
         assert_eq!(result.len(), self.size() as usize);
@@ -775,7 +780,7 @@ impl ISerializable for TrackBox {
     }
 
     fn size(&self) -> u32 {
-        8 + self.track_header_box.size() + self.media_box.size()
+        8 + self.track_header_box.size() + self.edit_box.as_ref().map_or(0, |edit_box| edit_box.size()) + self.media_box.size()
     }
 }
 
@@ -785,9 +790,117 @@ impl TrackBox {
             size: 0,
             box_type: ['t', 'r', 'a', 'k'],
             track_header_box,
+            edit_box: None,
             media_box
         }
     }
+
+    pub fn with_edit_box(mut self, edit_box: EditBox) -> Self {
+        self.edit_box = Some(edit_box);
+        self
+    }
+
+    #[inline]
+    pub fn track_id(&self) -> u32 {
+        match &self.track_header_box {
+            TrackHeaderBox::V0(tkhd) => tkhd.track_id,
+            TrackHeaderBox::V1(tkhd) => tkhd.track_id,
+        }
+    }
+}
+
+/// Holds the edit list of a track.
+#[derive(Debug)]
+pub struct EditBox {
+    pub size: u32,
+    pub box_type: [char; 4],
+
+    pub edit_list_box: EditListBox,
+}
+
+impl EditBox {
+    pub fn new(edit_list_box: EditListBox) -> Self {
+        Self {
+            size: 0,
+            box_type: ['e', 'd', 't', 's'],
+            edit_list_box,
+        }
+    }
+}
+
+impl ISerializable for EditBox {
+    #[inline]
+    fn serialize(&mut self) -> Vec<u8> {
+        self.size = self.size();
+
+        let mut result = vec![];
+        result.extend_from_slice(&self.size.to_be_bytes());
+        result.extend_from_slice(&self.box_type.map(|c| c as u8));
+        result.extend_from_slice(&self.edit_list_box.serialize());
+        assert_eq!(result.len(), self.size() as usize);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        8 + self.edit_list_box.size()
+    }
+}
+
+/// An edit list with a single edit, playing the media from `media_time` at normal rate.
+/// `segment_duration` is in the movie timescale, `media_time` in the track's.
+#[derive(Debug)]
+pub struct EditListBox {
+    pub size: u32,
+    pub box_type: [char; 4],
+    pub version: u8,
+    pub flags: U24,
+
+    pub segment_duration: u32,
+    pub media_time: i32,
+    pub media_rate_integer: i16,
+    pub media_rate_fraction: i16,
+}
+
+impl EditListBox {
+    pub fn new(segment_duration: u32, media_time: i32) -> Self {
+        Self {
+            size: 0,
+            box_type: ['e', 'l', 's', 't'],
+            version: 0,
+            flags: U24::from(0),
+
+            segment_duration,
+            media_time,
+            media_rate_integer: 1,
+            media_rate_fraction: 0,
+        }
+    }
+}
+
+impl ISerializable for EditListBox {
+    #[inline]
+    fn serialize(&mut self) -> Vec<u8> {
+        self.size = self.size();
+
+        let mut result = vec![];
+        result.extend_from_slice(&self.size.to_be_bytes());
+        result.extend_from_slice(&self.box_type.map(|c| c as u8));
+        result.extend_from_slice(&self.version.to_be_bytes());
+        result.extend_from_slice(&self.flags.serialize());
+
+        // entry_count
+        result.extend_from_slice(&1u32.to_be_bytes());
+        result.extend_from_slice(&self.segment_duration.to_be_bytes());
+        result.extend_from_slice(&self.media_time.to_be_bytes());
+        result.extend_from_slice(&self.media_rate_integer.to_be_bytes());
+        result.extend_from_slice(&self.media_rate_fraction.to_be_bytes());
+        assert_eq!(result.len(), 28);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        28
+    }
 }
 
 #[derive(Debug)]
@@ -1280,6 +1393,7 @@ impl ISerializable for HandlerBox {
 pub enum HandlerType {
     Video,
     Audio,
+    Text,
 }
 
 impl HandlerType {
@@ -1291,6 +1405,9 @@ impl HandlerType {
             HandlerType::Audio => {
                 HandlerBox::new(['s', 'o', 'u', 'n'], "SoundHandler\x00".to_string())
             }
+            HandlerType::Text => {
+                HandlerBox::new(['t', 'e', 'x', 't'], "Text Handler\x00".to_string())
+            }
         }
     }
 }
@@ -1343,6 +1460,7 @@ impl ISerializable for MediaInfoBox {
 pub enum XMediaHandlerBox {
     Video(VideoMediaHandlerBox),
     Audio(AudioMediaHandlerBox),
+    Null(NullMediaHandlerBox),
 }
 
 impl ISerializable for XMediaHandlerBox {
@@ -1351,6 +1469,7 @@ impl ISerializable for XMediaHandlerBox {
         match self {
             XMediaHandlerBox::Video(video) => video.serialize(),
             XMediaHandlerBox::Audio(audio) => audio.serialize(),
+            XMediaHandlerBox::Null(null) => null.serialize(),
         }
     }
 
@@ -1358,6 +1477,7 @@ impl ISerializable for XMediaHandlerBox {
         match self {
             XMediaHandlerBox::Video(video) => video.size(),
             XMediaHandlerBox::Audio(audio) => audio.size(),
+            XMediaHandlerBox::Null(null) => null.size(),
         }
     }
 }
@@ -1415,6 +1535,32 @@ impl ISerializable for AudioMediaHandlerBox {
     }
  }
 
+/// Media header of tracks without a dedicated one, e.g. WebVTT text.
+#[derive(Debug, Default)]
+pub struct NullMediaHandlerBox;
+
+impl NullMediaHandlerBox {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl ISerializable for NullMediaHandlerBox {
+    #[inline]
+    fn serialize(&mut self) -> Vec<u8> {
+        let data: [u8; 12] = [
+            0x00, 0x00, 0x00, 0x0C, // size = 12
+            0x6E, 0x6D, 0x68, 0x64, // "nmhd"
+            0x00, 0x00, 0x00, 0x00, // version = 0, flags = 0
+        ];
+        data.to_vec()
+    }
+
+    fn size(&self) -> u32 {
+        12
+    }
+}
+
 #[derive(Debug)]
 pub struct DataInformationBox;
 
@@ -1609,7 +1755,10 @@ impl SampleDescriptionTableBoxBuilder {
 pub enum SubSampleDescriptionTableBox {
     Mp4a(Mp4aDescriptionBox),
     Mp3(Mp3DescriptionBox),
+    Audio(AudioSampleEntryBox),
     Avc1(Avc1DescriptionBox),
+    Visual(VisualSampleEntryBox),
+    Wvtt(WvttDescriptionBox),
 }
 
 impl ISerializable for SubSampleDescriptionTableBox {
@@ -1618,7 +1767,10 @@ impl ISerializable for SubSampleDescriptionTableBox {
         match self {
             SubSampleDescriptionTableBox::Mp4a(mp4a) => mp4a.serialize(),
             SubSampleDescriptionTableBox::Mp3(mp3) => mp3.serialize(),
+            SubSampleDescriptionTableBox::Audio(audio) => audio.serialize(),
             SubSampleDescriptionTableBox::Avc1(avc1) => avc1.serialize(),
+            SubSampleDescriptionTableBox::Visual(visual) => visual.serialize(),
+            SubSampleDescriptionTableBox::Wvtt(wvtt) => wvtt.serialize(),
         }
     }
 
@@ -1626,7 +1778,10 @@ impl ISerializable for SubSampleDescriptionTableBox {
         match self {
             SubSampleDescriptionTableBox::Mp4a(mp4a) => mp4a.size(),
             SubSampleDescriptionTableBox::Mp3(mp3) => mp3.size(),
+            SubSampleDescriptionTableBox::Audio(audio) => audio.size(),
             SubSampleDescriptionTableBox::Avc1(avc1) => avc1.size(),
+            SubSampleDescriptionTableBox::Visual(visual) => visual.size(),
+            SubSampleDescriptionTableBox::Wvtt(wvtt) => wvtt.size(),
         }
     }
 }
@@ -2319,6 +2474,123 @@ impl Mp3DescriptionBoxBuilder {
     }
 }
 
+/// Audio sample entry for codecs other than AAC, e.g. `Opus` with a `dOps` box.
+/// The configuration box payload, if any, is carried verbatim.
+#[derive(Debug)]
+pub struct AudioSampleEntryBox {
+    pub entry: Mp3DescriptionBox,
+    pub config_box: Option<([char; 4], Vec<u8>)>,
+}
+
+impl AudioSampleEntryBox {
+    pub fn new(box_type: [char; 4], sample_rate: f32, num_audio_channels: u16, config_box: Option<([char; 4], Vec<u8>)>) -> Self {
+        let mut entry = Mp3DescriptionBox::new(sample_rate, num_audio_channels);
+        entry.box_type = box_type;
+        Self {
+            entry,
+            config_box,
+        }
+    }
+}
+
+impl ISerializable for AudioSampleEntryBox {
+    fn serialize(&mut self) -> Vec<u8> {
+        let size = self.size();
+        let mut result = self.entry.serialize();
+        result[0..4].copy_from_slice(&size.to_be_bytes());
+        if let Some((box_type, ref config)) = self.config_box {
+            result.extend_from_slice(&(config.len() as u32 + 8).to_be_bytes());
+            result.extend_from_slice(&box_type.map(|c| c as u8));
+            result.extend_from_slice(config);
+        }
+        result
+    }
+
+    fn size(&self) -> u32 {
+        match self.config_box {
+            Some((_, ref config)) => 36 + 8 + config.len() as u32,
+            None => 36
+        }
+    }
+}
+
+pub struct AudioSampleEntryBoxBuilder {
+    box_type: [char; 4],
+    sample_rate: f32,
+    num_audio_channels: u16,
+    sample_size: u16,
+    config_box: Option<([char; 4], Vec<u8>)>,
+}
+
+impl AudioSampleEntryBoxBuilder {
+    pub fn new(box_type: [char; 4]) -> Self {
+        Self {
+            box_type,
+            sample_rate: 0.0,
+            num_audio_channels: 0,
+            sample_size: 16,
+            config_box: None,
+        }
+    }
+
+    pub fn sample_rate(mut self, sample_rate: f32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn num_audio_channels(mut self, num_audio_channels: u16) -> Self {
+        self.num_audio_channels = num_audio_channels;
+        self
+    }
+
+    pub fn sample_size(mut self, sample_size: u16) -> Self {
+        self.sample_size = sample_size;
+        self
+    }
+
+    pub fn config_box(mut self, config_box_type: [char; 4], config: Vec<u8>) -> Self {
+        self.config_box = Some((config_box_type, config));
+        self
+    }
+
+    pub fn build(self) -> AudioSampleEntryBox {
+        let mut entry = AudioSampleEntryBox::new(self.box_type, self.sample_rate, self.num_audio_channels, self.config_box);
+        entry.entry.sample_size = self.sample_size;
+        entry
+    }
+}
+
+/// Payload of an `esds` box, version and flags included (ISO/IEC 14496-1, 7.2.6.5).
+/// MP3 is identified by the objectTypeIndication alone and has no decoder specific info.
+pub fn es_descriptor_payload(object_type_indication: u8, decoder_specific_info: &[u8]) -> Vec<u8> {
+    let mut decoder_config = vec![
+        object_type_indication,
+        0x15, // stream type: Audio
+    ];
+    // bufferSizeDB, maxBitrate, avgBitrate
+    decoder_config.extend_from_slice(&[0u8; 11]);
+    if !decoder_specific_info.is_empty() {
+        decoder_config.push(0x05);
+        decoder_config.push(decoder_specific_info.len() as u8);
+        decoder_config.extend_from_slice(decoder_specific_info);
+    }
+
+    let mut es = vec![
+        0x00, 0x01, // es id
+        0x00, // stream priority
+        0x04,
+        decoder_config.len() as u8,
+    ];
+    es.extend_from_slice(&decoder_config);
+    es.extend_from_slice(&aac_utils::GA_SPEC_CONF);
+
+    let mut result = vec![0u8; 4];
+    result.push(0x03);
+    result.push(es.len() as u8);
+    result.extend_from_slice(&es);
+    result
+}
+
 pub mod avc1_utils {
     use crate::fmpeg::mp4head::ISerializable;
 
@@ -2478,6 +2750,136 @@ impl Avc1DescriptionBoxBuilder {
     }
 }
 
+/// Visual sample entry for codecs other than AVC, e.g. `hvc1`/`hev1` with an `hvcC` box.
+/// The configuration box payload is carried verbatim.
+#[derive(Debug)]
+pub struct VisualSampleEntryBox {
+    pub entry: Avc1DescriptionBox,
+    pub config_box_type: [char; 4],
+    pub config: Vec<u8>,
+}
+
+impl VisualSampleEntryBox {
+    pub fn new(box_type: [char; 4], width: u16, height: u16, config_box_type: [char; 4], config: Vec<u8>) -> Self {
+        let mut entry = Avc1DescriptionBox::new(width, height, AvcCBoxLike(vec![]));
+        entry.box_type = box_type;
+        Self {
+            entry,
+            config_box_type,
+            config,
+        }
+    }
+}
+
+impl ISerializable for VisualSampleEntryBox {
+    fn serialize(&mut self) -> Vec<u8> {
+        let size = self.size();
+        let mut result = self.entry.serialize();
+        // drop the empty avcC box and patch the size.
+        result.truncate(86);
+        result[0..4].copy_from_slice(&size.to_be_bytes());
+        result.extend_from_slice(&(self.config.len() as u32 + 8).to_be_bytes());
+        result.extend_from_slice(&self.config_box_type.map(|c| c as u8));
+        result.extend_from_slice(&self.config);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        86 + 8 + self.config.len() as u32
+    }
+}
+
+pub struct VisualSampleEntryBoxBuilder {
+    box_type: [char; 4],
+    width: u16,
+    height: u16,
+    config_box_type: [char; 4],
+    config: Vec<u8>,
+}
+
+impl VisualSampleEntryBoxBuilder {
+    pub fn new(box_type: [char; 4]) -> Self {
+        Self {
+            box_type,
+            width: 0,
+            height: 0,
+            config_box_type: [' '; 4],
+            config: vec![],
+        }
+    }
+
+    pub fn config_box(mut self, config_box_type: [char; 4], config: Vec<u8>) -> Self {
+        self.config_box_type = config_box_type;
+        self.config = config;
+        self
+    }
+
+    pub fn set_width(mut self, width: u16) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn set_height(mut self, height: u16) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn build(self) -> VisualSampleEntryBox {
+        VisualSampleEntryBox::new(self.box_type, self.width, self.height, self.config_box_type, self.config)
+    }
+}
+
+/// WebVTT sample entry (ISO/IEC 14496-30); the `vttC` config only holds the file header.
+#[derive(Debug)]
+pub struct WvttDescriptionBox {
+    pub size: u32,
+    pub box_type: [char; 4],
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+    pub config: String,
+}
+
+impl WvttDescriptionBox {
+    pub fn new() -> Self {
+        Self {
+            size: 0,
+            box_type: ['w', 'v', 't', 't'],
+            reserved: [0; 6],
+            data_reference_index: 1,
+            config: "WEBVTT".to_string(),
+        }
+    }
+}
+
+impl Default for WvttDescriptionBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ISerializable for WvttDescriptionBox {
+    #[inline]
+    fn serialize(&mut self) -> Vec<u8> {
+        self.size = self.size();
+
+        let mut result = vec![];
+        result.extend_from_slice(&self.size.to_be_bytes());
+        result.extend_from_slice(&self.box_type.map(|c| c as u8));
+        result.extend_from_slice(&self.reserved);
+        result.extend_from_slice(&self.data_reference_index.to_be_bytes());
+
+        result.extend_from_slice(&(8 + self.config.len() as u32).to_be_bytes());
+        result.extend_from_slice(b"vttC");
+        result.extend_from_slice(self.config.as_bytes());
+        assert_eq!(result.len(), self.size as usize);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        16 + 8 + self.config.len() as u32
+    }
+}
+
 #[derive(Debug)]
 pub struct TimeToSampleBox;
 
@@ -2606,6 +3008,17 @@ impl MovieExtendBox {
     }
 }
 
+impl MovieExtendBox {
+    /// One `trex` per track of the movie.
+    pub fn with_tracks(tracks: &[TrackBox]) -> Self {
+        Self {
+            size: 8,
+            box_type: ['m', 'v', 'e', 'x'],
+            track_extend_boxes: tracks.iter().map(|track| TrackExtendsBox::new(track.track_id())).collect(),
+        }
+    }
+}
+
 impl ISerializable for MovieExtendBox {
     fn serialize(&mut self) -> Vec<u8> {
         self.size = self.size();
@@ -0,0 +1,132 @@
+/// Body size at which a page is closed, like libogg does.
+const PAGE_BODY_TARGET: usize = 4096;
+const MAX_SEGMENTS: usize = 255;
+
+const HEADER_TYPE_CONTINUED: u8 = 0x01;
+const HEADER_TYPE_BOS: u8 = 0x02;
+const HEADER_TYPE_EOS: u8 = 0x04;
+
+/// Granule position of a page on which no packet ends.
+const NO_GRANULE: u64 = u64::MAX;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+/// The CRC of an Ogg page: polynomial 0x04C11DB7, no reflection, zero initial value (RFC 3533, 6).
+pub fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |crc, byte| (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize])
+}
+
+/// Packs packets of one logical stream into Ogg pages (RFC 3533).
+/// Packets are buffered until a page is full or `flush` is called.
+pub struct OggWriter {
+    serial: u32,
+    sequence: u32,
+    bos_written: bool,
+    // lacing value of every buffered segment, with the granule position of the packet it ends.
+    segments: Vec<(u8, Option<u64>)>,
+    body: Vec<u8>,
+    continued: bool,
+}
+
+impl OggWriter {
+    pub fn new(serial: u32) -> Self {
+        Self {
+            serial,
+            sequence: 0,
+            bos_written: false,
+            segments: vec![],
+            body: vec![],
+            continued: false,
+        }
+    }
+
+    /// Queues a packet ending at `granule_position` and returns the pages completed by it.
+    pub fn write_packet(&mut self, packet: &[u8], granule_position: u64) -> Vec<u8> {
+        let full_segments = packet.len() / 255;
+        for _ in 0..full_segments {
+            self.segments.push((255, None));
+        }
+        // a packet of a multiple of 255 bytes ends with a zero lacing value.
+        self.segments.push(((packet.len() % 255) as u8, Some(granule_position)));
+        self.body.extend_from_slice(packet);
+
+        let mut pages = vec![];
+        while self.segments.len() >= MAX_SEGMENTS || self.body.len() >= PAGE_BODY_TARGET {
+            pages.append(&mut self.page_out(false));
+        }
+        pages
+    }
+
+    /// Writes out everything buffered, so that the next packet starts a new page.
+    pub fn flush(&mut self) -> Vec<u8> {
+        let mut pages = vec![];
+        while !self.segments.is_empty() {
+            pages.append(&mut self.page_out(false));
+        }
+        pages
+    }
+
+    /// Flushes and marks the last page as the end of the stream.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut pages = vec![];
+        while self.segments.len() > MAX_SEGMENTS {
+            pages.append(&mut self.page_out(false));
+        }
+        pages.append(&mut self.page_out(true));
+        pages
+    }
+
+    fn page_out(&mut self, end_of_stream: bool) -> Vec<u8> {
+        let count = self.segments.len().min(MAX_SEGMENTS);
+        let segments: Vec<(u8, Option<u64>)> = self.segments.drain(..count).collect();
+        let body_size = segments.iter().map(|(lacing, _)| *lacing as usize).sum::<usize>();
+        let body: Vec<u8> = self.body.drain(..body_size).collect();
+        let granule_position = segments.iter().rev().find_map(|(_, granule)| *granule).unwrap_or(NO_GRANULE);
+
+        let mut header_type = 0;
+        if self.continued {
+            header_type |= HEADER_TYPE_CONTINUED;
+        }
+        if !self.bos_written {
+            header_type |= HEADER_TYPE_BOS;
+            self.bos_written = true;
+        }
+        if end_of_stream {
+            header_type |= HEADER_TYPE_EOS;
+        }
+        self.continued = segments.last().is_some_and(|(_, granule)| granule.is_none());
+
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&granule_position.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        // the CRC is computed with its own field zeroed.
+        page.extend_from_slice(&[0; 4]);
+        page.push(segments.len() as u8);
+        page.extend(segments.iter().map(|(lacing, _)| *lacing));
+        page.extend_from_slice(&body);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        self.sequence += 1;
+        page
+    }
+}
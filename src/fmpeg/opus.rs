@@ -0,0 +1,104 @@
+/// Opus always decodes at 48 kHz, whatever the input rate was.
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// Longest packet allowed by RFC 6716, in 48 kHz samples.
+const MAX_PACKET_DURATION: u32 = 5760;
+
+/// The Opus identification header sent as the sequence start (RFC 7845, 5.1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpusHead {
+    pub version: u8,
+    pub channel_count: u8,
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+    pub output_gain: i16,
+    pub channel_mapping_family: u8,
+    /// Only present for mapping families other than 0.
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() < 19 || &data[..8] != b"OpusHead" {
+            return Err("Invalid OpusHead.".into());
+        }
+        let version = data[8];
+        // only the major version is incompatible.
+        if version >> 4 != 0 {
+            return Err(format!("Unsupported OpusHead version {}.", version).into());
+        }
+        let channel_count = data[9];
+        if channel_count == 0 {
+            return Err("OpusHead has no channels.".into());
+        }
+
+        let mut head = Self {
+            version,
+            channel_count,
+            pre_skip: u16::from_le_bytes([data[10], data[11]]),
+            input_sample_rate: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+            output_gain: i16::from_le_bytes([data[16], data[17]]),
+            channel_mapping_family: data[18],
+            stream_count: 1,
+            coupled_count: if channel_count > 1 { 1 } else { 0 },
+            channel_mapping: vec![],
+        };
+        if head.channel_mapping_family != 0 {
+            if data.len() < 21 + channel_count as usize {
+                return Err("OpusHead channel mapping table truncated.".into());
+            }
+            head.stream_count = data[19];
+            head.coupled_count = data[20];
+            head.channel_mapping = data[21..21 + channel_count as usize].to_vec();
+        } else if channel_count > 2 {
+            return Err("OpusHead mapping family 0 allows at most 2 channels.".into());
+        }
+        Ok(head)
+    }
+
+    /// Content of the `dOps` box (Encapsulation of Opus in ISO-BMFF, 4.3.2), which is big endian unlike OpusHead.
+    pub fn to_dops(&self) -> Vec<u8> {
+        let mut data = vec![0, self.channel_count];
+        data.extend_from_slice(&self.pre_skip.to_be_bytes());
+        data.extend_from_slice(&self.input_sample_rate.to_be_bytes());
+        data.extend_from_slice(&self.output_gain.to_be_bytes());
+        data.push(self.channel_mapping_family);
+        if self.channel_mapping_family != 0 {
+            data.push(self.stream_count);
+            data.push(self.coupled_count);
+            data.extend_from_slice(&self.channel_mapping);
+        }
+        data
+    }
+}
+
+/// Duration of one frame for the TOC configuration, in 48 kHz samples (RFC 6716, 3.1).
+fn frame_duration(config: u8) -> u32 {
+    match config {
+        // SILK: 10, 20, 40, 60 ms
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        // Hybrid: 10, 20 ms
+        12..=15 => [480, 960][(config % 2) as usize],
+        // CELT: 2.5, 5, 10, 20 ms
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    }
+}
+
+/// Duration of a packet from its TOC byte, in 48 kHz samples.
+/// `None` if the packet is empty, truncated or longer than 120 ms.
+pub fn packet_duration(packet: &[u8]) -> Option<u32> {
+    let toc = *packet.first()?;
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        // code 3 packets carry the frame count in the next byte.
+        _ => (*packet.get(1)? & 0x3F) as u32,
+    };
+    let duration = frames * frame_duration(toc >> 3);
+    if duration == 0 || duration > MAX_PACKET_DURATION {
+        return None;
+    }
+    Some(duration)
+}
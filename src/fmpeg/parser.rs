@@ -1,72 +1,33 @@
 use std::collections::VecDeque;
 use crate::flv::header::{AudioTagHeader, TagHeader, VideoTagHeader};
 use crate::flv::tag::{NormalTagBody, Tag, TagBody};
-use crate::fmpeg::remux_context::TIME_SCALE;
-use crate::io;
-
-#[inline]
-pub fn parse_timescale(timestamp_ms: u32) -> u32 {
-
-    /*if TIME_SCALE == 1000 {
-        timestamp_ms
-    } else {
-        // this may lead to overflow.
-        timestamp_ms * TIME_SCALE / 1000
-    }*/
-
-    // Note: no more parse_timescale()
-    // it may lead to accuracy issue.
-    parse_timescale_accurate(timestamp_ms as f32)
-}
-
-#[inline]
-fn parse_timescale_accurate(timestamp_ms: f32) -> u32 {
-    if TIME_SCALE == 1000 {
-        timestamp_ms as u32
-    } else {
-        (timestamp_ms * TIME_SCALE as f32 / 1000.0) as u32
-    }
-}
-
-#[inline]
-pub fn parse_mp3_timescale(sample_rate: u32, mp3version: Mp3Version) -> u32 {
-    // todo: test this.
-    match mp3version {
-        Mp3Version::Mp25 => {
-            parse_timescale_accurate(576000.0 / sample_rate as f32)
-        }
-        Mp3Version::Mp20 => {
-            parse_timescale_accurate(576000.0 / sample_rate as f32)
-        }
-        Mp3Version::Mp10 => {
-            parse_timescale_accurate(1152000.0 / sample_rate as f32)
-        }
-        Mp3Version::Reserved => {
-            panic!("Invalid mp3 version.");
-        }
-    }
-}
-
-#[inline]
-pub fn parse_aac_timescale(sample_rate: u32) -> u32 {
-    // todo: test this.
-    // this may be incorrect.
-    parse_timescale_accurate((1024.0 * 1000.0) / sample_rate as f32)
-}
-
-#[inline]
-pub fn parse_avc_timescale(fps: f32) -> u32 {
-    parse_timescale_accurate(1000.0 / fps)
-}
+use crate::fmpeg::aac::{self, AdtsFrame, AdtsHeader, AudioSpecificConfig};
+use crate::fmpeg::extract::ExtractedCodec;
+use crate::fmpeg::mp3::Mp3FrameHeader;
+use crate::fmpeg::opus::OpusHead;
 
 pub enum AudioParseResult {
     AacRaw(VecDeque<u8>),
+    /// Raw AAC tags that carried ADTS frames, stripped of their headers.
+    AacAdts(Vec<AdtsFrame>),
     AacSequenceHeader(AacSequenceHeader),
-    Mp3(Mp3ParseResult)
+    Mp3(Mp3ParseResult),
+    /// An MP3 tag that doesn't start with a frame header, i.e. the rest of a frame split across tags.
+    Mp3Partial(Vec<u8>),
+    OpusHead(OpusHead),
+    OpusRaw(VecDeque<u8>),
+    Pcm(PcmParseResult),
+    /// Enhanced FLV packets carrying no samples, e.g. a sequence end, and codecs MP4 can't carry.
+    Ignored,
 }
 
 pub enum VideoParseResult {
     Avc1(Avc1ParseResult),
+    Hevc(HevcParseResult),
+    Av1(Av1ParseResult),
+    Vp9(Vp9ParseResult),
+    /// Enhanced FLV packets carrying no samples, e.g. HDR metadata.
+    Ignored,
 }
 
 pub enum Avc1ParseResult {
@@ -75,8 +36,19 @@ pub enum Avc1ParseResult {
     AvcEndOfSequence
 }
 
+/// HEVC tags are laid out like AVC ones, both legacy (codec id 12) and Enhanced FLV (`hvc1`).
+pub type HevcParseResult = Avc1ParseResult;
+
+/// AV1 samples are OBUs, but the packet types are shared with AVC.
+pub type Av1ParseResult = Avc1ParseResult;
+
+/// VP9 samples are whole frames (or superframes); the packet types are shared with AVC.
+pub type Vp9ParseResult = Avc1ParseResult;
+
 pub struct AvcNalu {
     pub keyframe_type: KeyframeType,
+    /// Presentation time minus decode time, in milliseconds.
+    pub composition_time: i32,
     pub payload: VecDeque<u8>,
 }
 
@@ -90,7 +62,8 @@ impl From<u8> for KeyframeType {
     fn from(value: u8) -> Self {
         match value {
             1 => KeyframeType::Keyframe,
-            2 => KeyframeType::Interframe,
+            // disposable inter frames are inter frames all the same.
+            2 | 3 => KeyframeType::Interframe,
             _ => panic!("Invalid keyframe type."),
         }
     }
@@ -101,6 +74,7 @@ pub enum AudioConfigurationLike {
     Aac(AacSequenceHeader),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mp3Version {
     Mp25,
     Mp20,
@@ -120,6 +94,7 @@ impl From<u8> for Mp3Version {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mp3Layer {
     Reserved,
     L1,
@@ -139,6 +114,7 @@ impl From<u8> for Mp3Layer {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Channel {
     Mono,
     Dual,
@@ -169,6 +145,55 @@ pub struct Mp3ParseResult {
     pub body: Vec<u8>,
 }
 
+/// Uncompressed or G.711 audio; the format is only known from the tag header.
+pub struct PcmParseResult {
+    pub format: PcmFormat,
+    pub sample_rate: u32,
+    /// Bits per sample as stored in the sample; 8 for G.711.
+    pub sample_size: u16,
+    pub channels: u8,
+
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PcmFormat {
+    /// Sound format 0: platform endian, which is taken to be big endian.
+    BigEndian,
+    /// Sound format 3.
+    LittleEndian,
+    /// Sound format 7.
+    ALaw,
+    /// Sound format 8.
+    MuLaw,
+}
+
+impl PcmFormat {
+    pub fn sample_entry_type(&self) -> [char; 4] {
+        match self {
+            PcmFormat::BigEndian => ['t', 'w', 'o', 's'],
+            PcmFormat::LittleEndian => ['s', 'o', 'w', 't'],
+            PcmFormat::ALaw => ['a', 'l', 'a', 'w'],
+            PcmFormat::MuLaw => ['u', 'l', 'a', 'w'],
+        }
+    }
+}
+
+impl PcmParseResult {
+    /// Number of samples per channel in the payload.
+    #[inline]
+    pub fn sample_count(&self) -> u32 {
+        let frame_size = self.channels as usize * (self.sample_size as usize / 8);
+        (self.body.len() / frame_size) as u32
+    }
+}
+
+/// Sample rates of the FLV `SoundRate` field.
+pub const SOUND_RATE_TABLE: [u32; 4] = [5512, 11025, 22050, 44100];
+
+/// G.711 is 8 kHz by definition, which the FLV rate field cannot express.
+pub const G711_SAMPLE_RATE: u32 = 8000;
+
 pub const AUDIO_SAMPLE_RATE_TABLE_M10: [u32; 4] = [44100, 48000, 32000, 0];
 pub const AUDIO_SAMPLE_RATE_TABLE_M20: [u32; 4] = [22050, 24000, 16000, 0];
 pub const AUDIO_SAMPLE_RATE_TABLE_M25: [u32; 4] = [11025, 12000, 8000, 0];
@@ -177,15 +202,30 @@ pub const AUDIO_BITRATE_TABLE_L1: [u32; 16] = [0, 32, 64, 96, 128, 160, 192, 224
 pub const AUDIO_BITRATE_TABLE_L2: [u32; 16] = [0, 32, 48, 56,  64,  80,  96, 112, 128, 160, 192, 224, 256, 320, 384, 0];
 pub const AUDIO_BITRATE_TABLE_L3: [u32; 16] = [0, 32, 40, 48,  56,  64,  80,  96, 112, 128, 160, 192, 224, 256, 320, 0];
 
-const MP3_SYNC_WORD: u16 = 0x07FF;
 
 pub struct AacSequenceHeader {
     pub audio_object_type: u8,
     pub sampling_frequency_index: u8,
     pub channel_configuration: u8,
+    pub config: AudioSpecificConfig,
     pub raw: VecDeque<u8>,
 }
 
+impl AacSequenceHeader {
+    /// Synthesizes the sequence header from an ADTS header, for streams that never send one.
+    pub fn from_adts(header: &AdtsHeader) -> Result<Self, Box<dyn std::error::Error>> {
+        let raw = header.to_audio_specific_config();
+        let config = AudioSpecificConfig::parse(&raw)?;
+        Ok(Self {
+            audio_object_type: config.audio_object_type,
+            sampling_frequency_index: config.sampling_frequency_index,
+            channel_configuration: config.channel_configuration,
+            config,
+            raw: VecDeque::from(raw),
+        })
+    }
+}
+
 pub struct Parser;
 
 impl Parser {
@@ -204,88 +244,86 @@ impl Parser {
             _ => return Err("Encrypted audio is not supported.".into()),
         };
 
-        // mp3; aac
-        if header.sound_format != 2 && header.sound_format != 10 {
+        match header.fourcc.as_ref() {
+            Some(_) if header.aac_packet_type.is_none() => return Ok(AudioParseResult::Ignored),
+            Some(b"Opus") => return Self::parse_opus(header, body),
+            Some(fourcc) => return Err(format!("Unsupported audio codec {}.", String::from_utf8_lossy(fourcc)).into()),
+            None => {}
+        }
+
+        if let 0 | 3 | 7 | 8 = header.sound_format {
+            return Ok(Self::parse_pcm(header, body));
+        }
+
+        // Speex and Nellymoser can't be carried in MP4; the remuxer warns and `AudioExtractFilter` extracts them.
+        if ExtractedCodec::is_extracted(header.sound_format) {
+            return Ok(AudioParseResult::Ignored);
+        }
+
+        // mp3; mp3 8 kHz; aac
+        if header.sound_format != 2 && header.sound_format != 14 && header.sound_format != 10 {
             return Err("Unsupported sound format.".into());
         }
 
-        if header.sound_format == 2 {
+        if header.sound_format != 10 {
             // mp3
-            Self::parse_mp3(header, body)
+            Self::parse_mp3(body)
         } else {
             // aac
             Self::parse_aac(header, body)
         }
     }
 
-    fn parse_mp3(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
-        let mut u16io = io::bit::U16BitIO::new(
-            <u16>::from_be_bytes(
-                [
-                    body[0],
-                    body[1]
-                ]
-            ),
-            io::bit::UIntParserEndian::BigEndian
-        );
-        // dbg!(u16io.data);
-
-        let sync_word = u16io.read_range(0, 10);
-        if sync_word != MP3_SYNC_WORD {
-            // dbg!(sync_word);
-            return Err("MP3 sync word mismatch!".into());
-        }
-
-        let version = Mp3Version::from(u16io.read_range(11, 12) as u8);
-        let layer = Mp3Layer::from(u16io.read_range(13, 14) as u8);
-        let protection_bit = u16io.read_at(15);
-
-        let mut u16io = io::bit::U16BitIO::new(
-            <u16>::from_be_bytes(
-                [
-                    body[2],
-                    body[3]
-                ]
-            ),
-            io::bit::UIntParserEndian::BigEndian
-        );
-        let bitrate_index = u16io.read_range(0, 3);
-        let sampling_rate_index = u16io.read_range(4, 5);
-        // 1 bit padding.
-        let _ = u16io.read_range(6, 7);
-        let channel_mode = u16io.read_range(8, 9);
-
-        let sample_rate = match version {
-            Mp3Version::Mp25 => AUDIO_SAMPLE_RATE_TABLE_M25[sampling_rate_index as usize],
-            Mp3Version::Mp20 => AUDIO_SAMPLE_RATE_TABLE_M20[sampling_rate_index as usize],
-            Mp3Version::Mp10 => AUDIO_SAMPLE_RATE_TABLE_M10[sampling_rate_index as usize],
-            _ => panic!("Invalid mp3 version."),
+    fn parse_pcm(header: &AudioTagHeader, body: &VecDeque<u8>) -> AudioParseResult {
+        let format = match header.sound_format {
+            0 => PcmFormat::BigEndian,
+            3 => PcmFormat::LittleEndian,
+            7 => PcmFormat::ALaw,
+            _ => PcmFormat::MuLaw,
         };
-
-        let bitrate = match layer {
-            Mp3Layer::L1 => AUDIO_BITRATE_TABLE_L1[bitrate_index as usize],
-            Mp3Layer::L2 => AUDIO_BITRATE_TABLE_L2[bitrate_index as usize],
-            Mp3Layer::L3 => AUDIO_BITRATE_TABLE_L3[bitrate_index as usize],
-            _ => panic!("Invalid mp3 layer."),
+        let (sample_rate, sample_size) = match format {
+            PcmFormat::ALaw | PcmFormat::MuLaw => (G711_SAMPLE_RATE, 8),
+            _ => (SOUND_RATE_TABLE[header.sound_rate as usize], if header.sound_size { 16 } else { 8 }),
         };
-        // todo: is this okay?
+        let mut body = Vec::from(body.clone());
+        if sample_size == 8 && !matches!(format, PcmFormat::ALaw | PcmFormat::MuLaw) {
+            // FLV has unsigned 8-bit samples, MP4 signed ones.
+            body.iter_mut().for_each(|sample| *sample ^= 0x80);
+        }
 
-        let channel = Channel::from(channel_mode as u8);
-        let channel_extended: u8;
-        if let Channel::JointStereo = channel {
-            channel_extended = u16io.read_range(10, 11) as u8;
-        } else {
-            channel_extended = 0;
+        AudioParseResult::Pcm(PcmParseResult {
+            format,
+            sample_rate,
+            sample_size,
+            channels: if header.sound_type { 2 } else { 1 },
+            body,
+        })
+    }
+
+    fn parse_opus(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+        match header.aac_packet_type {
+            Some(0) => Ok(AudioParseResult::OpusHead(OpusHead::parse(&Vec::from(body.clone()))?)),
+            _ => Ok(AudioParseResult::OpusRaw(body.clone())),
         }
+    }
+
+    /// The stream parameters come from the first frame header; the frames themselves
+    /// are split by `mp3::Mp3FrameAssembler` since a tag may hold several, or part of one.
+    fn parse_mp3(body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+        let body = Vec::from(body.clone());
+        let header = match Mp3FrameHeader::parse(&body) {
+            Ok(header) => header,
+            Err(_) => return Ok(AudioParseResult::Mp3Partial(body)),
+        };
 
         Ok(AudioParseResult::Mp3(Mp3ParseResult {
-            version,
-            layer,
-            sample_rate,
-            bitrate,
-            channel,
-            channel_extended,
-            body: Vec::from(body.clone()),
+            version: header.version,
+            layer: header.layer,
+            sample_rate: header.sample_rate,
+            bitrate: header.bitrate,
+            channel: header.channel,
+            channel_extended: header.channel_extended,
+            body,
         }))
     }
 
@@ -302,29 +340,23 @@ impl Parser {
     }
 
     fn parse_aac_seq_hdr(body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
-        let mut u16io = io::bit::U16BitIO::new(
-            <u16>::from_be_bytes(
-                [
-                    body[0],
-                    body[1]
-                ]
-            ),
-            io::bit::UIntParserEndian::BigEndian
-        );
-        let audio_object_type = u16io.read_range(0, 4) as u8;
-        let sampling_frequency_index = u16io.read_range(5, 8) as u8;
-        let channel_configuration = u16io.read_range(9, 12) as u8;
-        // todo: note that this is just a temporary solution and requires optimizing.
+        let config = AudioSpecificConfig::parse(&Vec::from(body.clone()))?;
 
         Ok(AudioParseResult::AacSequenceHeader(AacSequenceHeader {
-            audio_object_type,
-            sampling_frequency_index,
-            channel_configuration,
+            audio_object_type: config.audio_object_type,
+            sampling_frequency_index: config.sampling_frequency_index,
+            channel_configuration: config.channel_configuration,
+            config,
             raw: body.clone(),
         }))
     }
 
+    /// Some encoders leave ADTS headers in raw packets, which decoders reject inside mdat.
     fn parse_aac_raw(body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+        let data = Vec::from(body.clone());
+        if AdtsHeader::is_adts(&data) {
+            return Ok(AudioParseResult::AacAdts(aac::strip_adts(&data)?));
+        }
         Ok(AudioParseResult::AacRaw(body.clone()))
     }
 
@@ -344,53 +376,48 @@ impl Parser {
             _ => return Err("Encrypted video is not supported.".into()),
         };
 
-        if header.codec_id == 7 {
-            // h264 avc
-            Self::parse_avc(header, body)
-        } else {
-            Err("Unsupported video codec.".into())
+        match (header.fourcc.as_ref(), header.codec_id) {
+            (Some(_), _) if header.avc_packet_type.is_none() => Ok(VideoParseResult::Ignored),
+            (Some(b"avc1"), _) | (None, 7) => {
+                // h264 avc
+                Ok(VideoParseResult::Avc1(Self::parse_avc(header, body)?))
+            }
+            (Some(b"hvc1"), _) | (None, 12) => {
+                // h265 hevc
+                Ok(VideoParseResult::Hevc(Self::parse_avc(header, body)?))
+            }
+            (Some(b"av01"), _) => {
+                Ok(VideoParseResult::Av1(Self::parse_avc(header, body)?))
+            }
+            (Some(b"vp09"), _) => {
+                Ok(VideoParseResult::Vp9(Self::parse_avc(header, body)?))
+            }
+            (Some(fourcc), _) => Err(format!("Unsupported video codec {}.", String::from_utf8_lossy(fourcc)).into()),
+            _ => Err("Unsupported video codec.".into()),
         }
     }
 
-    fn parse_avc(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<VideoParseResult, Box<dyn std::error::Error>> {
+    fn parse_avc(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<Avc1ParseResult, Box<dyn std::error::Error>> {
         match header.avc_packet_type {
             None => Err("AVC packet type is not set.".into()),
             Some(pack_type) => {
                 match pack_type {
                     // todo: use something instead of cloning.
-                    0 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(body.clone()))),
-                    1 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcNalu(Self::parse_avc_nalu(header, body.clone())?))),
-                    2 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcEndOfSequence)),
+                    0 => Ok(Avc1ParseResult::AvcSequenceHeader(body.clone())),
+                    1 => Ok(Avc1ParseResult::AvcNalu(Self::parse_avc_nalu(header, body.clone())?)),
+                    2 => Ok(Avc1ParseResult::AvcEndOfSequence),
                     _ => Err("Unsupported AVC packet type.".into()),
                 }
             }
         }
     }
 
-    fn parse_avc_nalu(header: &VideoTagHeader, mut payload: VecDeque<u8>) -> Result<AvcNalu, Box<dyn std::error::Error>> {
-        let size = payload.len() as u32;
-        let nalu_type = KeyframeType::from(header.frame_type);
-
-        if size != 0x00000001 { // start code not present
-            Ok(AvcNalu {
-                keyframe_type: nalu_type,
-                payload,
-            })
-        } else { // start code present
-            let mut u32io = io::bit::U32BitIO::new(size, io::bit::UIntParserEndian::BigEndian);
-            u32io.write_range(0, 31, size);
-            let data = u32io.get_data();
-
-            // convert the first 4 bytes to the chunk size.
-            payload[0] = data[0];
-            payload[1] = data[1];
-            payload[2] = data[2];
-            payload[3] = data[3];
-
-            Ok(AvcNalu {
-                keyframe_type: nalu_type,
-                payload,
-            })
-        }
+    /// The payload is passed on as is; `avc::to_avcc` normalizes it once the NAL length size is known.
+    fn parse_avc_nalu(header: &VideoTagHeader, payload: VecDeque<u8>) -> Result<AvcNalu, Box<dyn std::error::Error>> {
+        Ok(AvcNalu {
+            keyframe_type: KeyframeType::from(header.frame_type),
+            composition_time: header.composition_time.unwrap_or(0),
+            payload,
+        })
     }
 }
\ No newline at end of file
@@ -1,12 +1,21 @@
 use crate::exchange::{AudioCodecConfig, VideoCodecConfig};
 use crate::flv::header::FlvHeader;
 use crate::flv::meta::RawMetaData;
+use crate::fmpeg::codec_string;
+use crate::fmpeg::extract::ExtractedCodec;
+use crate::fmpeg::avc::{AvcDecoderConfigurationRecord, SequenceParameterSet, AVCC_LENGTH_SIZE};
+use crate::fmpeg::av1::{Av1CodecConfigurationRecord, Av1SequenceHeader};
+use crate::fmpeg::opus::{OpusHead, OPUS_SAMPLE_RATE};
+use crate::fmpeg::vp9::{Vp9CodecConfigurationRecord, Vp9FrameHeader};
+use crate::fmpeg::hevc::{HevcDecoderConfigurationRecord, HevcSequenceParameterSet, NAL_UNIT_SPS};
 use crate::fmpeg::mp4head::avc1_utils::AvcCBoxLike;
-use crate::fmpeg::parser::{AudioParseResult, Avc1ParseResult, Channel, VideoParseResult};
+use crate::fmpeg::timestamp::{FrameRate, MILLISECONDS, VIDEO_TIMESCALE};
+use crate::fmpeg::parser::{AacSequenceHeader, AudioParseResult, PcmFormat, Av1ParseResult, Avc1ParseResult, Channel, HevcParseResult, VideoParseResult, Vp9ParseResult};
 
 pub enum TrackType {
     Audio,
     Video,
+    Text,
 }
 
 pub struct TrackContext {
@@ -32,8 +41,8 @@ pub struct SampleContext {
     pub is_keyframe: bool,
     pub has_redundancy: bool,
 
-    pub decode_time: u32,
-    pub composition_time_offset: u32, // most of the time this can be set to 0.
+    pub decode_time: u64,
+    pub composition_time_offset: i32, // 0 without B-frames; negative only when the composition time of the tag is.
     // dts   +  cts    =   pts
     // decode   offset     presentation
     pub sample_duration: u32,
@@ -46,8 +55,8 @@ pub struct SampleContextBuilder {
     pub is_keyframe: bool,
     pub has_redundancy: bool,
 
-    pub decode_time: u32,
-    pub composition_time_offset: u32,
+    pub decode_time: u64,
+    pub composition_time_offset: i32,
     pub sample_duration: u32,
     pub sample_size: u32,
 }
@@ -92,13 +101,13 @@ impl SampleContextBuilder {
     }
 
     #[inline]
-    pub fn set_decode_time(&mut self, decode_time: u32) -> &mut Self {
+    pub fn set_decode_time(&mut self, decode_time: u64) -> &mut Self {
         self.decode_time = decode_time;
         self
     }
 
     #[inline]
-    pub fn set_composition_time_offset(&mut self, composition_time_offset: u32) -> &mut Self {
+    pub fn set_composition_time_offset(&mut self, composition_time_offset: i32) -> &mut Self {
         self.composition_time_offset = composition_time_offset;
         self
     }
@@ -131,14 +140,11 @@ impl SampleContextBuilder {
     }
 }
 
-pub const TIME_SCALE: u32 = 24000;
-// Magic number!!
-// Using 1000 is not accurate enough, and will lead to audio/video sync issue (e.g. flaws, time mismatch, etc.)
-// 24000 is big enough and will not cause overflow.
-
 pub struct RemuxContext {
     pub fps: f64,
+    /// The frame rate as `fps_num / fps_den`; `fps_num` is 0 when unknown.
     pub fps_num: u32,
+    pub fps_den: u32,
 
     pub duration_ms: u32,
 
@@ -156,7 +162,13 @@ pub struct RemuxContext {
     pub audio_sample_rate: u32,
     pub audio_channels: u8,
     pub audio_channels_extended: u8,
+    pub audio_samples_per_frame: u32,
     pub audio_aac_info: Vec<u8>,
+    pub audio_opus_head: Option<OpusHead>,
+    /// Bits per sample of PCM and G.711 audio.
+    pub audio_sample_size: u16,
+    /// `esds` objectTypeIndication of MP3: MPEG-1 or MPEG-2 audio.
+    pub audio_mp3_object_type: u8,
     // ------------------------------------------------
 
     pub video_codec_id: u8,
@@ -165,12 +177,32 @@ pub struct RemuxContext {
     // --- must be initialized using video tag data ---
     pub video_data_rate: u32,
     pub video_avcc_info: AvcCBoxLike,
+    pub video_avc_config: Option<AvcDecoderConfigurationRecord>,
+    pub video_sps: Option<SequenceParameterSet>,
+    pub video_hevc_config: Option<HevcDecoderConfigurationRecord>,
+    pub video_hevc_sps: Option<HevcSequenceParameterSet>,
+    pub video_av1_config: Option<Av1CodecConfigurationRecord>,
+    pub video_av1_sequence_header: Option<Av1SequenceHeader>,
+    pub video_vp9_config: Option<Vp9CodecConfigurationRecord>,
     // ------------------------------------------------
 
     pub major_brand: String,
     pub minor_version: String,
     pub compatible_brands: Vec<String>,
 
+    /// Whether the init segment declares a WebVTT track for captions.
+    pub caption_track: bool,
+    /// Whether the init segment declares an audio track; off when the audio is in a codec MP4 can't carry.
+    pub audio_track: bool,
+
+    /// Timescales of the tracks, fixed once the init segment is sent.
+    pub video_timescale: u32,
+    pub audio_timescale: u32,
+
+    /// Composition time of the first video sample, in ms; the video edit list starts there.
+    /// Kept across seeks to stay on the same timeline.
+    pub video_composition_shift: Option<i32>,
+
     header_sent: bool,
     flv_header_configured: bool,
     metadata_configured: bool,
@@ -178,10 +210,16 @@ pub struct RemuxContext {
     audio_metadata_configured: bool,
 
     pub(crate) sequence_number: u32,
+
+    // problems noticed while configuring, to be reported by the remuxer.
+    warnings: Vec<String>,
 }
 
 pub enum VideoCodecType {
     Avc1,
+    Hevc,
+    Av1,
+    Vp9,
     None
 }
 
@@ -189,6 +227,20 @@ impl From<u8> for VideoCodecType {
     fn from(value: u8) -> Self {
         match value {
             7 => VideoCodecType::Avc1,
+            12 => VideoCodecType::Hevc,
+            _ => VideoCodecType::None
+        }
+    }
+}
+
+impl VideoCodecType {
+    /// For Enhanced FLV, which signals codecs by FourCC.
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Self {
+        match fourcc {
+            b"avc1" => VideoCodecType::Avc1,
+            b"hvc1" => VideoCodecType::Hevc,
+            b"av01" => VideoCodecType::Av1,
+            b"vp09" => VideoCodecType::Vp9,
             _ => VideoCodecType::None
         }
     }
@@ -197,6 +249,8 @@ impl From<u8> for VideoCodecType {
 pub enum AudioCodecType {
     Aac,
     Mp3,
+    Opus,
+    Pcm(PcmFormat),
     None,
 }
 
@@ -204,7 +258,23 @@ impl From<u8> for AudioCodecType {
     fn from(value: u8) -> Self {
         match value {
             10 => AudioCodecType::Aac,
-            2 => AudioCodecType::Mp3,
+            2 | 14 => AudioCodecType::Mp3,
+            0 => AudioCodecType::Pcm(PcmFormat::BigEndian),
+            3 => AudioCodecType::Pcm(PcmFormat::LittleEndian),
+            7 => AudioCodecType::Pcm(PcmFormat::ALaw),
+            8 => AudioCodecType::Pcm(PcmFormat::MuLaw),
+            _ => AudioCodecType::None
+        }
+    }
+}
+
+impl AudioCodecType {
+    /// For Enhanced FLV, which signals codecs by FourCC.
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Self {
+        match fourcc {
+            b"mp4a" => AudioCodecType::Aac,
+            b".mp3" => AudioCodecType::Mp3,
+            b"Opus" => AudioCodecType::Opus,
             _ => AudioCodecType::None
         }
     }
@@ -215,6 +285,7 @@ impl RemuxContext {
         Self {
             fps: 0.0,
             fps_num: 0,
+            fps_den: MILLISECONDS,
             duration_ms: 0,
 
             width: 0.0,
@@ -228,16 +299,35 @@ impl RemuxContext {
             audio_sample_rate: 0,
             audio_channels: 0,
             audio_channels_extended: 0,
+            audio_samples_per_frame: 1024,
             audio_aac_info: vec![],
+            audio_opus_head: None,
+            audio_sample_size: 16,
+            audio_mp3_object_type: codec_string::OBJECT_TYPE_MPEG1_AUDIO,
 
             video_codec_id: 0,
             video_data_rate: 0,
             video_avcc_info: AvcCBoxLike::AvcCBoxLike(vec![]),
+            video_avc_config: None,
+            video_sps: None,
+            video_hevc_config: None,
+            video_hevc_sps: None,
+            video_av1_config: None,
+            video_av1_sequence_header: None,
+            video_vp9_config: None,
 
             major_brand: String::from("isom"),
             minor_version: String::from("512"),
             compatible_brands: vec![],
 
+            caption_track: false,
+            audio_track: true,
+
+            video_timescale: VIDEO_TIMESCALE,
+            audio_timescale: MILLISECONDS,
+
+            video_composition_shift: None,
+
             video_codec_type: VideoCodecType::None,
             audio_codec_type: AudioCodecType::None,
 
@@ -248,6 +338,8 @@ impl RemuxContext {
             audio_metadata_configured: false,
 
             sequence_number: 1,
+
+            warnings: vec![],
         }
     }
 
@@ -259,7 +351,7 @@ impl RemuxContext {
 
     pub fn parse_metadata(&mut self, metadata: &RawMetaData) {
         if let Some(duration) = metadata.try_get_number("duration") {
-            self.duration_ms = (duration * TIME_SCALE as f64) as u32;
+            self.duration_ms = (duration * MILLISECONDS as f64) as u32;
         }
 
         if let Some(width) = metadata.try_get_number("width") {
@@ -271,13 +363,20 @@ impl RemuxContext {
         }
 
         if let Some(frame_rate) = metadata.try_get_number("framerate") {
-            self.fps = frame_rate;
-            self.fps_num = (frame_rate * TIME_SCALE as f64) as u32;
+            self.set_frame_rate(frame_rate);
         }
 
         if let Some(audio_codec_id) = metadata.try_get_number("audiocodecid") {
-            self.audio_codec_id = audio_codec_id as u8;
-            self.audio_codec_type = AudioCodecType::from(self.audio_codec_id);
+            if audio_codec_id > u8::MAX as f64 {
+                // Enhanced FLV puts the FourCC here.
+                self.audio_codec_type = AudioCodecType::from_fourcc(&(audio_codec_id as u32).to_be_bytes());
+            } else {
+                self.audio_codec_id = audio_codec_id as u8;
+                self.audio_codec_type = AudioCodecType::from(self.audio_codec_id);
+                if ExtractedCodec::is_extracted(self.audio_codec_id) {
+                    self.drop_audio_track();
+                }
+            }
         }
 
         if let Some(audio_data_rate) = metadata.try_get_number("audiodatarate") {
@@ -285,8 +384,13 @@ impl RemuxContext {
         }
 
         if let Some(video_codec_id) = metadata.try_get_number("videocodecid") {
-            self.video_codec_id = video_codec_id as u8;
-            self.video_codec_type = VideoCodecType::from(self.video_codec_id);
+            if video_codec_id > u8::MAX as f64 {
+                // Enhanced FLV puts the FourCC here.
+                self.video_codec_type = VideoCodecType::from_fourcc(&(video_codec_id as u32).to_be_bytes());
+            } else {
+                self.video_codec_id = video_codec_id as u8;
+                self.video_codec_type = VideoCodecType::from(self.video_codec_id);
+            }
         }
 
         if let Some(video_data_rate) = metadata.try_get_number("videodatarate") {
@@ -320,34 +424,30 @@ impl RemuxContext {
         self.metadata_configured = true;
     }
 
-    const AAC_SAMPLE_RATES: [u32; 13] = [
-        96000, 88200, 64000, 48000,
-        44100, 32000, 24000, 22050,
-        16000, 12000, 11025, 8000,
-        7350
-    ];
     pub fn configure_audio_metadata(&mut self, audio_metadata: &AudioParseResult) -> Option<AudioCodecConfig> {
         match audio_metadata {
             AudioParseResult::AacSequenceHeader(aac_info) => {
-                if self.audio_codec_id != 10 {
-                    panic!("audio type mismatch: expected aac.");
+                // the sequence header is authoritative over onMetaData, whose codec id may also be a FourCC.
+                if !matches!(self.audio_codec_type, AudioCodecType::Aac | AudioCodecType::None) {
+                    self.warnings.push("Metadata announces another audio codec than AAC; going by the sequence header.".to_string());
                 }
+                self.audio_codec_type = AudioCodecType::Aac;
 
-                self.audio_channels = aac_info.channel_configuration;
-                if aac_info.sampling_frequency_index > 12 {
-                    panic!("invalid aac sample rate index");
-                }
-                self.audio_sample_rate = Self::AAC_SAMPLE_RATES[aac_info.sampling_frequency_index as usize];
+                // with SBR and PS the decoded output differs from what the core coder signals.
+                self.audio_channels = aac_info.config.output_channels();
+                self.set_audio_sample_rate(aac_info.config.output_sampling_frequency());
+                self.audio_samples_per_frame = aac_info.config.samples_per_frame();
                 self.audio_aac_info = Vec::from(aac_info.raw.clone());
 
                 self.audio_metadata_configured = true;
 
-                Some(AudioCodecConfig::new(AudioCodecType::Aac, aac_info.audio_object_type))
+                Some(AudioCodecConfig::new(AudioCodecType::Aac, aac_info.config.codec_object_type()))
             }
             AudioParseResult::Mp3(mp3_info) => {
-                if self.audio_codec_id != 2 {
-                    panic!("audio type mismatch: expected mp3.");
+                if !matches!(self.audio_codec_type, AudioCodecType::Mp3 | AudioCodecType::None) {
+                    self.warnings.push("Metadata announces another audio codec than MP3; going by the frame header.".to_string());
                 }
+                self.audio_codec_type = AudioCodecType::Mp3;
 
                 self.audio_channels = match mp3_info.channel {
                     Channel::Mono => {
@@ -364,11 +464,46 @@ impl RemuxContext {
                         2
                     }
                 };
-                self.audio_sample_rate = mp3_info.sample_rate;
+                self.set_audio_sample_rate(mp3_info.sample_rate);
+                self.audio_mp3_object_type = codec_string::mp3_object_type(mp3_info.version);
+
+                self.audio_metadata_configured = true;
+
+                Some(AudioCodecConfig::new(AudioCodecType::Mp3, self.audio_mp3_object_type))
+            }
+            AudioParseResult::OpusHead(head) => {
+                self.audio_codec_type = AudioCodecType::Opus;
+                self.audio_channels = head.channel_count;
+                // the input rate is informational only.
+                self.set_audio_sample_rate(OPUS_SAMPLE_RATE);
+                self.audio_opus_head = Some(head.clone());
 
                 self.audio_metadata_configured = true;
 
-                Some(AudioCodecConfig::new(AudioCodecType::Mp3, 0))
+                Some(AudioCodecConfig::new(AudioCodecType::Opus, 0))
+            }
+            AudioParseResult::Pcm(pcm_info) => {
+                self.audio_codec_type = AudioCodecType::Pcm(pcm_info.format);
+                self.audio_channels = pcm_info.channels;
+                self.set_audio_sample_rate(pcm_info.sample_rate);
+                self.audio_sample_size = pcm_info.sample_size;
+
+                self.audio_metadata_configured = true;
+
+                Some(AudioCodecConfig::new(AudioCodecType::Pcm(pcm_info.format), 0))
+            }
+            AudioParseResult::AacAdts(frames) => {
+                // without a sequence header, the ADTS header is all there is to go by.
+                if self.audio_metadata_configured {
+                    return None;
+                }
+                match frames.first().map(|frame| AacSequenceHeader::from_adts(&frame.header)) {
+                    Some(Ok(header)) => {
+                        self.warnings.push("AAC sequence header missing, synthesized from ADTS.".to_string());
+                        self.configure_audio_metadata(&AudioParseResult::AacSequenceHeader(header))
+                    }
+                    _ => None,
+                }
             }
             _ => {
                 // raw data, do nothing.
@@ -380,40 +515,250 @@ impl RemuxContext {
         // todo: test this.
     }
 
-    pub fn configure_video_metadata(&mut self, video_metadata: &VideoParseResult) -> Option<VideoCodecConfig> {
+    /// Malformed AVC sequence headers are rejected instead of ending up in the `avcC` box.
+    pub fn configure_video_metadata(&mut self, video_metadata: &VideoParseResult) -> Result<Option<VideoCodecConfig>, Box<dyn std::error::Error>> {
         match video_metadata {
             VideoParseResult::Avc1(h264_info) => {
                 match h264_info {
                     Avc1ParseResult::AvcSequenceHeader(header) => {
-                        self.video_avcc_info = AvcCBoxLike::AvcCBoxLike(Vec::from(header.clone()));
-                        // todo: handle the codec config here.
-                        // note that raw data may contain some misleading stuff.
-                        // use dbg!() to check what's inside header: &VecDeque<u8>.
+                        let record = AvcDecoderConfigurationRecord::parse(&Vec::from(header.clone()))?;
+                        // the sequence header is authoritative over onMetaData.
+                        self.video_codec_type = VideoCodecType::Avc1;
+                        // samples are rewritten with 4-byte lengths, so the box has to say so.
+                        let mut avcc_record = record.clone();
+                        avcc_record.length_size_minus_one = AVCC_LENGTH_SIZE - 1;
+                        self.video_avcc_info = AvcCBoxLike::AvcCBoxLike(avcc_record.serialize());
+                        match SequenceParameterSet::parse(&record.sequence_parameter_sets[0]) {
+                            Ok(sps) => self.apply_sps(sps),
+                            Err(e) => self.warnings.push(format!("Failed to parse SPS, keeping metadata values: {}", e)),
+                        }
+                        if self.fps == 0.0 {
+                            self.warnings.push("Frame rate unknown: neither metadata nor SPS provide it.".to_string());
+                        }
+
                         let codec_conf = VideoCodecConfig::new(
-                            header[1],
-                            header[2],
-                            header[3]
+                            record.avc_profile_indication,
+                            record.profile_compatibility,
+                            record.avc_level_indication
                         );
+                        self.video_avc_config = Some(record);
 
                         self.video_metadata_configured = true;
-                        Some(codec_conf)
+                        Ok(Some(codec_conf))
                     }
                     Avc1ParseResult::AvcEndOfSequence => {
                         // todo: handle this.
-                        None
+                        Ok(None)
                     }
                     _ => {
                         // raw data, do nothing.
-                        None
+                        Ok(None)
+                    }
+                }
+            }
+            VideoParseResult::Hevc(HevcParseResult::AvcSequenceHeader(header)) => {
+                let record = HevcDecoderConfigurationRecord::parse(&Vec::from(header.clone()))?;
+                self.video_codec_type = VideoCodecType::Hevc;
+                match record.nal_units(NAL_UNIT_SPS).next().map(|sps| HevcSequenceParameterSet::parse(sps)) {
+                    Some(Ok(sps)) => self.apply_hevc_sps(sps),
+                    Some(Err(e)) => self.warnings.push(format!("Failed to parse SPS, keeping metadata values: {}", e)),
+                    None => {}
+                }
+                if self.fps == 0.0 {
+                    self.warnings.push("Frame rate unknown: neither metadata nor SPS provide it.".to_string());
+                }
+
+                let codec_conf = VideoCodecConfig::with_codec_string(record.codec_string());
+                self.video_hevc_config = Some(record);
+
+                self.video_metadata_configured = true;
+                Ok(Some(codec_conf))
+            }
+            VideoParseResult::Av1(Av1ParseResult::AvcSequenceHeader(header)) => {
+                let record = Av1CodecConfigurationRecord::parse(&Vec::from(header.clone()))?;
+                self.video_codec_type = VideoCodecType::Av1;
+                match record.sequence_header() {
+                    Some(Ok(sequence_header)) => {
+                        self.apply_video_size(sequence_header.max_frame_width, sequence_header.max_frame_height, sequence_header.frame_rate());
+                        self.video_av1_sequence_header = Some(sequence_header);
                     }
+                    Some(Err(e)) => self.warnings.push(format!("Failed to parse the sequence header, keeping metadata values: {}", e)),
+                    None => self.warnings.push("AV1 codec configuration record has no sequence header, keeping metadata values.".to_string()),
+                }
+                if self.fps == 0.0 {
+                    self.warnings.push("Frame rate unknown: neither metadata nor sequence header provide it.".to_string());
                 }
+
+                let codec_conf = VideoCodecConfig::with_codec_string(record.codec_string());
+                self.video_av1_config = Some(record);
+
+                self.video_metadata_configured = true;
+                Ok(Some(codec_conf))
+            }
+            VideoParseResult::Vp9(Vp9ParseResult::AvcSequenceHeader(header)) => {
+                self.video_codec_type = VideoCodecType::Vp9;
+                if header.is_empty() {
+                    self.warnings.push("VP9 sequence start without a record, waiting for a key frame.".to_string());
+                    return Ok(None);
+                }
+                let record = Vp9CodecConfigurationRecord::parse(&Vec::from(header.clone()))?;
+                if self.fps == 0.0 {
+                    self.warnings.push("Frame rate unknown: VP9 only has it in metadata.".to_string());
+                }
+
+                let codec_conf = VideoCodecConfig::with_codec_string(record.codec_string());
+                self.video_vp9_config = Some(record);
+
+                self.video_metadata_configured = true;
+                Ok(Some(codec_conf))
+            }
+            VideoParseResult::Vp9(Vp9ParseResult::AvcNalu(frame)) if !self.video_metadata_configured => {
+                let frame_header = match Vp9FrameHeader::parse(&Vec::from(frame.payload.clone())) {
+                    Ok(frame_header) if frame_header.key_frame => frame_header,
+                    // nothing to configure from, and undecodable without a key frame anyway.
+                    _ => return Ok(None),
+                };
+                self.video_codec_type = VideoCodecType::Vp9;
+                self.warnings.push("No VP9 record received, deriving it from the first key frame.".to_string());
+                self.apply_video_size(frame_header.width, frame_header.height, None);
+
+                let record = Vp9CodecConfigurationRecord::from_frame_header(&frame_header);
+                let codec_conf = VideoCodecConfig::with_codec_string(record.codec_string());
+                self.video_vp9_config = Some(record);
+
+                self.video_metadata_configured = true;
+                Ok(Some(codec_conf))
             }
             _ => {
-                None
+                Ok(None)
+            }
+        }
+    }
+
+    /// The `vpcC` content: version 1, no flags, then the record.
+    pub fn video_vpcc_info(&self) -> Vec<u8> {
+        match self.video_vp9_config {
+            Some(ref record) => {
+                let mut data = vec![1, 0, 0, 0];
+                data.append(&mut record.serialize());
+                data
+            }
+            None => vec![]
+        }
+    }
+
+    /// The `av1C` content.
+    pub fn video_av1c_info(&self) -> Vec<u8> {
+        match self.video_av1_config {
+            Some(ref record) => record.serialize(),
+            None => vec![]
+        }
+    }
+
+    /// Whether AV1 frame headers of this stream omit the frame type, i.e. every frame is a key frame.
+    pub fn video_av1_reduced_still_picture_header(&self) -> bool {
+        self.video_av1_sequence_header.as_ref().is_some_and(|header| header.reduced_still_picture_header)
+    }
+
+    /// The `hvcC` content, rewritten for the 4-byte lengths the samples get.
+    pub fn video_hvcc_info(&self) -> Vec<u8> {
+        match self.video_hevc_config {
+            Some(ref record) => {
+                let mut record = record.clone();
+                record.length_size_minus_one = AVCC_LENGTH_SIZE - 1;
+                record.serialize()
             }
+            None => vec![]
         }
     }
 
+    /// The SPS is authoritative: its values fill in or replace the ones taken from onMetaData.
+    fn apply_sps(&mut self, sps: SequenceParameterSet) {
+        self.apply_video_size(sps.width, sps.height, sps.frame_rate());
+        self.video_sps = Some(sps);
+    }
+
+    fn apply_hevc_sps(&mut self, sps: HevcSequenceParameterSet) {
+        self.apply_video_size(sps.width, sps.height, sps.frame_rate());
+        self.video_hevc_sps = Some(sps);
+    }
+
+    fn apply_video_size(&mut self, width: u32, height: u32, frame_rate: Option<f64>) {
+        let width = width as f64;
+        let height = height as f64;
+        if self.width != 0.0 && self.width != width {
+            self.warnings.push(format!("Metadata width {} disagrees with bitstream width {}, using the bitstream.", self.width, width));
+        }
+        if self.height != 0.0 && self.height != height {
+            self.warnings.push(format!("Metadata height {} disagrees with bitstream height {}, using the bitstream.", self.height, height));
+        }
+        self.width = width;
+        self.height = height;
+
+        if let Some(fps) = frame_rate {
+            if self.fps != 0.0 && (self.fps - fps).abs() > 0.01 {
+                self.warnings.push(format!("Metadata frame rate {} disagrees with bitstream frame rate {}, using the bitstream.", self.fps, fps));
+            }
+            self.set_frame_rate(fps);
+        }
+    }
+
+    fn set_frame_rate(&mut self, fps: f64) {
+        self.fps = fps;
+        let frame_rate = FrameRate::from_fps(fps);
+        (self.fps_num, self.fps_den) = frame_rate.map_or((0, MILLISECONDS), |rate| (rate.num, rate.den));
+        if !self.header_sent {
+            self.video_timescale = frame_rate.map_or(VIDEO_TIMESCALE, |rate| rate.timescale());
+        }
+    }
+
+    /// Audio tracks count in samples, so that every frame lasts a whole number of ticks.
+    fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.audio_sample_rate = sample_rate;
+        if !self.header_sent && sample_rate > 0 {
+            self.audio_timescale = sample_rate;
+        }
+    }
+
+    pub fn frame_rate(&self) -> Option<FrameRate> {
+        (self.fps_num > 0 && self.fps_den > 0).then(|| FrameRate::new(self.fps_num, self.fps_den))
+    }
+
+    /// Ticks per video frame, or 0 when the frame rate is unknown.
+    pub fn video_frame_duration(&self) -> u32 {
+        self.frame_rate().map_or(0, |rate| rate.frame_duration(self.video_timescale))
+    }
+
+    /// NAL unit length size of the incoming samples, as announced by the AVC or HEVC sequence header.
+    pub fn video_nal_length_size(&self) -> u8 {
+        match (self.video_avc_config.as_ref(), self.video_hevc_config.as_ref()) {
+            (_, Some(record)) if matches!(self.video_codec_type, VideoCodecType::Hevc) => record.nal_length_size(),
+            (Some(record), _) => record.nal_length_size(),
+            _ => AVCC_LENGTH_SIZE
+        }
+    }
+
+    /// Width of the track once the sample aspect ratio is applied.
+    pub fn display_width(&self) -> f64 {
+        match (self.video_sps.as_ref(), self.video_hevc_sps.as_ref()) {
+            (Some(sps), _) => sps.display_width(),
+            (_, Some(sps)) => sps.display_width(),
+            _ => self.width
+        }
+    }
+
+    /// Returns and clears the problems noticed while configuring.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Leaves the audio track out of the init segment; its tags are skipped from now on.
+    pub fn drop_audio_track(&mut self) {
+        self.audio_track = false;
+        self.audio_codec_type = AudioCodecType::None;
+        self.audio_metadata_configured = true;
+    }
+
     pub fn is_metadata_complete(&self) -> bool {
         self.flv_header_configured && self.metadata_configured
     }
@@ -433,6 +778,12 @@ impl RemuxContext {
         self.audio_metadata_configured = flag;
     }
 
+    /// Restarts fragment numbering after a seek.
+    /// The init segment stays valid, so the configuration and header state are kept.
+    pub fn reset_fragment_sequence(&mut self) {
+        self.sequence_number = 1;
+    }
+
     pub fn is_header_sent(&self) -> bool {
         self.header_sent
     }
@@ -1,17 +1,36 @@
 use crate::exchange::PackedContentToCore::Data;
-use crate::exchange::{Destination, ExchangeRegistrable, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, PackedContentToRemuxer, RemuxedData};
-use crate::flv::header::FlvHeader;
+use crate::exchange::{AudioCodecConfig, Destination, ExchangeRegistrable, IStateMachine, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, PackedContentToRemuxer, RemuxedData};
+use crate::flv::header::{FlvHeader, TagHeader};
 use crate::flv::meta::RawMetaData;
 use crate::flv::tag::{Tag, TagType};
-use crate::fmpeg::encoder::{Encoder, DEFAULT_AUDIO_TRACK_ID, DEFAULT_VIDEO_TRACK_ID};
+use crate::fmpeg::encoder::{Encoder, DEFAULT_AUDIO_TRACK_ID, DEFAULT_CAPTION_TRACK_ID, DEFAULT_VIDEO_TRACK_ID};
+use crate::fmpeg::mp4frag::WebVttSample;
 use crate::fmpeg::mp4head::ISerializable;
-use crate::fmpeg::parser::{parse_aac_timescale, parse_avc_timescale, parse_mp3_timescale, parse_timescale, AudioParseResult, Avc1ParseResult, KeyframeType, Parser, VideoParseResult};
-use crate::fmpeg::remux_context::{RemuxContext, SampleContextBuilder, TrackContext, TrackType};
+use crate::fmpeg::avc;
+use crate::fmpeg::avc::{NaluFilter, AVCC_LENGTH_SIZE};
+use crate::fmpeg::caption;
+use crate::fmpeg::extract::ExtractedCodec;
+use crate::fmpeg::av1;
+use crate::fmpeg::hevc;
+use crate::fmpeg::duration::{DurationTracker, PendingSample};
+use crate::fmpeg::mp3::Mp3FrameAssembler;
+use crate::fmpeg::opus;
+use crate::fmpeg::vp9;
+use crate::fmpeg::caption::{CaptionEvent, CaptionExtractor};
+use crate::fmpeg::opus::OPUS_SAMPLE_RATE;
+use crate::fmpeg::parser::{AudioParseResult, AvcNalu, PcmParseResult, Avc1ParseResult, KeyframeType, Parser, VideoParseResult, Vp9ParseResult};
+use crate::fmpeg::timeline::TimestampNormalizer;
+use crate::fmpeg::timestamp::Timestamp;
+use crate::fmpeg::remux_context::{AudioCodecType, RemuxContext, SampleContext, SampleContextBuilder, TrackContext, TrackType, VideoCodecType};
+use crate::event::{Logger, Metrics};
 use std::cmp::PartialEq;
 use std::collections::VecDeque;
 use std::sync::mpsc;
 use std::thread::JoinHandle;
 
+/// How long the init segment may wait for the first video sample, in ms of queued tags.
+const MAX_INIT_SEGMENT_DELAY_MS: u32 = 1000;
+
 pub struct Remuxer {
     channel_exchange: Option<mpsc::Sender<Packed>>,
     channel_receiver: mpsc::Receiver<PackedContent>,
@@ -26,8 +45,34 @@ pub struct Remuxer {
 
     audio_track: TrackContext,
     video_track: TrackContext,
+    caption_track: TrackContext,
+
+    captions: CaptionExtractor,
+    // end of the last caption sample, to fill gaps with empty cues.
+    caption_end_ms: Option<u64>,
+
+    _temp: Option<Vec<u8>>,
+    // a video sample that arrived before the init segment could be sent.
+    _temp_video: Option<Vec<u8>>,
+
+    // the sequence header the video track was configured from; later ones are compared to it.
+    video_sequence_header: Option<VecDeque<u8>>,
+
+    mp3_frames: Mp3FrameAssembler,
+    // end of the last MP3 frame sent, where a frame continued from the previous tag starts.
+    mp3_next_decode_time: Option<u64>,
 
-    _temp: Option<Vec<u8>>
+    // 64-bit, monotonic millisecond timelines for the tags of each track.
+    audio_timeline: TimestampNormalizer,
+    video_timeline: TimestampNormalizer,
+
+    // the last sample of each track, held until the next one gives its duration.
+    audio_durations: DurationTracker,
+    video_durations: DurationTracker,
+
+    nalu_filter: NaluFilter,
+
+    logger: Logger,
 }
 
 impl ExchangeRegistrable for Remuxer {
@@ -42,6 +87,10 @@ impl ExchangeRegistrable for Remuxer {
     fn get_self_as_destination(&self) -> Destination {
         Destination::Remuxer
     }
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
 }
 
 impl PartialEq for KeyframeType {
@@ -70,11 +119,41 @@ impl Remuxer {
 
             audio_track: TrackContext::new(DEFAULT_AUDIO_TRACK_ID, TrackType::Audio),
             video_track: TrackContext::new(DEFAULT_VIDEO_TRACK_ID, TrackType::Video),
+            caption_track: TrackContext::new(DEFAULT_CAPTION_TRACK_ID, TrackType::Text),
 
-            _temp: None
+            captions: CaptionExtractor::new(),
+            caption_end_ms: None,
+
+            _temp: None,
+            _temp_video: None,
+            video_sequence_header: None,
+            mp3_frames: Mp3FrameAssembler::new(),
+            mp3_next_decode_time: None,
+            audio_timeline: TimestampNormalizer::default(),
+            video_timeline: TimestampNormalizer::default(),
+            audio_durations: DurationTracker::new(),
+            video_durations: DurationTracker::new(),
+            nalu_filter: NaluFilter::new(),
+            logger: Logger::new(Destination::Remuxer),
         }
     }
 
+    /// Sets which NAL units are dropped from video samples. Nothing is dropped by default.
+    pub fn set_nalu_filter(&mut self, filter: NaluFilter) {
+        self.nalu_filter = filter;
+    }
+
+    /// Sets how far, in ms, a timestamp may jump from the previous one of its track before the track is rebased.
+    pub fn set_discontinuity_threshold(&mut self, threshold_ms: u64) {
+        self.audio_timeline.set_threshold(threshold_ms);
+        self.video_timeline.set_threshold(threshold_ms);
+    }
+
+    /// Adds a WebVTT track with the decoded captions to the output. Must be set before the init segment is sent.
+    pub fn set_caption_track(&mut self, flag: bool) {
+        self.ctx.caption_track = flag;
+    }
+
     #[inline]
     fn set_remuxing(&mut self, flag: bool) {
         self.remuxing = flag;
@@ -91,16 +170,32 @@ impl Remuxer {
         let mut header = Encoder::encode_ftyp(&self.ctx).serialize();
         header.append(&mut Encoder::encode_moov(&self.ctx).serialize());
         self.ctx.set_header_sent(true);
+        Metrics::add(&self.logger.metrics().bytes_out, header.len() as u64);
+        self.logger.debug("Init segment sent.");
 
         self.send(
             Packed {
                 packed_routing: Destination::Core,
                 packed_content: PackedContent::ToCore(Data(RemuxedData::Header(header))),
             }
-        )
+        )?;
+        if let Some(tmp) = self._temp_video.take() {
+            self.send_raw_data(RemuxedData::Video(tmp))?;
+        }
+        Ok(())
     }
 
     fn send_raw_data(&mut self, data: RemuxedData) -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = self.logger.metrics();
+        match data {
+            RemuxedData::Header(ref data) => {
+                Metrics::add(&metrics.bytes_out, data.len() as u64);
+            }
+            RemuxedData::Audio(ref data) | RemuxedData::Video(ref data) | RemuxedData::Text(ref data) => {
+                Metrics::add(&metrics.bytes_out, data.len() as u64);
+                Metrics::add(&metrics.fragments_emitted, 1);
+            }
+        }
         self.send(
             Packed {
                 packed_routing: Destination::Core,
@@ -109,17 +204,318 @@ impl Remuxer {
         )
     }
 
+    /// Sends the captions completed so far to the core and, if enabled, to the caption track.
+    fn send_captions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        for event in self.captions.take_events() {
+            if self.ctx.caption_track {
+                self.send_caption_samples(&event)?;
+            }
+            self.send(
+                Packed {
+                    packed_routing: Destination::Core,
+                    packed_content: PackedContent::ToCore(PackedContentToCore::Caption(event)),
+                }
+            )?;
+        }
+        Ok(())
+    }
+
+    fn send_caption_samples(&mut self, event: &CaptionEvent) -> Result<(), Box<dyn std::error::Error>> {
+        // WebVTT samples must not overlap; the gaps between cues are filled with empty ones.
+        let start_ms = match self.caption_end_ms {
+            Some(end_ms) if end_ms < event.start_ms => {
+                self.send_caption_sample(end_ms, event.start_ms, WebVttSample::Empty)?;
+                event.start_ms
+            }
+            Some(end_ms) => end_ms,
+            None => event.start_ms,
+        };
+        if event.end_ms > start_ms {
+            self.send_caption_sample(start_ms, event.end_ms, WebVttSample::Cue(event.text.clone()))?;
+            self.caption_end_ms = Some(event.end_ms);
+        }
+        Ok(())
+    }
+
+    fn send_caption_sample(&mut self, start_ms: u64, end_ms: u64, mut sample: WebVttSample) -> Result<(), Box<dyn std::error::Error>> {
+        // the caption track counts in milliseconds.
+        let payload = sample.serialize();
+        let mut sample_ctx = SampleContextBuilder::new()
+            .set_decode_time(start_ms)
+            .set_sample_size(payload.len() as u32)
+            .set_sample_duration((end_ms - start_ms) as u32)
+            .set_is_keyframe(true)
+            .build();
+
+        let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.caption_track, &mut sample_ctx).serialize();
+        data.append(&mut Encoder::encode_mdat(payload).serialize());
+        self.send_raw_data(RemuxedData::Text(data))
+    }
+
+    /// Rewrites a sample to 4-byte length prefixes, warning about the NAL units it had to drop.
+    fn to_avcc(&self, payload: &[u8], timestamp: u64) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (payload, dropped) = avc::to_avcc(payload, self.ctx.video_nal_length_size())?;
+        if dropped > 0 {
+            self.logger.warn(format!("Dropped {} empty or truncated NAL units at {} ms.", dropped, timestamp));
+        }
+        Ok(payload)
+    }
+
+    /// Turns one video tag into a moof and mdat pair, feeding the caption extractor on the way.
+    fn encode_video_sample(&mut self, timestamp: u64, data: AvcNalu, codec_type: VideoCodecType) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // the tag header is sometimes wrong; the NAL units (or OBUs) are not.
+        let (payload, keyframe) = match codec_type {
+            VideoCodecType::Hevc => {
+                let payload = self.to_avcc(&Vec::from(data.payload), timestamp)?;
+                let keyframe = hevc::contains_irap(&payload);
+                (payload, keyframe)
+            }
+            VideoCodecType::Av1 => {
+                let payload = av1::strip_temporal_delimiters(&Vec::from(data.payload));
+                let keyframe = av1::is_keyframe(&payload, self.ctx.video_av1_reduced_still_picture_header());
+                (payload, keyframe)
+            }
+            VideoCodecType::Vp9 => {
+                let payload = Vec::from(data.payload);
+                let keyframe = vp9::is_keyframe(&payload);
+                (payload, keyframe)
+            }
+            _ => {
+                let payload = self.to_avcc(&Vec::from(data.payload), timestamp)?;
+                // the filter only understands AVC NAL units.
+                let payload = self.nalu_filter.apply(payload, self.ctx.video_avc_config.as_ref());
+                let keyframe = avc::contains_idr(&payload);
+                (payload, keyframe)
+            }
+        };
+        if keyframe != (data.keyframe_type == KeyframeType::Keyframe) {
+            self.logger.debug(format!("Frame type in tag header disagrees with the NAL units at {} ms.", timestamp));
+        }
+        // the offsets are kept as in the tags; the edit list starts at the first one.
+        self.ctx.video_composition_shift.get_or_insert(data.composition_time);
+        let composition_time = data.composition_time;
+        let timescale = self.ctx.video_timescale;
+        let sample_ctx = SampleContextBuilder::new()
+            .set_decode_time(Timestamp::from_millis(timestamp as i64).ticks(timescale) as u64)
+            .set_sample_size(payload.len() as u32)
+            .set_composition_time_offset(Timestamp::from_millis(composition_time as i64).ticks(timescale) as i32)
+            .set_has_redundancy(false)
+            .set_is_leading(self.video_track.sequence_number == 1 && !self.video_durations.has_pending())
+            .set_is_keyframe(keyframe)
+            .set_is_non_sync(!keyframe)
+            .build();
+        let nominal = self.ctx.video_frame_duration();
+
+        let presentation_time = (timestamp as i64 + composition_time as i64).max(0) as u64;
+        if let VideoCodecType::Avc1 = codec_type {
+            self.captions.push(timestamp, presentation_time, caption::extract_cc_data(&payload, AVCC_LENGTH_SIZE));
+        }
+
+        Ok(self.queue_video_sample(sample_ctx, payload, nominal))
+    }
+
+    fn audio_decode_time(&self, timestamp: u64) -> u64 {
+        Timestamp::from_millis(timestamp as i64).ticks(self.ctx.audio_timescale) as u64
+    }
+
+    /// Duration of `samples` samples at `sample_rate`, in ticks of the audio track.
+    fn audio_duration(&self, samples: u32, sample_rate: u32) -> u32 {
+        Timestamp::new(samples as i64, sample_rate).ticks(self.ctx.audio_timescale) as u32
+    }
+
+    /// PCM and G.711 tags carry no frame structure; the duration follows from the payload length.
+    fn encode_pcm_sample(&mut self, timestamp: u64, parsed: PcmParseResult) -> Vec<u8> {
+        let duration = self.audio_duration(parsed.sample_count(), parsed.sample_rate);
+        let sample_ctx = SampleContextBuilder::new()
+            .set_decode_time(self.audio_decode_time(timestamp))
+            .set_sample_size(parsed.body.len() as u32)
+            .set_composition_time_offset(0)
+            .build();
+        self.queue_audio_sample(sample_ctx, parsed.body, duration)
+    }
+
+    /// Raw AAC frames from one tag, back to back from its timestamp.
+    fn encode_aac_frames(&mut self, timestamp: u64, frames: Vec<Vec<u8>>) -> Vec<u8> {
+        let duration = self.audio_duration(self.ctx.audio_samples_per_frame, self.ctx.audio_sample_rate);
+        let mut decode_time = self.audio_decode_time(timestamp);
+
+        let mut data = vec![];
+        for frame in frames {
+            let sample_ctx = SampleContextBuilder::new()
+                .set_decode_time(decode_time)
+                .set_sample_size(frame.len() as u32)
+                .set_composition_time_offset(0)
+                .build();
+            data.append(&mut self.queue_audio_sample(sample_ctx, frame, duration));
+            decode_time += duration as u64;
+        }
+        data
+    }
+
+    /// A tag may hold several MP3 frames, or part of one; every complete frame becomes a sample.
+    fn encode_mp3_frames(&mut self, timestamp: u64, body: &[u8]) -> Vec<u8> {
+        let mut decode_time = match self.mp3_next_decode_time {
+            Some(next) if self.mp3_frames.has_pending() => next,
+            _ => self.audio_decode_time(timestamp),
+        };
+
+        let mut data = vec![];
+        for (header, frame, crc) in self.mp3_frames.push(body) {
+            if crc == Some(false) {
+                self.logger.warn(format!("MP3 frame CRC mismatch at {} ms.", timestamp));
+            }
+            let duration = self.audio_duration(header.samples_per_frame(), header.sample_rate);
+            let sample_ctx = SampleContextBuilder::new()
+                .set_decode_time(decode_time)
+                .set_sample_size(frame.len() as u32)
+                .set_composition_time_offset(0)
+                .build();
+            data.append(&mut self.queue_audio_sample(sample_ctx, frame, duration));
+            decode_time += duration as u64;
+        }
+        self.mp3_next_decode_time = Some(decode_time);
+        data
+    }
+
+    /// Holds an audio sample back and returns the previous one, encoded, once its duration is known.
+    fn queue_audio_sample(&mut self, sample_ctx: SampleContext, payload: Vec<u8>, nominal: u32) -> Vec<u8> {
+        let (released, gap) = self.audio_durations.push(sample_ctx, payload, nominal, self.ctx.audio_timescale);
+        if let Some(gap) = gap {
+            self.logger.warn(format!("Audio gap of {} ticks at decode time {}.", gap.duration(), gap.expected));
+        }
+        released.map(|pending| self.encode_audio_pending(pending)).unwrap_or_default()
+    }
+
+    fn encode_audio_pending(&mut self, mut pending: PendingSample) -> Vec<u8> {
+        let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut pending.sample_ctx).serialize();
+        data.append(&mut Encoder::encode_mdat(pending.payload).serialize());
+        data
+    }
+
+    fn queue_video_sample(&mut self, sample_ctx: SampleContext, payload: Vec<u8>, nominal: u32) -> Vec<u8> {
+        let (released, gap) = self.video_durations.push(sample_ctx, payload, nominal, self.ctx.video_timescale);
+        if let Some(gap) = gap {
+            self.logger.warn(format!("Video gap of {} ticks at decode time {}.", gap.duration(), gap.expected));
+        }
+        released.map(|pending| self.encode_video_pending(pending)).unwrap_or_default()
+    }
+
+    fn encode_video_pending(&mut self, mut pending: PendingSample) -> Vec<u8> {
+        let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut pending.sample_ctx).serialize();
+        data.append(&mut Encoder::encode_mdat(pending.payload).serialize());
+        data
+    }
+
+    /// Tells the core there is no audio codec, so that the MIME type only names the video one.
+    fn send_audio_track_dropped(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.logger.warn("Speex and Nellymoser audio can't be carried in MP4 and is dropped; extract it with AudioExtractFilter.");
+        self.send(Packed {
+            packed_routing: Destination::Core,
+            packed_content: PackedContent::ToCore(
+                PackedContentToCore::DecoderConfig(
+                    MseDecoderConfig::AudioCodec(AudioCodecConfig::new(AudioCodecType::None, 0))
+                )
+            )
+        })
+    }
+
+    /// Encoders often repeat the sequence header; a changed one would need a new init segment.
+    fn skip_audio_sequence_header(&self, repeated: bool, timestamp: u64) {
+        if repeated {
+            self.logger.debug(format!("Repeated audio sequence header at {} ms ignored.", timestamp));
+        } else {
+            self.logger.warn(format!("Audio sequence header changed at {} ms; ignored, the track keeps its configuration.", timestamp));
+        }
+    }
+
+    /// Maps a tag timestamp onto the track's timeline, reporting discontinuities.
+    fn normalize_timestamp(&mut self, tag_type: &TagType, timestamp: u32) -> u64 {
+        let (timeline, track) = match tag_type {
+            TagType::Video => (&mut self.video_timeline, "Video"),
+            _ => (&mut self.audio_timeline, "Audio"),
+        };
+        let (output, discontinuity) = timeline.push(timestamp);
+        if let Some(discontinuity) = discontinuity {
+            Metrics::add(&self.logger.metrics().resyncs, 1);
+            self.logger.warn(format!(
+                "{} timestamp discontinuity: jumped {} ms at {} ms, rebased to {} ms.",
+                track, discontinuity.jump_ms, discontinuity.timestamp, discontinuity.output_ms
+            ));
+        }
+        output
+    }
+
+    /// Sends the samples still held, with their nominal durations, as the stream has ended.
+    fn flush_durations(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.ctx.is_header_sent() {
+            return Ok(());
+        }
+        if let Some(pending) = self.audio_durations.flush() {
+            let data = self.encode_audio_pending(pending);
+            self.send_raw_data(RemuxedData::Audio(data))?;
+        }
+        if let Some(pending) = self.video_durations.flush() {
+            let data = self.encode_video_pending(pending);
+            self.send_raw_data(RemuxedData::Video(data))?;
+        }
+        Ok(())
+    }
+
+    /// Composition time of the tag if it carries a video sample.
+    fn video_sample_composition_time(tag: &Tag) -> Option<i32> {
+        match tag.tag_header {
+            TagHeader::Video(ref header) if header.avc_packet_type == Some(1) => Some(header.composition_time.unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    /// The init segment carries the video edit list, so it waits until the first video sample is queued.
+    /// Past `MAX_INIT_SEGMENT_DELAY_MS` of queued tags, it goes out without one.
+    fn ready_for_header(&mut self, tag: Option<&Tag>) -> bool {
+        if self.ctx.video_composition_shift.is_some() {
+            return true;
+        }
+        let queued = || tag.into_iter().chain(self.tags.iter());
+        if let Some(shift) = queued().find_map(Self::video_sample_composition_time) {
+            self.ctx.video_composition_shift = Some(shift);
+            return true;
+        }
+        let first = queued().map(|tag| tag.timestamp).min().unwrap_or(0);
+        let last = queued().map(|tag| tag.timestamp).max().unwrap_or(0);
+        if last - first > MAX_INIT_SEGMENT_DELAY_MS {
+            self.logger.warn(format!("No video sample within {} ms; init segment sent without an edit list.", MAX_INIT_SEGMENT_DELAY_MS));
+            self.ctx.video_composition_shift = Some(0);
+            return true;
+        }
+        false
+    }
+
     fn remux(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.ctx.is_configured() && !self.ctx.is_header_sent() {
+        if self.ctx.is_configured() && !self.ctx.is_header_sent() && self.ready_for_header(None) {
             self.send_mpeg4_header()?;
             if let Some(tmp) = self._temp.take() {
                 self.send_raw_data(RemuxedData::Audio(tmp))?;
             }
         }
 
-        while let Some(ref tag) = self.tags.pop_front() {
+        while let Some(tag) = self.tags.pop_front() {
+            if self.ctx.is_configured() && !self.ctx.is_header_sent() && !self.ready_for_header(Some(&tag)) {
+                self.tags.push_front(tag);
+                return Ok(());
+            }
+            let tag = &tag;
             match tag.tag_type {
                 TagType::Audio => {
+                    if let TagHeader::Audio(ref header) = tag.tag_header {
+                        if ExtractedCodec::is_extracted(header.sound_format) && self.ctx.audio_track && !self.ctx.is_header_sent() {
+                            self.ctx.drop_audio_track();
+                            self.send_audio_track_dropped()?;
+                        }
+                    }
+                    if !self.ctx.audio_track {
+                        continue;
+                    }
+                    let timestamp = self.normalize_timestamp(&tag.tag_type, tag.timestamp);
                     let parsed = Parser::parse_audio(tag)?;
                     if self.ctx.is_configured() {
                         if !self.ctx.is_header_sent() {
@@ -130,47 +526,79 @@ impl Remuxer {
                         }
                         match parsed {
                             AudioParseResult::AacRaw(raw) => {
-                                let mut sample_ctx = SampleContextBuilder::new()
-                                    .set_decode_time(parse_timescale(tag.timestamp))
-                                    .set_sample_size(raw.len() as u32)
-                                    .set_sample_duration(parse_aac_timescale(self.ctx.audio_sample_rate))
-                                    .set_composition_time_offset(0)
-                                    .build();
-
-                                let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut sample_ctx).serialize();
-                                data.append(&mut Encoder::encode_mdat(Vec::from(raw)).serialize());
-                                self.send_raw_data(RemuxedData::Audio(data))?;
+                                let data = self.encode_aac_frames(timestamp, vec![Vec::from(raw)]);
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
+                            }
+                            AudioParseResult::AacAdts(frames) => {
+                                let data = self.encode_aac_frames(timestamp, frames.into_iter().map(|frame| frame.payload).collect());
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
                             }
                             AudioParseResult::Mp3(parsed) => {
-                                let mut sample_ctx = SampleContextBuilder::new()
-                                    .set_decode_time(parse_timescale(tag.timestamp))
-                                    .set_sample_size(parsed.body.len() as u32)
-                                    .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version))
+                                let data = self.encode_mp3_frames(timestamp, &parsed.body);
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
+                            }
+                            AudioParseResult::Mp3Partial(body) => {
+                                let data = self.encode_mp3_frames(timestamp, &body);
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
+                            }
+                            AudioParseResult::OpusRaw(raw) => {
+                                let raw = Vec::from(raw);
+                                let duration = match opus::packet_duration(&raw) {
+                                    Some(duration) => duration,
+                                    None => {
+                                        self.logger.warn(format!("Malformed Opus packet at {} ms, assuming 20 ms.", timestamp));
+                                        960
+                                    }
+                                };
+                                let sample_ctx = SampleContextBuilder::new()
+                                    .set_decode_time(self.audio_decode_time(timestamp))
+                                    .set_sample_size(raw.len() as u32)
                                     .set_composition_time_offset(0)
                                     .build();
-
-                                let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut sample_ctx).serialize();
-                                data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
-                                self.send_raw_data(RemuxedData::Audio(data))?;
+                                let duration = self.audio_duration(duration, OPUS_SAMPLE_RATE);
+                                let data = self.queue_audio_sample(sample_ctx, raw, duration);
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
+                            }
+                            AudioParseResult::Pcm(parsed) => {
+                                let data = self.encode_pcm_sample(timestamp, parsed);
+                                if !data.is_empty() {
+                                    self.send_raw_data(RemuxedData::Audio(data))?;
+                                }
                             }
-                            _ => {
-                                panic!("[Remuxer] Aac format header not set!")
+                            AudioParseResult::AacSequenceHeader(header) => {
+                                let repeated = self.ctx.audio_aac_info.iter().eq(header.raw.iter());
+                                self.skip_audio_sequence_header(repeated, timestamp);
                             }
+                            AudioParseResult::OpusHead(head) => {
+                                let repeated = self.ctx.audio_opus_head.as_ref() == Some(&head);
+                                self.skip_audio_sequence_header(repeated, timestamp);
+                            }
+                            AudioParseResult::Ignored => {}
                         }
                     } else {
                         let audio_codec_conf =  self.ctx.configure_audio_metadata(&parsed);
+                        for warning in self.ctx.take_warnings() {
+                            self.logger.warn(warning);
+                        }
 
-                        if let AudioParseResult::Mp3(parsed) = parsed {
-                            let mut sample_ctx = SampleContextBuilder::new()
-                                .set_decode_time(parse_timescale(tag.timestamp))
-                                .set_sample_size(parsed.body.len() as u32)
-                                .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version))
-                                .set_composition_time_offset(0)
-                                .build();
-
-                            let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut sample_ctx).serialize();
-                            data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
-                            self._temp = Some(data);
+                        let mut stashed = match parsed {
+                            AudioParseResult::Mp3(parsed) => self.encode_mp3_frames(timestamp, &parsed.body),
+                            AudioParseResult::Pcm(parsed) => self.encode_pcm_sample(timestamp, parsed),
+                            AudioParseResult::AacAdts(frames) => self.encode_aac_frames(timestamp, frames.into_iter().map(|frame| frame.payload).collect()),
+                            _ => vec![],
+                        };
+                        if !stashed.is_empty() {
+                            self._temp.get_or_insert_with(Vec::new).append(&mut stashed);
                         }
 
                         if let Some(conf) = audio_codec_conf {
@@ -186,6 +614,7 @@ impl Remuxer {
                     }
                 }
                 TagType::Video => {
+                    let timestamp = self.normalize_timestamp(&tag.tag_type, tag.timestamp);
                     let parsed = Parser::parse_video(tag)?;
                     if self.ctx.is_configured() {
                         if !self.ctx.is_header_sent() {
@@ -194,37 +623,54 @@ impl Remuxer {
                                 self.send_raw_data(RemuxedData::Video(tmp))?;
                             }
                         }
-                        if let VideoParseResult::Avc1(parsed) = parsed {
+                        let parsed = match parsed {
+                            VideoParseResult::Avc1(parsed) => Some((parsed, VideoCodecType::Avc1)),
+                            VideoParseResult::Hevc(parsed) => Some((parsed, VideoCodecType::Hevc)),
+                            VideoParseResult::Av1(parsed) => Some((parsed, VideoCodecType::Av1)),
+                            VideoParseResult::Vp9(parsed) => Some((parsed, VideoCodecType::Vp9)),
+                            VideoParseResult::Ignored => None,
+                        };
+                        if let Some((parsed, codec_type)) = parsed {
                             match parsed {
                                 Avc1ParseResult::AvcNalu(data) => {
-                                    let mut sample_ctx = SampleContextBuilder::new()
-                                        .set_decode_time(parse_timescale(tag.timestamp))
-                                        .set_sample_size(data.payload.len() as u32)
-                                        .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                        .set_composition_time_offset(0)
-                                        .set_has_redundancy(false)
-                                        .set_is_leading(self.video_track.sequence_number == 1)
-                                        .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                        .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                        .build();
-
-                                    let mut send_data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut sample_ctx).serialize();
-                                    send_data.append(&mut Encoder::encode_mdat(Vec::from(data.payload)).serialize());
-                                    self.send_raw_data(RemuxedData::Video(send_data))?;
+                                    let send_data = self.encode_video_sample(timestamp, data, codec_type)?;
+                                    if !send_data.is_empty() {
+                                        self.send_raw_data(RemuxedData::Video(send_data))?;
+                                    }
+                                    self.send_captions()?;
                                 }
-                                Avc1ParseResult::AvcSequenceHeader(_) => {
-                                    panic!("[Remuxer] Avc sequence header not set!")
+                                Avc1ParseResult::AvcSequenceHeader(header) => {
+                                    // encoders often repeat it; a changed one would need a new init segment.
+                                    if self.video_sequence_header.as_ref() == Some(&header) {
+                                        self.logger.debug(format!("Repeated video sequence header at {} ms ignored.", timestamp));
+                                    } else {
+                                        self.logger.warn(format!("Video sequence header changed at {} ms; ignored, the track keeps its configuration.", timestamp));
+                                    }
                                 }
                                 Avc1ParseResult::AvcEndOfSequence => {
-                                    // todo: handle end of sequence
-                                    println!("[Remuxer] End of sequence.")
+                                    self.logger.info("End of sequence.");
+                                    if let Some(pending) = self.video_durations.flush() {
+                                        let send_data = self.encode_video_pending(pending);
+                                        self.send_raw_data(RemuxedData::Video(send_data))?;
+                                    }
+                                    self.captions.flush();
+                                    self.send_captions()?;
                                 }
                             }
                         }
                     } else {
-                        // todo: make this method return video codec configuration.
                         // todo: for ctx.configure_audio_metadata(), do the same thing.
-                        if let Some(conf) = self.ctx.configure_video_metadata(&parsed) {
+                        let video_codec_conf = self.ctx.configure_video_metadata(&parsed)?;
+                        for warning in self.ctx.take_warnings() {
+                            self.logger.warn(warning);
+                        }
+                        if let Some(conf) = video_codec_conf {
+                            if let VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(ref header))
+                                | VideoParseResult::Hevc(Avc1ParseResult::AvcSequenceHeader(ref header))
+                                | VideoParseResult::Av1(Avc1ParseResult::AvcSequenceHeader(ref header))
+                                | VideoParseResult::Vp9(Avc1ParseResult::AvcSequenceHeader(ref header)) = parsed {
+                                self.video_sequence_header = Some(header.clone());
+                            }
                             self.send(
                                 Packed {
                                     packed_routing: Destination::Core,
@@ -235,6 +681,11 @@ impl Remuxer {
                                     )
                                 }
                             )?;
+                            // VP9 without a record is configured from its first key frame, which must not be lost.
+                            if let VideoParseResult::Vp9(Vp9ParseResult::AvcNalu(data)) = parsed {
+                                let stashed = self.encode_video_sample(timestamp, data, VideoCodecType::Vp9)?;
+                                self._temp_video = Some(stashed).filter(|stashed| !stashed.is_empty());
+                            }
                         }
                     }
                 }
@@ -249,53 +700,12 @@ impl Remuxer {
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         loop {
             if let Ok(received) = self.channel_receiver.recv() {
-                if let PackedContent::ToRemuxer(content) = received {
-                    match content {
-                        PackedContentToRemuxer::PushTag(tag) => {
-                            // println!("Pushed tag.");
-                            self.tags.push_back(tag);
-                        }
-                        PackedContentToRemuxer::PushFlvHeader(flv_header) => {
-                            println!("[Remuxer] Pushed flv header.");
-                            self.ctx.parse_flv_header(&flv_header);
-                            self.flv_header = Some(flv_header);
-                        }
-                        PackedContentToRemuxer::PushMetadata(metadata) => {
-                            println!("[Remuxer] Pushed metadata.");
-                            self.ctx.parse_metadata(&metadata);
-                            self.metadata = Some(metadata);
-                        }
-                        PackedContentToRemuxer::StartRemuxing => {
-                            println!("[Remuxer] Start remuxing.");
-                            self.set_remuxing(true)
-                        }
-                        PackedContentToRemuxer::StopRemuxing => {
-                            println!("[Remuxer] Stop remuxing.");
-                            self.set_remuxing(false)
-                        }
-                        PackedContentToRemuxer::CloseWorkerThread => {
-                            println!("[Remuxer] Closing remuxer thread.");
-                            return Ok(());
-                        }
-                        PackedContentToRemuxer::Now => { }
-                    }
-                }
-            } else {
-                println!("[Remuxer] Channel closed.");
-                break;
-            }
-
-            if !self.remuxing {
-                continue;
-            }
-
-            if self.ctx.is_metadata_complete() {
-                if self.remux().is_err() {
-                    println!("[Remuxer] Channel closed");
+                if !self.handle(received)? || !self.pump()? {
                     break;
                 }
             } else {
-                println!("[Remuxer] Not configured yet.");
+                self.logger.warn("Channel closed.");
+                break;
             }
         }
         Ok(())
@@ -308,4 +718,86 @@ impl Remuxer {
             }
         })
     }
-}
\ No newline at end of file
+}
+
+impl IStateMachine for Remuxer {
+    fn handle(&mut self, content: PackedContent) -> Result<bool, Box<dyn std::error::Error>> {
+        if let PackedContent::ToRemuxer(content) = content {
+            match content {
+                PackedContentToRemuxer::PushTag(tag) => {
+                    // println!("Pushed tag.");
+                    self.tags.push_back(tag);
+                }
+                PackedContentToRemuxer::PushFlvHeader(flv_header) => {
+                    self.logger.debug("Pushed flv header.");
+                    self.ctx.parse_flv_header(&flv_header);
+                    self.flv_header = Some(flv_header);
+                }
+                PackedContentToRemuxer::PushMetadata(metadata) => {
+                    self.logger.debug("Pushed metadata.");
+                    self.ctx.parse_metadata(&metadata);
+                    self.metadata = Some(metadata);
+                    if !self.ctx.audio_track {
+                        self.send_audio_track_dropped()?;
+                    }
+                }
+                PackedContentToRemuxer::Seek(time_ms) => {
+                    self.logger.info(format!("Seek to {} ms, resetting fragment sequence.", time_ms));
+                    self.tags.clear();
+                    self._temp = None;
+                    self._temp_video = None;
+                    self.mp3_frames.clear();
+                    self.mp3_next_decode_time = None;
+                    self.audio_durations.clear();
+                    self.video_durations.clear();
+                    self.audio_timeline.reset();
+                    self.video_timeline.reset();
+                    self.ctx.reset_fragment_sequence();
+                    self.audio_track.sequence_number = 1;
+                    self.video_track.sequence_number = 1;
+                    self.caption_track.sequence_number = 1;
+                    self.captions.reset();
+                    self.caption_end_ms = None;
+                    self.send(
+                        Packed {
+                            packed_routing: Destination::Core,
+                            packed_content: PackedContent::ToCore(PackedContentToCore::SeekCompleted(time_ms)),
+                        }
+                    )?;
+                }
+                PackedContentToRemuxer::StartRemuxing => {
+                    self.logger.info("Start remuxing.");
+                    self.set_remuxing(true)
+                }
+                PackedContentToRemuxer::StopRemuxing => {
+                    self.logger.info("Stop remuxing.");
+                    self.set_remuxing(false)
+                }
+                PackedContentToRemuxer::CloseWorkerThread => {
+                    self.logger.info("Closing worker thread.");
+                    // the receiving end may already be gone.
+                    let _ = self.flush_durations();
+                    return Ok(false);
+                }
+                PackedContentToRemuxer::Now => { }
+            }
+        }
+        Ok(true)
+    }
+
+    fn pump(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        if !self.remuxing {
+            return Ok(true);
+        }
+
+        if self.ctx.is_metadata_complete() {
+            if let Err(e) = self.remux() {
+                self.logger.error(format!("Remuxing failed: {}", e));
+                return Ok(false);
+            }
+        } else {
+            self.logger.debug("Not configured yet.");
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,71 @@
+/// FLV Speex is always wideband: 16 kHz, mono.
+pub const SPEEX_SAMPLE_RATE: u32 = 16000;
+/// Samples in one wideband frame, i.e. 20 ms.
+pub const SPEEX_FRAME_SIZE: u32 = 320;
+
+const SPEEX_MODE_WIDEBAND: i32 = 1;
+const SPEEX_HEADER_SIZE: usize = 80;
+
+/// The Speex header packet that starts an Ogg Speex stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeexHeader {
+    pub rate: i32,
+    pub mode: i32,
+    pub nb_channels: i32,
+    pub frame_size: i32,
+    pub frames_per_packet: i32,
+}
+
+impl SpeexHeader {
+    /// The header matching what Flash writes into FLV.
+    pub fn flv() -> Self {
+        Self {
+            rate: SPEEX_SAMPLE_RATE as i32,
+            mode: SPEEX_MODE_WIDEBAND,
+            nb_channels: 1,
+            frame_size: SPEEX_FRAME_SIZE as i32,
+            frames_per_packet: 1,
+        }
+    }
+
+    /// Little endian, as laid out by libspeex.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SPEEX_HEADER_SIZE);
+        data.extend_from_slice(b"Speex   ");
+        let mut version = [0u8; 20];
+        version[..3].copy_from_slice(b"1.2");
+        data.extend_from_slice(&version);
+        for value in [
+            // speex_version_id, header_size
+            1,
+            SPEEX_HEADER_SIZE as i32,
+            self.rate,
+            self.mode,
+            // mode_bitstream_version
+            4,
+            self.nb_channels,
+            // bitrate: unknown
+            -1,
+            self.frame_size,
+            // vbr
+            0,
+            self.frames_per_packet,
+            // extra_headers, reserved1, reserved2
+            0,
+            0,
+            0,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// The Vorbis-style comment packet that follows the header; only carries the vendor.
+pub fn comment_packet(vendor: &str) -> Vec<u8> {
+    let mut data = (vendor.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(vendor.as_bytes());
+    // user_comment_list_length
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data
+}
@@ -0,0 +1,110 @@
+/// Jumps between consecutive timestamps of a track larger than this, either way, are discontinuities.
+pub const DEFAULT_DISCONTINUITY_THRESHOLD_MS: u64 = 10_000;
+
+const WRAP: i64 = 1 << 32;
+
+/// A timestamp that didn't follow the previous one, and where it was moved to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Discontinuity {
+    /// As read from the tag.
+    pub timestamp: u32,
+    /// From the previous timestamp, once wraps are accounted for.
+    pub jump_ms: i64,
+    /// Where the timestamp lands on the output timeline.
+    pub output_ms: u64,
+}
+
+/// Puts the 32-bit millisecond timestamps of one track on a 64-bit timeline that never goes back.
+///
+/// Wraps after about 49.7 days are detected as a jump back by more than half the range.
+/// Larger jumps than the threshold, e.g. from an encoder restart, are rebased to continue one step
+/// after the previous timestamp; smaller steps back are held at the previous one.
+pub struct TimestampNormalizer {
+    threshold_ms: u64,
+    last_input: Option<u32>,
+    wraps: i64,
+    // added to the unwrapped timestamp; changed on every discontinuity.
+    offset: i64,
+    last_output: u64,
+    last_step: u64,
+    discontinuities: u32,
+}
+
+impl TimestampNormalizer {
+    pub fn new(threshold_ms: u64) -> Self {
+        Self {
+            threshold_ms,
+            last_input: None,
+            wraps: 0,
+            offset: 0,
+            last_output: 0,
+            last_step: 0,
+            discontinuities: 0,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold_ms: u64) {
+        self.threshold_ms = threshold_ms;
+    }
+
+    /// Number of discontinuities found so far.
+    pub fn discontinuities(&self) -> u32 {
+        self.discontinuities
+    }
+
+    /// Returns the output time of `timestamp`, and the discontinuity it starts, if any.
+    /// The first timestamp is kept as is.
+    pub fn push(&mut self, timestamp: u32) -> (u64, Option<Discontinuity>) {
+        let last_input = match self.last_input {
+            Some(last_input) => last_input,
+            None => {
+                self.last_input = Some(timestamp);
+                self.last_output = timestamp as u64;
+                return (self.last_output, None);
+            }
+        };
+
+        let mut wraps = self.wraps;
+        if timestamp > last_input && timestamp - last_input > u32::MAX / 2 && wraps > 0 {
+            // a late one from before the wrap; the next ones are still compared to the last after it.
+            wraps -= 1;
+        } else {
+            if timestamp < last_input && last_input - timestamp > u32::MAX / 2 {
+                self.wraps += 1;
+                wraps = self.wraps;
+            }
+            self.last_input = Some(timestamp);
+        }
+        let unwrapped = wraps * WRAP + timestamp as i64;
+        let jump_ms = unwrapped + self.offset - self.last_output as i64;
+
+        let mut discontinuity = None;
+        let output = if jump_ms.unsigned_abs() > self.threshold_ms {
+            let output = self.last_output + self.last_step;
+            self.offset = output as i64 - unwrapped;
+            self.discontinuities += 1;
+            discontinuity = Some(Discontinuity { timestamp, jump_ms, output_ms: output });
+            output
+        } else if jump_ms < 0 {
+            self.last_output
+        } else {
+            if jump_ms > 0 {
+                self.last_step = jump_ms as u64;
+            }
+            self.last_output + jump_ms as u64
+        };
+        self.last_output = output;
+        (output, discontinuity)
+    }
+
+    /// Forgets the timeline, e.g. on seek, so that the next timestamp is taken as is.
+    pub fn reset(&mut self) {
+        *self = Self { discontinuities: self.discontinuities, ..Self::new(self.threshold_ms) };
+    }
+}
+
+impl Default for TimestampNormalizer {
+    fn default() -> Self {
+        Self::new(DEFAULT_DISCONTINUITY_THRESHOLD_MS)
+    }
+}
@@ -0,0 +1,85 @@
+/// Timescale of FLV timestamps, and of the movie header and the caption track.
+pub const MILLISECONDS: u32 = 1000;
+/// The usual video timescale. Frame rates whose frame duration it can't count exactly, like 24000/1001,
+/// get a timescale of their own; see `FrameRate::timescale`.
+pub const VIDEO_TIMESCALE: u32 = 90000;
+
+/// A time as a count of ticks at a timescale.
+/// Rescaling multiplies before it divides, in 128 bits, so nothing is lost but the final rounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub value: i64,
+    pub timescale: u32,
+}
+
+impl Timestamp {
+    pub fn new(value: i64, timescale: u32) -> Self {
+        Self { value, timescale }
+    }
+
+    pub fn from_millis(milliseconds: i64) -> Self {
+        Self::new(milliseconds, MILLISECONDS)
+    }
+
+    /// Rounded towards negative infinity, so that ordered timestamps stay ordered.
+    pub fn rescale(&self, timescale: u32) -> Self {
+        if timescale == self.timescale {
+            return *self;
+        }
+        let value = (self.value as i128 * timescale as i128).div_euclid(self.timescale as i128);
+        Self::new(value as i64, timescale)
+    }
+
+    /// The value at `timescale`.
+    pub fn ticks(&self, timescale: u32) -> i64 {
+        self.rescale(timescale).value
+    }
+}
+
+/// Frames per second as `num / den`, where `den` is 1000, or 1001 for NTSC rates like 30000/1001.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl FrameRate {
+    pub fn new(num: u32, den: u32) -> Self {
+        Self { num, den }
+    }
+
+    /// Metadata and parameter sets give the rate as a float; it is turned into a fraction once, here.
+    pub fn from_fps(fps: f64) -> Option<Self> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return None;
+        }
+        let ntsc = (fps * 1.001).round();
+        if (fps - fps.round()).abs() >= 0.001 && (fps - ntsc / 1.001).abs() < 0.001 {
+            return Some(Self::new(ntsc as u32 * 1000, 1001));
+        }
+        let num = (fps * 1000.0).round() as u32;
+        (num > 0).then(|| Self::new(num, 1000))
+    }
+
+    /// 90000 when it makes every frame duration exact, otherwise the smallest multiple of the
+    /// numerator that still counts whole milliseconds.
+    pub fn timescale(&self) -> u32 {
+        if (VIDEO_TIMESCALE as u64 * self.den as u64).is_multiple_of(self.num as u64) {
+            return VIDEO_TIMESCALE;
+        }
+        let lcm = self.num as u64 / gcd(self.num as u64, MILLISECONDS as u64) * MILLISECONDS as u64;
+        u32::try_from(lcm).unwrap_or(VIDEO_TIMESCALE)
+    }
+
+    /// Ticks per frame at `timescale`.
+    pub fn frame_duration(&self, timescale: u32) -> u32 {
+        (timescale as u64 * self.den as u64 / self.num as u64) as u32
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
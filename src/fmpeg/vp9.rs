@@ -0,0 +1,232 @@
+use crate::fmpeg::codec_string;
+use crate::io::bit::BitReader;
+
+const FRAME_MARKER: u32 = 2;
+const FRAME_SYNC_CODE: u32 = 0x498342;
+const COLOR_SPACE_RGB: u32 = 7;
+
+/// `chromaSubsampling` values of the `vpcC` record.
+pub const CHROMA_420_VERTICAL: u8 = 0;
+pub const CHROMA_420_COLOCATED: u8 = 1;
+pub const CHROMA_422: u8 = 2;
+pub const CHROMA_444: u8 = 3;
+
+/// Minimum level for a luma picture size, from the VP9 level definitions.
+const LEVELS: [(u32, u8); 9] = [
+    (36864, 10),
+    (73728, 11),
+    (122880, 20),
+    (245760, 21),
+    (552960, 30),
+    (983040, 31),
+    (2228224, 40),
+    (8912896, 50),
+    (35651584, 60),
+];
+
+/// The payload of a VP9 sequence start tag, i.e. the `vpcC` record (VP Codec ISO Media File Format Binding, 2.2).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vp9CodecConfigurationRecord {
+    pub profile: u8,
+    pub level: u8,
+    pub bit_depth: u8,
+    pub chroma_subsampling: u8,
+    pub video_full_range_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub codec_initialization_data: Vec<u8>,
+}
+
+impl Vp9CodecConfigurationRecord {
+    /// Accepts the record with or without the version and flags of the `vpcC` full box;
+    /// muxers disagree on which one goes into the tag.
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        // version 1 and no flags; a record never starts with level 0 and bit depth 0.
+        let data = if data.len() >= 4 && data[..4] == [1, 0, 0, 0] { &data[4..] } else { data };
+        if data.len() < 8 {
+            return Err("VP9 codec configuration record truncated.".into());
+        }
+        let bit_depth = data[2] >> 4;
+        if ![8, 10, 12].contains(&bit_depth) {
+            return Err(format!("Invalid VP9 bit depth {}.", bit_depth).into());
+        }
+        let initialization_data_size = u16::from_be_bytes([data[6], data[7]]) as usize;
+        if data.len() < 8 + initialization_data_size {
+            return Err("VP9 codec initialization data truncated.".into());
+        }
+
+        Ok(Self {
+            profile: data[0],
+            level: data[1],
+            bit_depth,
+            chroma_subsampling: (data[2] >> 1) & 0x07,
+            video_full_range_flag: data[2] & 0x01 != 0,
+            colour_primaries: data[3],
+            transfer_characteristics: data[4],
+            matrix_coefficients: data[5],
+            codec_initialization_data: data[8..8 + initialization_data_size].to_vec(),
+        })
+    }
+
+    /// Derives the record from the header of a key frame, for streams that never send one.
+    pub fn from_frame_header(header: &Vp9FrameHeader) -> Self {
+        let luma_picture_size = header.width.saturating_mul(header.height);
+        let level = LEVELS.iter()
+            .find(|(max_size, _)| luma_picture_size <= *max_size)
+            .map_or(61, |(_, level)| *level);
+        let chroma_subsampling = match (header.subsampling_x, header.subsampling_y) {
+            (true, true) => CHROMA_420_VERTICAL,
+            (true, false) => CHROMA_422,
+            _ => CHROMA_444,
+        };
+
+        Self {
+            profile: header.profile,
+            level,
+            bit_depth: header.bit_depth,
+            chroma_subsampling,
+            video_full_range_flag: header.full_range,
+            // VP9 only signals the matrix.
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coefficients: header.matrix_coefficients(),
+            codec_initialization_data: vec![],
+        }
+    }
+
+    /// Codec string `vp09.PP.LL.DD`.
+    pub fn codec_string(&self) -> String {
+        codec_string::vp9(self)
+    }
+
+    /// The record without the full box version and flags.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.profile,
+            self.level,
+            (self.bit_depth << 4) | (self.chroma_subsampling << 1) | self.video_full_range_flag as u8,
+            self.colour_primaries,
+            self.transfer_characteristics,
+            self.matrix_coefficients,
+        ];
+        data.extend_from_slice(&(self.codec_initialization_data.len() as u16).to_be_bytes());
+        data.extend_from_slice(&self.codec_initialization_data);
+        data
+    }
+}
+
+/// The start of a VP9 uncompressed frame header (VP9 bitstream spec, 6.2).
+/// Size and colour config are only known for key frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vp9FrameHeader {
+    pub profile: u8,
+    pub show_existing_frame: bool,
+    pub key_frame: bool,
+    pub show_frame: bool,
+
+    pub bit_depth: u8,
+    pub color_space: u8,
+    pub full_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Vp9FrameHeader {
+    /// Parses the first frame of a sample; for a superframe, that is the first frame in it.
+    pub fn parse(frame: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BitReader::new(frame);
+        if reader.read_bits(2)? != FRAME_MARKER {
+            return Err("Invalid VP9 frame marker.".into());
+        }
+        let profile_low_bit = reader.read_bits(1)?;
+        let profile = ((reader.read_bits(1)? << 1) | profile_low_bit) as u8;
+        if profile == 3 {
+            // reserved_zero
+            reader.skip_bits(1)?;
+        }
+
+        let mut header = Self {
+            profile,
+            show_existing_frame: reader.read_bit()?,
+            key_frame: false,
+            show_frame: false,
+            bit_depth: 8,
+            color_space: 0,
+            full_range: false,
+            subsampling_x: true,
+            subsampling_y: true,
+            width: 0,
+            height: 0,
+        };
+        if header.show_existing_frame {
+            return Ok(header);
+        }
+
+        // frame_type is 0 for key frames.
+        header.key_frame = !reader.read_bit()?;
+        header.show_frame = reader.read_bit()?;
+        // error_resilient_mode
+        reader.skip_bits(1)?;
+        if !header.key_frame {
+            return Ok(header);
+        }
+
+        if reader.read_bits(24)? != FRAME_SYNC_CODE {
+            return Err("Invalid VP9 frame sync code.".into());
+        }
+        header.parse_color_config(&mut reader)?;
+        header.width = reader.read_bits(16)? + 1;
+        header.height = reader.read_bits(16)? + 1;
+        Ok(header)
+    }
+
+    fn parse_color_config(&mut self, reader: &mut BitReader) -> Result<(), Box<dyn std::error::Error>> {
+        if self.profile >= 2 {
+            self.bit_depth = if reader.read_bit()? { 12 } else { 10 };
+        }
+        let color_space = reader.read_bits(3)?;
+        self.color_space = color_space as u8;
+        let subsampling_signalled = self.profile == 1 || self.profile == 3;
+        if color_space != COLOR_SPACE_RGB {
+            self.full_range = reader.read_bit()?;
+            if subsampling_signalled {
+                self.subsampling_x = reader.read_bit()?;
+                self.subsampling_y = reader.read_bit()?;
+                // reserved_zero
+                reader.skip_bits(1)?;
+            }
+        } else {
+            self.full_range = true;
+            (self.subsampling_x, self.subsampling_y) = (false, false);
+            if subsampling_signalled {
+                reader.skip_bits(1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// ISO/IEC 23091-2 matrix coefficients for the VP9 color space.
+    pub fn matrix_coefficients(&self) -> u8 {
+        match self.color_space {
+            1 => 5,
+            2 => 1,
+            3 => 6,
+            4 => 7,
+            5 => 9,
+            6 => 3,
+            7 => 0,
+            _ => 2,
+        }
+    }
+}
+
+/// Whether the sample is a shown key frame, i.e. a sync sample.
+pub fn is_keyframe(sample: &[u8]) -> bool {
+    match Vp9FrameHeader::parse(sample) {
+        Ok(header) => header.key_frame && header.show_frame,
+        Err(_) => false,
+    }
+}
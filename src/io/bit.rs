@@ -35,9 +35,7 @@ impl BitIO {
     /// Why? because it's easier to read.
     #[inline]
     pub fn read_range(&self, start: usize, end: usize) -> u8 {
-        let mut mask: u8 = 0b11111111u8;
-        mask >>= start;
-        mask <<= 7 - end;
+        let mask: u8 = (0b11111111u8 >> start) & (0b11111111u8 << (7 - end));
         (self.byte & mask) >> (7 - end)
     }
 }
@@ -179,4 +177,249 @@ impl U32BitIO {
             self.write_at(i, (value & (1 << (end - i))) != 0);
         }
     }
-}
\ No newline at end of file
+}
+/// Removes emulation prevention bytes from a NAL unit (0x000003 -> 0x0000), yielding its RBSP.
+pub fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+/// Inserts emulation prevention bytes so the RBSP can be carried in a NAL unit.
+pub fn add_emulation_prevention(rbsp: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros >= 2 && byte <= 0x03 {
+            data.push(0x03);
+            zeros = 0;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        data.push(byte);
+    }
+    data
+}
+
+/// Streaming big-endian (MSB first) bit reader over a byte slice.
+/// Positions are counted in bits from the start of the slice.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0
+        }
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of bits left.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    #[inline]
+    pub fn is_byte_aligned(&self) -> bool {
+        self.position.is_multiple_of(8)
+    }
+
+    /// Skips to the next byte boundary.
+    #[inline]
+    pub fn byte_align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if count > self.remaining() {
+            return Err("Bit reader out of data.".into());
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_bit(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Reads up to 32 bits.
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, Box<dyn std::error::Error>> {
+        if count > 32 {
+            return Err("Cannot read more than 32 bits at once.".into());
+        }
+        Ok(self.read_bits_u64(count)? as u32)
+    }
+
+    /// Reads up to 64 bits, a byte at a time where possible.
+    pub fn read_bits_u64(&mut self, count: u32) -> Result<u64, Box<dyn std::error::Error>> {
+        if count > 64 {
+            return Err("Cannot read more than 64 bits at once.".into());
+        }
+        if count as usize > self.remaining() {
+            return Err("Bit reader out of data.".into());
+        }
+
+        let mut value = 0u64;
+        let mut left = count;
+        while left > 0 {
+            let byte = self.data[self.position / 8];
+            let available = 8 - (self.position % 8) as u32;
+            let taken = available.min(left);
+            let bits = (byte >> (available - taken)) & (0xFFu8 >> (8 - taken));
+            value = (value << taken) | bits as u64;
+            self.position += taken as usize;
+            left -= taken;
+        }
+        Ok(value)
+    }
+
+    /// Reads whole bytes; the reader must be byte aligned.
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        if !self.is_byte_aligned() {
+            return Err("Bit reader is not byte aligned.".into());
+        }
+        if count * 8 > self.remaining() {
+            return Err("Bit reader out of data.".into());
+        }
+        let start = self.position / 8;
+        self.position += count * 8;
+        Ok(&self.data[start..start + count])
+    }
+
+    /// Unsigned Exp-Golomb code, ue(v).
+    pub fn read_ue(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err("Invalid Exp-Golomb code.".into());
+            }
+        }
+        Ok(((1u64 << leading_zeros) - 1 + self.read_bits(leading_zeros)? as u64) as u32)
+    }
+
+    /// Signed Exp-Golomb code, se(v).
+    pub fn read_se(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let value = self.read_ue()? as i64;
+        if value % 2 == 0 {
+            Ok((-(value / 2)) as i32)
+        } else {
+            Ok(((value + 1) / 2) as i32)
+        }
+    }
+}
+
+/// Big-endian (MSB first) bit writer, the counterpart of `BitReader`.
+pub struct BitWriter {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            data: vec![],
+            position: 0
+        }
+    }
+
+    /// Number of bits written so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn is_byte_aligned(&self) -> bool {
+        self.position.is_multiple_of(8)
+    }
+
+    /// Pads with zero bits up to the next byte boundary.
+    #[inline]
+    pub fn byte_align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    #[inline]
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits_u64(bit as u64, 1);
+    }
+
+    /// Writes the lowest `count` bits of `value`, up to 32.
+    #[inline]
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        self.write_bits_u64(value as u64, count.min(32));
+    }
+
+    /// Writes the lowest `count` bits of `value`, up to 64.
+    pub fn write_bits_u64(&mut self, value: u64, count: u32) {
+        let mut left = count.min(64);
+        while left > 0 {
+            if self.position.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            let available = 8 - (self.position % 8) as u32;
+            let taken = available.min(left);
+            let bits = ((value >> (left - taken)) & ((1u64 << taken) - 1)) as u8;
+            *self.data.last_mut().unwrap() |= bits << (available - taken);
+            self.position += taken as usize;
+            left -= taken;
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.is_byte_aligned() {
+            self.data.extend_from_slice(bytes);
+            self.position += bytes.len() * 8;
+        } else {
+            for &byte in bytes {
+                self.write_bits(byte as u32, 8);
+            }
+        }
+    }
+
+    /// Unsigned Exp-Golomb code, ue(v).
+    pub fn write_ue(&mut self, value: u32) {
+        let code = value as u64 + 1;
+        let length = 64 - code.leading_zeros();
+        self.write_bits_u64(0, length - 1);
+        self.write_bits_u64(code, length);
+    }
+
+    /// Signed Exp-Golomb code, se(v).
+    pub fn write_se(&mut self, value: i32) {
+        let code = if value > 0 {
+            value as u32 * 2 - 1
+        } else {
+            value.unsigned_abs() * 2
+        };
+        self.write_ue(code);
+    }
+
+    /// Returns the written bytes; a trailing partial byte is padded with zeros.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
@@ -1,8 +1,11 @@
-mod flv;
-mod io;
-mod core;
-mod exchange;
-mod fmpeg;
+pub mod flv;
+pub mod io;
+pub mod core;
+pub mod exchange;
+pub mod fmpeg;
+pub mod stage;
+pub mod event;
+pub mod session;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -227,6 +230,7 @@ mod tests {
                     RemuxedData::Header(data) => data,
                     RemuxedData::Audio(data) => data,
                     RemuxedData::Video(data) => data,
+                    RemuxedData::Text(data) => data,
                 };
                 buf_written += output_file.write(&buf).unwrap();
                 // todo: not sure why ffmpeg cannot convert the output file.
@@ -247,4 +251,1509 @@ mod tests {
 
         println!("Done.");
     }
-}
\ No newline at end of file
+
+    fn push_flv_tag(buf: &mut Vec<u8>, tag_type: u8, timestamp: u32, body: &[u8]) {
+        let size = body.len() as u32;
+        buf.push(tag_type);
+        buf.extend_from_slice(&size.to_be_bytes()[1..]);
+        buf.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        buf.push((timestamp >> 24) as u8);
+        buf.extend_from_slice(&[0, 0, 0]);
+        buf.extend_from_slice(body);
+        buf.extend_from_slice(&(size + 11).to_be_bytes());
+    }
+
+    /// AVCDecoderConfigurationRecord with one SPS and one PPS, High profile at level 3.1.
+    const AVC_RECORD: [u8; 23] = [
+        0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0x00, 0x04, 0x67, 0x64, 0x00, 0x1F,
+        0x01, 0x00, 0x04, 0x68, 0xEE, 0x3C, 0xB0, 0xFD, 0xF8, 0xF8, 0x00,
+    ];
+
+    /// AVC sequence header tag body carrying `AVC_RECORD`.
+    fn avc_sequence_header() -> Vec<u8> {
+        let mut body = vec![0x17, 0, 0, 0, 0];
+        body.extend_from_slice(&AVC_RECORD);
+        body
+    }
+
+    /// FLV header with the given stream flags, followed by an onMetaData tag of numeric entries.
+    fn flv_with_metadata(flags: u8, entries: &[(&str, f64)]) -> Vec<u8> {
+        let mut buf = vec![b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0];
+        let mut script = vec![0x02, 0x00, 0x0A];
+        script.extend_from_slice(b"onMetaData");
+        script.push(0x08);
+        script.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (key, value) in entries {
+            script.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            script.extend_from_slice(key.as_bytes());
+            script.push(0x00);
+            script.extend_from_slice(&value.to_be_bytes());
+        }
+        script.extend_from_slice(&[0, 0, 9]);
+        push_flv_tag(&mut buf, 18, 0, &script);
+        buf
+    }
+
+    /// Handles what the workers send the core until `done` holds, giving up after two seconds.
+    fn run_until(core: &mut core::Core, mut done: impl FnMut(&mut core::Core) -> bool) {
+        for _ in 0..200 {
+            core.process_incoming().unwrap();
+            if done(core) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Video-only stream with one tag every 500 ms and a keyframe every second.
+    fn video_only_flv(tag_count: u32) -> Vec<u8> {
+        let mut buf = vec![b'F', b'L', b'V', 1, 0x01, 0, 0, 0, 9, 0, 0, 0, 0];
+        for i in 0..tag_count {
+            let frame_type = if i % 2 == 0 { 0x17 } else { 0x27 };
+            push_flv_tag(&mut buf, 9, i * 500, &[frame_type, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_seek() {
+        let buf = video_only_flv(6);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut decoder = Decoder::new(VecDeque::from(buf.clone()));
+        decoder.set_seekable(true);
+        exchange::ExchangeRegistrable::set_exchange(&mut decoder, sender);
+        decoder.decode_header().unwrap();
+        while decoder.decode_body_once().is_ok() {}
+        while receiver.try_recv().is_ok() {}
+
+        decoder.seek(1700).unwrap();
+        match receiver.try_recv().unwrap().packed_content {
+            exchange::PackedContent::ToDemuxer(exchange::PackedContentToDemuxer::Seek(time_ms)) => assert_eq!(time_ms, 1000),
+            _ => panic!("expected a seek marker"),
+        }
+        decoder.decode_body_once().unwrap();
+        match receiver.try_recv().unwrap().packed_content {
+            exchange::PackedContent::ToDemuxer(exchange::PackedContentToDemuxer::PushTag(tag)) => assert_eq!(tag.timestamp, 1000),
+            _ => panic!("expected a tag"),
+        }
+
+        decoder.seek(0).unwrap();
+        let _ = receiver.try_recv();
+        decoder.decode_body_once().unwrap();
+        match receiver.try_recv().unwrap().packed_content {
+            exchange::PackedContent::ToDemuxer(exchange::PackedContentToDemuxer::PushTag(tag)) => assert_eq!(tag.timestamp, 0),
+            _ => panic!("expected a tag"),
+        }
+
+        // not seekable by default: consumed bytes are dropped, so there is nothing to go back to.
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut live = Decoder::new(VecDeque::from(buf));
+        exchange::ExchangeRegistrable::set_exchange(&mut live, sender);
+        live.decode_header().unwrap();
+        while live.decode_body_once().is_ok() {}
+        assert!(live.seek(0).is_err());
+    }
+
+    struct PanickingFilter;
+
+    impl stage::ITagFilter for PanickingFilter {
+        fn filter_tag(&mut self, _tag: flv::tag::Tag) -> Option<flv::tag::Tag> {
+            panic!("filter failure");
+        }
+    }
+
+    #[test]
+    fn test_session_pool() {
+        let pool = session::SessionPool::new(2);
+
+        // both sessions land on the same worker.
+        let faulty = stage::PipelineBuilder::new()
+            .filter(1, Box::new(PanickingFilter))
+            .build_session(&pool, 1, VecDeque::from(video_only_flv(4)))
+            .unwrap();
+        let healthy = stage::PipelineBuilder::new()
+            .build_session(&pool, 3, VecDeque::from(video_only_flv(4)))
+            .unwrap();
+        assert_eq!(healthy.session_id(), Some(3));
+        assert!(stage::PipelineBuilder::new().build_session(&pool, 3, VecDeque::new()).is_err());
+
+        faulty.start().unwrap();
+        healthy.start().unwrap();
+
+        for _ in 0..200 {
+            if !pool.is_open(1) && healthy.get_metrics().video_tags_decoded == 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!pool.is_open(1));
+        assert!(pool.is_open(3));
+        assert_eq!(healthy.get_metrics().video_tags_decoded, 4);
+        assert_eq!(faulty.get_metrics().errors, 1);
+
+        healthy.drop_all_workers().unwrap();
+        for _ in 0..200 {
+            if !pool.is_open(3) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!pool.is_open(3));
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_demuxer_interleaving() {
+        use crate::exchange::{ExchangeRegistrable, IStateMachine, PackedContent, PackedContentToDemuxer, PackedContentToRemuxer};
+
+        // audio + video header, all video tags first, then all audio tags.
+        let mut buf = vec![b'F', b'L', b'V', 1, 0x05, 0, 0, 0, 9, 0, 0, 0, 0];
+        for i in 0..10u32 {
+            push_flv_tag(&mut buf, 9, i * 40, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        }
+        for i in 0..20u32 {
+            push_flv_tag(&mut buf, 8, i * 20, &[0xAF, 1, 0x21]);
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut decoder = Decoder::new(VecDeque::from(buf));
+        decoder.set_exchange(sender);
+        decoder.decode_header().unwrap();
+        while decoder.decode_body_once().is_ok() {}
+        let tags: Vec<_> = receiver.try_iter().filter_map(|packed| match packed.packed_content {
+            PackedContent::ToDemuxer(PackedContentToDemuxer::PushTag(tag)) => Some(tag),
+            _ => None,
+        }).collect();
+        assert_eq!(tags.len(), 30);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut demuxer = Demuxer::new();
+        demuxer.set_exchange(sender);
+        let header = flv::header::FlvHeader::new(*b"FLV", 1, true, true, 9);
+        demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::PushFlvHeader(header))).unwrap();
+        demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::StartDemuxing)).unwrap();
+
+        let mut sent = vec![];
+        for tag in tags {
+            demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::PushTag(tag))).unwrap();
+            demuxer.pump().unwrap();
+            sent.extend(receiver.try_iter().filter_map(|packed| match packed.packed_content {
+                PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(tag)) => Some((tag.timestamp, matches!(tag.tag_type, TagType::Audio))),
+                _ => None,
+            }));
+        }
+        // the audio track lagged behind but stayed within the window, so every tag went out in
+        // timestamp order, audio first on ties; the last audio tag waits for video that never comes.
+        // Tags are (timestamp, is audio).
+        let mut expected: Vec<(u32, bool)> = (0..20u32).map(|i| (i * 20, true))
+            .chain((0..10u32).map(|i| (i * 40, false)))
+            .collect();
+        expected.sort_by_key(|&(timestamp, audio)| (timestamp, !audio));
+        let last = expected.pop().unwrap();
+        assert_eq!(sent, expected);
+
+        demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::StopDemuxing)).unwrap();
+        let flushed: Vec<_> = receiver.try_iter().filter_map(|packed| match packed.packed_content {
+            PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(tag)) => Some((tag.timestamp, matches!(tag.tag_type, TagType::Audio))),
+            _ => None,
+        }).collect();
+        assert_eq!(flushed, vec![last]);
+
+        // a track that never shows up only holds the others back for the configured delta.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut demuxer = Demuxer::new();
+        demuxer.set_exchange(sender);
+        demuxer.set_max_interleave_delta(100);
+        let header = flv::header::FlvHeader::new(*b"FLV", 1, true, true, 9);
+        demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::PushFlvHeader(header))).unwrap();
+        demuxer.handle(PackedContent::ToDemuxer(PackedContentToDemuxer::StartDemuxing)).unwrap();
+        let mut decoder = Decoder::new(VecDeque::from(video_only_flv(10)));
+        let (tag_sender, tag_receiver) = std::sync::mpsc::channel();
+        decoder.set_exchange(tag_sender);
+        decoder.decode_header().unwrap();
+        while decoder.decode_body_once().is_ok() {}
+        for packed in tag_receiver.try_iter() {
+            demuxer.handle(packed.packed_content).unwrap();
+            demuxer.pump().unwrap();
+        }
+        let timestamps: Vec<u32> = receiver.try_iter().filter_map(|packed| match packed.packed_content {
+            PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(tag)) => Some(tag.timestamp),
+            _ => None,
+        }).collect();
+        // tags run from 0 to 4500 ms; only the newest one still fits in the 100 ms window.
+        assert_eq!(timestamps, (0..9).map(|i| i * 500).collect::<Vec<u32>>());
+
+        // the builder hands the delta to the demuxer, on dedicated threads and in a session alike.
+        let mut buf = video_only_flv(10);
+        buf[4] = 0x05;
+        let pool = session::SessionPool::new(1);
+        for session in [false, true] {
+            let seen = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            let builder = stage::PipelineBuilder::new()
+                .max_interleave_delta(100)
+                .filter(1, Box::new(DropFilter { drop_ms: u32::MAX, seen: seen.clone() }));
+            let mut core = if session {
+                builder.build_session(&pool, 1, VecDeque::from(buf.clone())).unwrap()
+            } else {
+                builder.build(VecDeque::from(buf.clone())).core
+            };
+            core.start().unwrap();
+            run_until(&mut core, |_| seen.lock().unwrap().len() == 9);
+            assert_eq!(*seen.lock().unwrap(), (0..9).map(|i| i * 500).collect::<Vec<u32>>());
+            core.drop_all_workers().unwrap();
+        }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_avc_decoder_configuration_record() {
+        use crate::fmpeg::avc::AvcDecoderConfigurationRecord;
+
+        let raw = AVC_RECORD;
+        let record = AvcDecoderConfigurationRecord::parse(&raw).unwrap();
+        assert_eq!(record.avc_profile_indication, 100);
+        assert_eq!(record.avc_level_indication, 31);
+        assert_eq!(record.nal_length_size(), 4);
+        assert_eq!(record.sequence_parameter_sets, vec![vec![0x67, 0x64, 0x00, 0x1F]]);
+        assert_eq!(record.picture_parameter_sets, vec![vec![0x68, 0xEE, 0x3C, 0xB0]]);
+        let extension = record.high_profile_extension.as_ref().unwrap();
+        assert_eq!(extension.chroma_format, 1);
+        assert_eq!(extension.bit_depth_luma_minus8, 0);
+        assert_eq!(record.serialize(), raw.to_vec());
+
+        // no extension for high profile is tolerated, truncation and missing parameter sets are not.
+        assert!(AvcDecoderConfigurationRecord::parse(&raw[..19]).unwrap().high_profile_extension.is_none());
+        assert!(AvcDecoderConfigurationRecord::parse(&raw[..10]).is_err());
+        assert!(AvcDecoderConfigurationRecord::parse(&[0x00, 0x64, 0x00, 0x1F, 0xFF, 0xE0]).is_err());
+        assert!(AvcDecoderConfigurationRecord::parse(&[0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE0, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_sps() {
+        use crate::fmpeg::avc::SequenceParameterSet;
+        use crate::fmpeg::parser::{Avc1ParseResult, VideoParseResult};
+
+        // high profile 1920x1088 cropped to 1080, SAR 4:3, 25 fps, bt.709, with emulation prevention bytes.
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xFF, 0xC0, 0x01, 0x00, 0x00,
+            0xDA, 0x80, 0x80, 0x80, 0xA0, 0x00, 0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x06, 0x50, 0x80,
+        ];
+        let parsed = SequenceParameterSet::parse(&sps).unwrap();
+        assert_eq!((parsed.coded_width, parsed.coded_height), (1920, 1088));
+        assert_eq!((parsed.width, parsed.height), (1920, 1080));
+        assert_eq!((parsed.sar_width, parsed.sar_height), (4, 3));
+        assert_eq!(parsed.frame_rate(), Some(25.0));
+        assert!(!parsed.interlaced);
+        assert_eq!(parsed.colour_description.as_ref().unwrap().colour_primaries, 1);
+        assert!(SequenceParameterSet::parse(&sps[..8]).is_err());
+
+        let mut avcc = vec![0x01, 0x64, 0x00, 0x28, 0xFF, 0xE1, 0x00, sps.len() as u8];
+        avcc.extend_from_slice(&sps);
+        avcc.extend_from_slice(&[0x01, 0x00, 0x04, 0x68, 0xEE, 0x3C, 0xB0]);
+        let mut ctx = RemuxContext::new();
+        ctx.width = 640.0;
+        ctx.height = 360.0;
+        let header = VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(VecDeque::from(avcc)));
+        assert!(ctx.configure_video_metadata(&header).unwrap().is_some());
+        assert_eq!((ctx.width, ctx.height, ctx.fps), (1920.0, 1080.0, 25.0));
+        assert_eq!(ctx.display_width(), 2560.0);
+        assert_eq!(ctx.take_warnings().len(), 2);
+    }
+
+    #[test]
+    fn test_bit_reader_writer() {
+        use crate::io::bit::{add_emulation_prevention, remove_emulation_prevention, BitReader, BitWriter};
+
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_ue(0);
+        writer.write_ue(7);
+        writer.write_se(-3);
+        writer.write_se(4);
+        writer.write_bits_u64(0x1_2345_6789, 33);
+        writer.write_bit(true);
+        writer.byte_align();
+        writer.write_bytes(&[0xAB, 0xCD]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_ue().unwrap(), 0);
+        assert_eq!(reader.read_ue().unwrap(), 7);
+        assert_eq!(reader.read_se().unwrap(), -3);
+        assert_eq!(reader.read_se().unwrap(), 4);
+        assert_eq!(reader.read_bits_u64(33).unwrap(), 0x1_2345_6789);
+        assert!(reader.read_bit().unwrap());
+        assert!(reader.read_bytes(1).is_err());
+        reader.byte_align();
+        assert_eq!(reader.read_bytes(2).unwrap(), &[0xAB, 0xCD]);
+        assert_eq!(reader.remaining(), 0);
+        assert!(reader.read_bit().is_err());
+
+        let rbsp = [0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03, 0x42];
+        let nal = add_emulation_prevention(&rbsp);
+        assert_eq!(nal, vec![0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x03, 0x42]);
+        assert_eq!(remove_emulation_prevention(&nal), rbsp.to_vec());
+    }
+
+    #[test]
+    fn test_audio_specific_config() {
+        use crate::fmpeg::aac::AudioSpecificConfig;
+        use crate::io::bit::BitWriter;
+
+        let lc = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+        assert_eq!((lc.audio_object_type, lc.output_sampling_frequency(), lc.output_channels()), (2, 44100, 2));
+        assert_eq!((lc.samples_per_frame(), lc.codec_object_type()), (1024, 2));
+
+        // HE-AAC v1, explicit signalling: 24 kHz core, 48 kHz output.
+        let mut writer = BitWriter::new();
+        for (value, bits) in [(5, 5), (6, 4), (2, 4), (3, 4), (2, 5), (0, 3)] {
+            writer.write_bits(value, bits);
+        }
+        let he_aac = AudioSpecificConfig::parse(&writer.into_bytes()).unwrap();
+        assert_eq!((he_aac.audio_object_type, he_aac.output_sampling_frequency()), (2, 48000));
+        assert_eq!((he_aac.samples_per_frame(), he_aac.codec_object_type()), (2048, 5));
+
+        // HE-AAC v2, backward compatible signalling: mono core, stereo output.
+        let mut writer = BitWriter::new();
+        for (value, bits) in [(2, 5), (6, 4), (1, 4), (0, 3), (0x2B7, 11), (5, 5), (1, 1), (3, 4), (0x548, 11), (1, 1)] {
+            writer.write_bits(value, bits);
+        }
+        let he_aac_v2 = AudioSpecificConfig::parse(&writer.into_bytes()).unwrap();
+        assert!(he_aac_v2.sbr_present && he_aac_v2.ps_present);
+        assert_eq!((he_aac_v2.output_channels(), he_aac_v2.output_sampling_frequency(), he_aac_v2.codec_object_type()), (2, 48000, 29));
+
+        // escaped object type and explicit sampling frequency.
+        let mut writer = BitWriter::new();
+        for (value, bits) in [(31, 5), (10, 6), (15, 4), (12345, 24), (2, 4)] {
+            writer.write_bits(value, bits);
+        }
+        let escaped = AudioSpecificConfig::parse(&writer.into_bytes()).unwrap();
+        assert_eq!((escaped.audio_object_type, escaped.sampling_frequency, escaped.channel_configuration), (42, 12345, 2));
+
+        // 5.1 through a program config element: front SCE + CPE, back CPE, one LFE.
+        let mut writer = BitWriter::new();
+        for (value, bits) in [(2, 5), (3, 4), (0, 4), (0, 3)] {
+            writer.write_bits(value, bits);
+        }
+        for (value, bits) in [(0, 4), (1, 2), (3, 4), (2, 4), (0, 4), (1, 4), (1, 2), (0, 3), (0, 4), (0, 3)] {
+            writer.write_bits(value, bits);
+        }
+        for (value, bits) in [(0, 5), (0x11, 5), (0x12, 5), (0, 4)] {
+            writer.write_bits(value, bits);
+        }
+        writer.byte_align();
+        writer.write_bits(0, 8);
+        let surround = AudioSpecificConfig::parse(&writer.into_bytes()).unwrap();
+        assert_eq!(surround.output_channels(), 6);
+
+        assert!(AudioSpecificConfig::parse(&[0x12]).is_err());
+        assert!(AudioSpecificConfig::parse(&[0x16, 0x90]).is_err());
+    }
+
+    #[test]
+    fn test_annex_b_conversion() {
+        use crate::fmpeg::avc::{is_annex_b, to_annex_b, to_avcc};
+
+        let annex_b = [0, 0, 0, 1, 0x09, 0xF0, 0, 0, 1, 0x65, 0x88, 0x84, 0, 0, 0, 0, 1, 0x06, 0x05];
+        assert!(is_annex_b(&annex_b, 4));
+        let (avcc, _) = to_avcc(&annex_b, 4).unwrap();
+        assert_eq!(avcc, vec![0, 0, 0, 2, 0x09, 0xF0, 0, 0, 0, 3, 0x65, 0x88, 0x84, 0, 0, 0, 2, 0x06, 0x05]);
+        assert!(!is_annex_b(&avcc, 4));
+        assert_eq!(to_annex_b(&avcc, 4).unwrap(), vec![0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1, 0x65, 0x88, 0x84, 0, 0, 0, 1, 0x06, 0x05]);
+
+        // 2-byte lengths are widened; a 4-byte length of 256..511 starts like a start code but is not one.
+        assert_eq!(to_avcc(&[0, 2, 0x09, 0xF0, 0, 1, 0x65], 2).unwrap().0, vec![0, 0, 0, 2, 0x09, 0xF0, 0, 0, 0, 1, 0x65]);
+        let mut long = vec![0, 0, 1, 0];
+        long.extend(std::iter::repeat(0x41).take(256));
+        assert!(!is_annex_b(&long, 4));
+        assert_eq!(to_avcc(&long, 4).unwrap(), (long, 0));
+
+        assert!(to_avcc(&[0, 1, 0x65], 3).is_err());
+    }
+
+    #[test]
+    fn test_nalu_filter() {
+        use crate::fmpeg::avc::{contains_idr, AvcDecoderConfigurationRecord, NaluFilter, NaluIter, NaluType};
+
+        let record = AvcDecoderConfigurationRecord::parse(&[
+            0x01, 0x42, 0xC0, 0x1E, 0xFF, 0xE1, 0x00, 0x02, 0x67, 0x42, 0x01, 0x00, 0x02, 0x68, 0xCE,
+        ]).unwrap();
+        // AUD, SPS (same as avcC), PPS (new), SEI, IDR, filler
+        let sample = vec![
+            0, 0, 0, 2, 0x09, 0xF0,
+            0, 0, 0, 2, 0x67, 0x42,
+            0, 0, 0, 2, 0x68, 0xCF,
+            0, 0, 0, 2, 0x06, 0x05,
+            0, 0, 0, 2, 0x65, 0x88,
+            0, 0, 0, 2, 0x0C, 0xFF,
+        ];
+        let types: Vec<NaluType> = NaluIter::new(&sample, 4).map(|nalu| nalu.nalu_type()).collect();
+        assert_eq!(types, vec![
+            NaluType::AccessUnitDelimiter, NaluType::Sps, NaluType::Pps, NaluType::Sei, NaluType::IdrSlice, NaluType::Filler,
+        ]);
+        assert_eq!(NaluIter::new(&sample, 4).nth(4).unwrap().nal_ref_idc(), 3);
+        assert!(contains_idr(&sample));
+        assert!(!contains_idr(&sample[..24]));
+
+        assert_eq!(NaluFilter::new().apply(sample.clone(), Some(&record)), sample);
+        let filter = NaluFilter::new().strip_aud(true).strip_filler(true).strip_redundant_parameter_sets(true);
+        let filtered = filter.apply(sample.clone(), Some(&record));
+        assert_eq!(filtered, sample[12..30].to_vec());
+    }
+
+    /// Wraps CEA-608 field 1 byte pairs into a length-prefixed SEI NAL unit.
+    fn caption_sample(pairs: &[[u8; 2]]) -> Vec<u8> {
+        let mut t35 = vec![0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03, 0x40 | pairs.len() as u8, 0xFF];
+        for pair in pairs {
+            t35.extend_from_slice(&[0xFC, pair[0], pair[1]]);
+        }
+        t35.push(0xFF);
+
+        let mut nal = vec![0x06, 0x04, t35.len() as u8];
+        nal.append(&mut t35);
+        nal.push(0x80);
+
+        let mut sample = (nal.len() as u32).to_be_bytes().to_vec();
+        sample.append(&mut nal);
+        sample
+    }
+
+    #[test]
+    fn test_captions() {
+        use crate::fmpeg::caption::{extract_cc_data, to_srt, to_webvtt, CaptionEvent, CaptionExtractor};
+
+        const RCL: [u8; 2] = [0x14, 0x20];
+        const EOC: [u8; 2] = [0x14, 0x2F];
+        const EDM: [u8; 2] = [0x14, 0x2C];
+        const PAC_ROW_15: [u8; 2] = [0x14, 0x70];
+
+        // pop-on "HI" shown at 0 ms; control codes are doubled as broadcasters do.
+        let show = caption_sample(&[RCL, RCL, PAC_ROW_15, PAC_ROW_15, [b'H', b'I'], EOC, EOC]);
+        let clear = caption_sample(&[EDM, EDM]);
+        let triplets = extract_cc_data(&show, 4);
+        assert_eq!(triplets.len(), 7);
+        assert!(triplets.iter().all(|triplet| triplet.cc_valid && triplet.cc_type == 0));
+
+        // the clearing sample is decoded early but presented at 1000 ms.
+        let mut extractor = CaptionExtractor::new();
+        extractor.push(0, 0, extract_cc_data(&show, 4));
+        extractor.push(33, 1000, extract_cc_data(&clear, 4));
+        extractor.push(66, 66, vec![]);
+        assert!(extractor.take_events().is_empty());
+        extractor.push(1033, 1033, vec![]);
+        let events = extractor.take_events();
+        assert_eq!(events, vec![CaptionEvent { start_ms: 0, end_ms: 1000, text: "HI".to_string() }]);
+
+        assert_eq!(to_webvtt(&events), "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nHI\n\n");
+        assert_eq!(to_srt(&events), "1\n00:00:00,000 --> 00:00:01,000\nHI\n\n");
+    }
+
+    #[test]
+    fn test_hevc() {
+        use crate::fmpeg::hevc::{self, HevcDecoderConfigurationRecord, HevcSequenceParameterSet};
+        use crate::fmpeg::parser::{HevcParseResult, VideoParseResult};
+        use crate::io::bit::{add_emulation_prevention, BitWriter};
+
+        // main profile, level 3.1, 1920x1088 cropped to 1080, SAR 4:3, 25 fps.
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x4201, 16);
+        writer.write_bits(0b0000_0001, 8);
+        writer.write_bits(0b000_00001, 8);
+        writer.write_bits(0x6000_0000, 32);
+        writer.write_bits_u64(0xB000_0000_0000, 48);
+        writer.write_bits(93, 8);
+        for value in [0, 1, 1920, 1088] {
+            writer.write_ue(value);
+        }
+        writer.write_bit(true);
+        for value in [0, 0, 0, 4, 0, 0, 4] {
+            writer.write_ue(value);
+        }
+        writer.write_bit(true);
+        for _ in 0..9 {
+            writer.write_ue(0);
+        }
+        // scaling lists, amp, sao, pcm
+        writer.write_bits(0, 4);
+        writer.write_ue(0);
+        // long term refs, temporal mvp, strong intra smoothing, vui
+        writer.write_bits(0b0001, 4);
+        writer.write_bit(true);
+        writer.write_bits(255, 8);
+        writer.write_bits(4, 16);
+        writer.write_bits(3, 16);
+        writer.write_bits(0, 7);
+        writer.write_bit(true);
+        writer.write_bits(1, 32);
+        writer.write_bits(25, 32);
+        writer.write_bit(true);
+        let sps = add_emulation_prevention(&writer.into_bytes());
+
+        let parsed = HevcSequenceParameterSet::parse(&sps).unwrap();
+        assert_eq!((parsed.coded_width, parsed.coded_height), (1920, 1088));
+        assert_eq!((parsed.width, parsed.height), (1920, 1080));
+        assert_eq!((parsed.general_profile_idc, parsed.general_level_idc), (1, 93));
+        assert_eq!(parsed.frame_rate(), Some(25.0));
+        assert_eq!(parsed.display_width(), 2560.0);
+
+        let mut hvcc = vec![0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 93];
+        hvcc.extend_from_slice(&[0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F, 0x03]);
+        for (nal_unit_type, nal_unit) in [(32, vec![0x40, 0x01, 0x0C]), (33, sps.clone()), (34, vec![0x44, 0x01, 0xC1])] {
+            hvcc.extend_from_slice(&[0x80 | nal_unit_type, 0x00, 0x01]);
+            hvcc.extend_from_slice(&(nal_unit.len() as u16).to_be_bytes());
+            hvcc.extend_from_slice(&nal_unit);
+        }
+        let record = HevcDecoderConfigurationRecord::parse(&hvcc).unwrap();
+        assert_eq!(record.nal_length_size(), 4);
+        assert_eq!(record.codec_string(), "hvc1.1.6.L93.B0");
+        assert_eq!(record.serialize(), hvcc);
+        assert!(HevcDecoderConfigurationRecord::parse(&hvcc[..30]).is_err());
+
+        let mut ctx = RemuxContext::new();
+        let header = VideoParseResult::Hevc(HevcParseResult::AvcSequenceHeader(VecDeque::from(hvcc)));
+        let mut conf = ctx.configure_video_metadata(&header).unwrap().unwrap();
+        assert_eq!(conf.video_conf(), "hvc1.1.6.L93.B0");
+        assert!(matches!(ctx.video_codec_type, VideoCodecType::Hevc));
+        assert_eq!((ctx.width, ctx.height, ctx.fps), (1920.0, 1080.0, 25.0));
+
+        // an IDR_W_RADL slice after an AUD.
+        let sample = [0, 0, 0, 3, 0x46, 0x01, 0x10, 0, 0, 0, 3, 0x26, 0x01, 0xAF];
+        assert!(hevc::contains_irap(&sample));
+        assert!(!hevc::contains_irap(&sample[..7]));
+        assert_eq!(hevc::nal_unit_type(&sample[11..]), 19);
+    }
+
+    #[test]
+    fn test_av1() {
+        use crate::fmpeg::av1::{self, Av1CodecConfigurationRecord};
+        use crate::fmpeg::parser::{Av1ParseResult, VideoParseResult};
+        use crate::io::bit::BitWriter;
+
+        // main profile, level 4.0, 1280x720, 30 fps, bt.709.
+        let mut writer = BitWriter::new();
+        // profile, still picture, reduced header, timing info present
+        writer.write_bits(0b000001, 6);
+        writer.write_bits(1, 32);
+        writer.write_bits(30, 32);
+        writer.write_bits(0b1_1_0_0, 4);
+        writer.write_bits(0, 5);
+        writer.write_bits(0, 12);
+        writer.write_bits(8, 5);
+        writer.write_bit(false);
+        writer.write_bits(10, 4);
+        writer.write_bits(10, 4);
+        writer.write_bits(1279, 11);
+        writer.write_bits(719, 11);
+        // no frame ids, order hints of 7 bits, screen content tools chosen per frame
+        writer.write_bits(0b0000000010011110000, 19);
+        writer.write_bits(0b0_0_1, 3);
+        writer.write_bits(0x010101, 24);
+        writer.write_bits(0, 5);
+        let sequence_header = writer.into_bytes();
+
+        let mut av1c = vec![0x81, 0x08, 0x0C, 0x00, 0x0A, sequence_header.len() as u8];
+        av1c.extend_from_slice(&sequence_header);
+        let record = Av1CodecConfigurationRecord::parse(&av1c).unwrap();
+        assert_eq!(record.codec_string(), "av01.0.08M.08");
+        assert_eq!(record.serialize(), av1c);
+        let parsed = record.sequence_header().unwrap().unwrap();
+        assert_eq!((parsed.max_frame_width, parsed.max_frame_height), (1280, 720));
+        assert_eq!(parsed.frame_rate(), Some(30.0));
+        assert_eq!(parsed.colour_description.as_ref().unwrap().colour_primaries, 1);
+        assert!(Av1CodecConfigurationRecord::parse(&[0x01, 0x08, 0x0C, 0x00]).is_err());
+
+        let mut ctx = RemuxContext::new();
+        let header = VideoParseResult::Av1(Av1ParseResult::AvcSequenceHeader(VecDeque::from(av1c)));
+        let mut conf = ctx.configure_video_metadata(&header).unwrap().unwrap();
+        assert_eq!(conf.video_conf(), "av01.0.08M.08");
+        assert!(matches!(ctx.video_codec_type, VideoCodecType::Av1));
+        assert_eq!((ctx.width, ctx.height, ctx.fps), (1280.0, 720.0, 30.0));
+
+        // temporal delimiter, then a frame OBU holding a shown key frame.
+        let sample = [0x12, 0x00, 0x32, 0x02, 0b0001_0000, 0x00];
+        let stripped = av1::strip_temporal_delimiters(&sample);
+        assert_eq!(stripped, &sample[2..]);
+        assert!(av1::is_keyframe(&stripped, false));
+        assert!(!av1::is_keyframe(&[0x32, 0x02, 0b0011_0000, 0x00], false));
+        assert!(!av1::is_keyframe(&[0x12, 0x00], false));
+        assert_eq!(av1::read_leb128(&[0xE5, 0x8E, 0x26]), Some((624485, 3)));
+    }
+
+    #[test]
+    fn test_vp9() {
+        use crate::fmpeg::parser::{AvcNalu, KeyframeType, VideoParseResult, Vp9ParseResult};
+        use crate::fmpeg::vp9::{self, Vp9CodecConfigurationRecord, Vp9FrameHeader};
+        use crate::io::bit::BitWriter;
+
+        // profile 0 shown key frame, bt.709, 1280x720.
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x82, 8);
+        writer.write_bits(0x498342, 24);
+        writer.write_bits(0b0100, 4);
+        writer.write_bits(1279, 16);
+        writer.write_bits(719, 16);
+        let keyframe = writer.into_bytes();
+
+        let header = Vp9FrameHeader::parse(&keyframe).unwrap();
+        assert!(header.key_frame && header.show_frame);
+        assert_eq!((header.width, header.height, header.bit_depth), (1280, 720, 8));
+        assert!(vp9::is_keyframe(&keyframe));
+        assert!(!vp9::is_keyframe(&[0x86, 0x00]));
+
+        let derived = Vp9CodecConfigurationRecord::from_frame_header(&header);
+        assert_eq!(derived.codec_string(), "vp09.00.31.08");
+        assert_eq!(derived.matrix_coefficients, 1);
+        // 65536 x 65536, the largest a frame header can signal, is past every level.
+        let huge = Vp9FrameHeader { width: 65536, height: 65536, ..header.clone() };
+        assert_eq!(Vp9CodecConfigurationRecord::from_frame_header(&huge).level, 61);
+
+        // with and without the version and flags of the full box.
+        let vpcc = [0x01, 0x00, 0x00, 0x00, 0x02, 0x28, 0xA2, 0x09, 0x10, 0x09, 0x00, 0x00];
+        let record = Vp9CodecConfigurationRecord::parse(&vpcc).unwrap();
+        assert_eq!(record.codec_string(), "vp09.02.40.10");
+        assert_eq!(record.chroma_subsampling, vp9::CHROMA_420_COLOCATED);
+        assert_eq!(Vp9CodecConfigurationRecord::parse(&vpcc[4..]).unwrap(), record);
+        assert_eq!(record.serialize(), &vpcc[4..]);
+
+        // no record: the first key frame configures the track.
+        let mut ctx = RemuxContext::new();
+        let empty = VideoParseResult::Vp9(Vp9ParseResult::AvcSequenceHeader(VecDeque::new()));
+        assert!(ctx.configure_video_metadata(&empty).unwrap().is_none());
+        let frame = VideoParseResult::Vp9(Vp9ParseResult::AvcNalu(AvcNalu {
+            keyframe_type: KeyframeType::Keyframe,
+            composition_time: 0,
+            payload: VecDeque::from(keyframe),
+        }));
+        let mut conf = ctx.configure_video_metadata(&frame).unwrap().unwrap();
+        assert_eq!(conf.video_conf(), "vp09.00.31.08");
+        assert_eq!((ctx.width, ctx.height), (1280.0, 720.0));
+        assert_eq!(ctx.video_vpcc_info()[..4], [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_opus() {
+        use crate::exchange::AudioCodecConfig;
+        use crate::flv::header::AudioTagHeader;
+        use crate::fmpeg::mp4head::AudioSampleEntryBoxBuilder;
+        use crate::fmpeg::opus::{self, OpusHead};
+        use crate::fmpeg::parser::AudioParseResult;
+
+        // sound format 9, coded frames, `Opus`.
+        let mut decoder = Decoder::new(VecDeque::from(vec![0x91, b'O', b'p', b'u', b's']));
+        let mut header_size = 0;
+        let header = AudioTagHeader::parse(&mut decoder, &mut header_size).unwrap();
+        assert_eq!(header.fourcc, Some(*b"Opus"));
+        assert_eq!((header.aac_packet_type, header_size), (Some(1), 5));
+
+        let mut head_data = b"OpusHead".to_vec();
+        head_data.extend_from_slice(&[0x01, 0x02, 0x38, 0x01, 0x80, 0xBB, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let head = OpusHead::parse(&head_data).unwrap();
+        assert_eq!((head.channel_count, head.pre_skip, head.input_sample_rate), (2, 312, 48000));
+        assert_eq!(head.to_dops(), vec![0x00, 0x02, 0x01, 0x38, 0x00, 0x00, 0xBB, 0x80, 0x00, 0x00, 0x00]);
+        assert!(OpusHead::parse(&head_data[..18]).is_err());
+
+        // CELT 20 ms, SILK 60 ms twice, three CELT 2.5 ms frames.
+        assert_eq!(opus::packet_duration(&[0xF8, 0x00]), Some(960));
+        assert_eq!(opus::packet_duration(&[0x19, 0x00]), Some(5760));
+        assert_eq!(opus::packet_duration(&[0x83, 0x03]), Some(360));
+        assert_eq!(opus::packet_duration(&[0x1B, 0x03]), None);
+        assert_eq!(opus::packet_duration(&[]), None);
+
+        let mut ctx = RemuxContext::new();
+        let mut conf = ctx.configure_audio_metadata(&AudioParseResult::OpusHead(head.clone())).unwrap();
+        assert_eq!(conf.audio_conf(), "opus");
+        assert!(matches!(ctx.audio_codec_type, AudioCodecType::Opus));
+        assert_eq!((ctx.audio_sample_rate, ctx.audio_channels), (48000, 2));
+        assert_eq!(AudioCodecConfig::new(AudioCodecType::Opus, 0).audio_conf(), "opus");
+
+        let mut entry = AudioSampleEntryBoxBuilder::new(['O', 'p', 'u', 's'])
+            .sample_rate(48000.0)
+            .num_audio_channels(2)
+            .config_box(['d', 'O', 'p', 's'], head.to_dops())
+            .build();
+        let serialized = entry.serialize();
+        assert_eq!(serialized.len(), 55);
+        assert_eq!(&serialized[..8], &[0, 0, 0, 55, b'O', b'p', b'u', b's']);
+        assert_eq!(&serialized[36..44], &[0, 0, 0, 19, b'd', b'O', b'p', b's']);
+    }
+
+    #[test]
+    fn test_pcm() {
+        use crate::flv::header::{AudioTagHeader, TagHeader};
+        use crate::flv::tag::{NormalTagBody, Tag, TagBody};
+        use crate::fmpeg::mp4head::AudioSampleEntryBoxBuilder;
+        use crate::fmpeg::parser::{AudioParseResult, Parser, PcmFormat};
+        use crate::fmpeg::timestamp::{Timestamp, MILLISECONDS};
+
+        let audio_tag = |header: AudioTagHeader, body: Vec<u8>| Tag::new(
+            false, TagType::Audio, body.len() as u32 + 1, 0, 0, 0, 0,
+            TagHeader::Audio(header),
+            TagBody::Normal(NormalTagBody::Audio(VecDeque::from(body))),
+            None, None,
+        );
+
+        // 16-bit stereo little endian at 44.1 kHz: 4 bytes per sample frame.
+        let tag = audio_tag(AudioTagHeader::new(3, 3, true, true, None), vec![0; 4410 * 4]);
+        let parsed = match Parser::parse_audio(&tag).unwrap() {
+            AudioParseResult::Pcm(parsed) => parsed,
+            _ => panic!("expected pcm"),
+        };
+        assert_eq!(parsed.format, PcmFormat::LittleEndian);
+        assert_eq!((parsed.sample_rate, parsed.sample_size, parsed.channels), (44100, 16, 2));
+        assert_eq!(Timestamp::new(parsed.sample_count() as i64, parsed.sample_rate).ticks(MILLISECONDS), 100);
+
+        // 8-bit samples are made signed.
+        let tag = audio_tag(AudioTagHeader::new(0, 1, false, false, None), vec![0x80, 0xFF, 0x00]);
+        match Parser::parse_audio(&tag).unwrap() {
+            AudioParseResult::Pcm(parsed) => {
+                assert_eq!(parsed.format, PcmFormat::BigEndian);
+                assert_eq!(parsed.body, vec![0x00, 0x7F, 0x80]);
+                assert_eq!((parsed.sample_rate, parsed.sample_count()), (11025, 3));
+            }
+            _ => panic!("expected pcm"),
+        }
+
+        // G.711 is 8 kHz whatever the rate field says; 160 bytes are 20 ms.
+        let tag = audio_tag(AudioTagHeader::new(8, 0, false, false, None), vec![0xFF; 160]);
+        let parsed = match Parser::parse_audio(&tag).unwrap() {
+            AudioParseResult::Pcm(parsed) => parsed,
+            _ => panic!("expected g.711"),
+        };
+        assert_eq!(parsed.format, PcmFormat::MuLaw);
+        assert_eq!(Timestamp::new(parsed.sample_count() as i64, parsed.sample_rate).ticks(MILLISECONDS), 20);
+
+        let mut ctx = RemuxContext::new();
+        let mut conf = ctx.configure_audio_metadata(&AudioParseResult::Pcm(parsed)).unwrap();
+        assert_eq!(conf.audio_conf(), "ulaw");
+        assert!(matches!(ctx.audio_codec_type, AudioCodecType::Pcm(PcmFormat::MuLaw)));
+        assert_eq!((ctx.audio_sample_rate, ctx.audio_channels), (8000, 1));
+
+        let serialized = AudioSampleEntryBoxBuilder::new(PcmFormat::ALaw.sample_entry_type())
+            .sample_rate(8000.0)
+            .num_audio_channels(1)
+            .build()
+            .serialize();
+        assert_eq!(&serialized[..8], &[0, 0, 0, 36, b'a', b'l', b'a', b'w']);
+        assert_eq!(&serialized[24..26], &[0, 1]);
+    }
+
+    #[test]
+    fn test_speex_nellymoser() {
+        use crate::flv::header::{AudioTagHeader, TagHeader};
+        use crate::flv::tag::{NormalTagBody, Tag, TagBody};
+        use crate::fmpeg::extract::{AudioExtractFilter, ExtractedCodec};
+        use crate::fmpeg::ogg::crc32;
+        use crate::fmpeg::parser::{AudioParseResult, Parser};
+        use crate::stage::ITagFilter;
+
+        let audio_tag = |header: AudioTagHeader, timestamp: u32, body: Vec<u8>| Tag::new(
+            false, TagType::Audio, body.len() as u32 + 1, timestamp, 0, timestamp, 0,
+            TagHeader::Audio(header),
+            TagBody::Normal(NormalTagBody::Audio(VecDeque::from(body))),
+            None, None,
+        );
+
+        let mut filter = AudioExtractFilter::new();
+        let extraction = filter.extraction();
+        for timestamp in [0, 20, 40] {
+            assert!(filter.filter_tag(audio_tag(AudioTagHeader::new(11, 3, true, false, None), timestamp, vec![0x5A; 42])).is_none());
+        }
+        // other codecs pass through.
+        assert!(filter.filter_tag(audio_tag(AudioTagHeader::new(10, 3, true, true, Some(1)), 0, vec![0; 8])).is_some());
+
+        let mut extraction = extraction.lock().unwrap();
+        extraction.finish();
+        assert_eq!(extraction.codec(), Some(ExtractedCodec::Speex));
+        let data = extraction.take_data();
+
+        // speex header, comment, then all three frames on the last page.
+        let mut pages = vec![];
+        let mut offset = 0;
+        while offset < data.len() {
+            let page = &data[offset..];
+            assert_eq!(&page[..4], b"OggS");
+            let segments = page[26] as usize;
+            let size = 27 + segments + page[27..27 + segments].iter().map(|lacing| *lacing as usize).sum::<usize>();
+            let mut zeroed = page[..size].to_vec();
+            zeroed[22..26].fill(0);
+            assert_eq!(crc32(&zeroed).to_le_bytes(), page[22..26]);
+            pages.push((page[5], u64::from_le_bytes(page[6..14].try_into().unwrap()), page[27..27 + segments].to_vec()));
+            offset += size;
+        }
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], (0x02, 0, vec![80]));
+        assert_eq!(&data[28..36], b"Speex   ");
+        assert_eq!(pages[1].0, 0x00);
+        assert_eq!(pages[2], (0x04, 40 * 16 + 320, vec![42, 42, 42]));
+        assert_eq!(crc32(b"123456789"), 0x89A1897F);
+
+        let mut filter = AudioExtractFilter::new();
+        for timestamp in [0, 32] {
+            filter.filter_tag(audio_tag(AudioTagHeader::new(5, 0, true, false, None), timestamp, vec![timestamp as u8; 64]));
+        }
+        let extraction = filter.extraction();
+        let mut extraction = extraction.lock().unwrap();
+        assert_eq!(extraction.codec(), Some(ExtractedCodec::Nellymoser { sample_rate: 8000, channels: 1 }));
+        assert_eq!(extraction.take_data().len(), 128);
+        let sidecar = extraction.sidecar().unwrap();
+        assert!(sidecar.starts_with("codec=nellymoser\nsample_rate=8000\nchannels=1\n"));
+        assert!(sidecar.ends_with("0 64 0\n64 64 32\n"));
+
+        // left in the stream, they are skipped rather than failing the remuxer.
+        assert!(matches!(Parser::parse_audio(&audio_tag(AudioTagHeader::new(11, 3, true, false, None), 0, vec![0x5A; 42])).unwrap(), AudioParseResult::Ignored));
+        assert!(matches!(Parser::parse_audio(&audio_tag(AudioTagHeader::new(6, 2, true, true, None), 0, vec![0; 64])).unwrap(), AudioParseResult::Ignored));
+    }
+
+    #[test]
+    fn test_unsupported_audio_track() {
+        use crate::fmpeg::extract::AudioExtractFilter;
+
+        // Speex alongside AVC: the audio track is left out, whether its tags reach the remuxer or not.
+        let speex_flv = |entries: &[(&str, f64)]| {
+            let mut buf = flv_with_metadata(0x05, entries);
+            push_flv_tag(&mut buf, 9, 0, &avc_sequence_header());
+            for timestamp in (0..=120).step_by(40) {
+                push_flv_tag(&mut buf, 8, timestamp, &[0xB6, 0x5A, 0x5A, 0x5A]);
+                push_flv_tag(&mut buf, 9, timestamp, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+            }
+            buf
+        };
+        let pipelines = [
+            stage::PipelineBuilder::new().build(VecDeque::from(speex_flv(&[]))),
+            // with the tags extracted, only the metadata names the codec.
+            stage::PipelineBuilder::new()
+                .filter(1, Box::new(AudioExtractFilter::new()))
+                .build(VecDeque::from(speex_flv(&[("audiocodecid", 11.0)]))),
+        ];
+        for pipeline in pipelines {
+            let mut core = pipeline.core;
+            core.start().unwrap();
+            let mut header = None;
+            let mut fragments = (0, 0);
+            run_until(&mut core, |core| {
+                while let Ok(data) = core.consume() {
+                    match data {
+                        RemuxedData::Header(data) => header = Some(data),
+                        RemuxedData::Video(_) => fragments.0 += 1,
+                        RemuxedData::Audio(_) => fragments.1 += 1,
+                        _ => {}
+                    }
+                }
+                header.is_some() && fragments.0 >= 2
+            });
+            assert_eq!(core.try_get_mime_type(), Some("video/mp4; codecs=\"avc1.64001f\"".to_string()));
+            core.drop_all_workers().unwrap();
+
+            let header = header.unwrap();
+            assert!(header.windows(4).any(|window| window == b"vide"));
+            assert!(!header.windows(4).any(|window| window == b"soun"));
+            assert!(fragments.0 >= 2);
+            assert_eq!(fragments.1, 0);
+        }
+    }
+
+    #[test]
+    fn test_mp3_frames() {
+        use crate::flv::header::{AudioTagHeader, TagHeader};
+        use crate::flv::tag::{NormalTagBody, Tag, TagBody};
+        use crate::fmpeg::mp3::{crc16, samples_per_frame, Mp3FrameAssembler, Mp3FrameHeader, Mp3FrameIter};
+        use crate::fmpeg::parser::{AudioParseResult, Mp3Layer, Mp3Version, Parser};
+
+        let frame = |header: [u8; 4], fill: u8| {
+            let mut frame = header.to_vec();
+            frame.resize(Mp3FrameHeader::parse(&header).unwrap().frame_length(), fill);
+            frame
+        };
+
+        // MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, mono; with and without padding.
+        assert_eq!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0xC0]).unwrap().frame_length(), 417);
+        assert_eq!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0xC0]).unwrap().frame_length(), 418);
+        // MPEG-1 Layer I, 32 kbit/s: 4-byte slots, 384 samples.
+        let header = Mp3FrameHeader::parse(&[0xFF, 0xFF, 0x10, 0xC0]).unwrap();
+        assert_eq!((header.frame_length(), header.samples_per_frame()), (32, 384));
+        assert_eq!(samples_per_frame(Mp3Version::Mp10, Mp3Layer::L1), 384);
+        // MPEG-2 Layer III, 64 kbit/s, 22.05 kHz: half the samples.
+        let header = Mp3FrameHeader::parse(&[0xFF, 0xF3, 0x80, 0xC0]).unwrap();
+        assert_eq!((header.frame_length(), header.samples_per_frame()), (208, 576));
+        assert!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x00, 0xC0]).is_err());
+
+        // CRC over the last two header bytes and the side information.
+        assert_eq!(crc16(b"123456789"), 0xAEE7);
+        let mut protected = frame([0xFF, 0xFA, 0x90, 0xC0], 0x11);
+        let mut covered = vec![0x90, 0xC0];
+        covered.extend_from_slice(&protected[6..6 + 17]);
+        protected[4..6].copy_from_slice(&crc16(&covered).to_be_bytes());
+        assert_eq!(Mp3FrameIter::new(&protected).next().unwrap().check_crc(), Some(true));
+        protected[10] ^= 0xFF;
+        assert_eq!(Mp3FrameIter::new(&protected).next().unwrap().check_crc(), Some(false));
+
+        // two frames and the start of a third in one tag, the rest in the next one.
+        let first = frame([0xFF, 0xFB, 0x90, 0xC0], 1);
+        let second = frame([0xFF, 0xFB, 0x92, 0xC0], 2);
+        let third = frame([0xFF, 0xFB, 0x90, 0xC0], 3);
+        let mut tag_body = [first.clone(), second.clone(), third[..100].to_vec()].concat();
+        let mut iter = Mp3FrameIter::new(&tag_body);
+        assert_eq!(iter.by_ref().map(|frame| frame.data.len()).collect::<Vec<_>>(), vec![417, 418]);
+        assert_eq!(iter.remainder(), &third[..100]);
+
+        let mut assembler = Mp3FrameAssembler::new();
+        assert_eq!(assembler.push(&tag_body).len(), 2);
+        assert!(assembler.has_pending());
+        tag_body = [third[100..].to_vec(), first.clone()].concat();
+        let frames = assembler.push(&tag_body);
+        assert_eq!(frames.iter().map(|(_, data, _)| data.clone()).collect::<Vec<_>>(), vec![third.clone(), first.clone()]);
+        assert!(!assembler.has_pending());
+
+        // sound format 14 is MPEG-2.5 at 8 kHz; a continuation has no header to parse.
+        let audio_tag = |body: Vec<u8>| Tag::new(
+            false, TagType::Audio, body.len() as u32 + 1, 0, 0, 0, 0,
+            TagHeader::Audio(AudioTagHeader::new(14, 0, true, false, None)),
+            TagBody::Normal(NormalTagBody::Audio(VecDeque::from(body))),
+            None, None,
+        );
+        match Parser::parse_audio(&audio_tag(frame([0xFF, 0xE3, 0x18, 0xC0], 0))).unwrap() {
+            AudioParseResult::Mp3(parsed) => {
+                assert_eq!((parsed.sample_rate, parsed.version, parsed.body.len()), (8000, Mp3Version::Mp25, 72));
+            }
+            _ => panic!("expected mp3"),
+        }
+        assert!(matches!(Parser::parse_audio(&audio_tag(third[100..].to_vec())).unwrap(), AudioParseResult::Mp3Partial(_)));
+    }
+
+    #[test]
+    fn test_adts() {
+        use crate::flv::header::{AudioTagHeader, TagHeader};
+        use crate::flv::tag::{NormalTagBody, Tag, TagBody};
+        use crate::fmpeg::aac::{strip_adts, to_adts, AdtsHeader, AudioSpecificConfig};
+        use crate::fmpeg::parser::{AudioParseResult, Parser};
+
+        // AAC LC, 44.1 kHz, stereo.
+        let config = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+        let first = to_adts(&config, &[0xA1; 100]).unwrap();
+        let second = to_adts(&config, &[0xB2; 50]).unwrap();
+        assert_eq!(&first[..7], &[0xFF, 0xF1, 0x50, 0x80, 0x0D, 0x7F, 0xFC]);
+
+        let header = AdtsHeader::parse(&first).unwrap();
+        assert_eq!((header.audio_object_type, header.sampling_frequency_index, header.channel_configuration), (2, 4, 2));
+        assert_eq!((header.frame_length, header.header_length()), (107, 7));
+        assert_eq!(header.to_audio_specific_config(), vec![0x12, 0x10]);
+        assert_eq!(header.serialize(), first[..7].to_vec());
+
+        // a protected frame carries a CRC after the header.
+        let mut protected = first.clone();
+        protected[1] &= 0xFE;
+        protected[3..7].copy_from_slice(&AdtsHeader { frame_length: 109, ..header.clone() }.serialize()[3..7]);
+        protected.splice(7..7, [0xCC, 0xCC]);
+        let frames = strip_adts(&protected).unwrap();
+        assert_eq!((frames[0].header.header_length(), frames[0].payload.clone()), (9, vec![0xA1; 100]));
+
+        let mut unsupported = config.clone();
+        unsupported.audio_object_type = 23;
+        assert!(to_adts(&unsupported, &[0; 10]).is_err());
+
+        // two ADTS frames in one raw packet, and no sequence header before them.
+        let body = [first, second].concat();
+        let tag = Tag::new(
+            false, TagType::Audio, body.len() as u32 + 2, 0, 0, 0, 0,
+            TagHeader::Audio(AudioTagHeader::new(10, 3, true, true, Some(1))),
+            TagBody::Normal(NormalTagBody::Audio(VecDeque::from(body))),
+            None, None,
+        );
+        let parsed = Parser::parse_audio(&tag).unwrap();
+        match parsed {
+            AudioParseResult::AacAdts(ref frames) => {
+                assert_eq!(frames.iter().map(|frame| frame.payload.len()).collect::<Vec<_>>(), vec![100, 50]);
+            }
+            _ => panic!("expected adts"),
+        }
+
+        let mut ctx = RemuxContext::new();
+        ctx.audio_codec_id = 10;
+        let mut conf = ctx.configure_audio_metadata(&parsed).unwrap();
+        assert_eq!(conf.audio_conf(), "mp4a.40.2");
+        assert_eq!((ctx.audio_sample_rate, ctx.audio_channels), (44100, 2));
+        assert_eq!(ctx.audio_aac_info, vec![0x12, 0x10]);
+        assert_eq!(ctx.take_warnings().len(), 1);
+        assert!(ctx.configure_audio_metadata(&parsed).is_none());
+    }
+
+    #[test]
+    fn test_codec_string() {
+        use crate::exchange::{AudioCodecConfig, VideoCodecConfig};
+        use crate::fmpeg::codec_string;
+        use crate::fmpeg::mp4head::es_descriptor_payload;
+        use crate::fmpeg::parser::{AudioParseResult, Channel, Mp3Layer, Mp3ParseResult, Mp3Version};
+
+        // every byte keeps its two digits.
+        assert_eq!(codec_string::avc(0x64, 0x00, 0x1F), "avc1.64001f");
+        assert_eq!(VideoCodecConfig::new(0x42, 0xC0, 0x0A).video_conf(), "avc1.42c00a");
+
+        let mp3 = |version| AudioParseResult::Mp3(Mp3ParseResult {
+            version,
+            layer: Mp3Layer::L3,
+            sample_rate: 44100,
+            bitrate: 128,
+            channel: Channel::Stereo,
+            channel_extended: 0,
+            body: vec![],
+        });
+        let mut ctx = RemuxContext::new();
+        ctx.audio_codec_id = 2;
+        assert_eq!(ctx.configure_audio_metadata(&mp3(Mp3Version::Mp10)).unwrap().audio_conf(), "mp4a.6B");
+        assert_eq!(ctx.configure_audio_metadata(&mp3(Mp3Version::Mp25)).unwrap().audio_conf(), "mp4a.69");
+        assert_eq!(ctx.audio_mp3_object_type, 0x69);
+        assert_eq!(
+            es_descriptor_payload(0x69, &[]),
+            vec![0, 0, 0, 0, 0x03, 0x15, 0x00, 0x01, 0x00, 0x04, 0x0D, 0x69, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x06, 0x01, 0x02]
+        );
+
+        assert_eq!(AudioCodecConfig::new(AudioCodecType::Aac, 29).audio_conf(), "mp4a.40.29");
+        // unknown codecs no longer panic.
+        assert_eq!(AudioCodecConfig::new(AudioCodecType::None, 0).audio_conf(), "");
+        assert_eq!(codec_string::MP3_MPEG4, "mp4a.40.34");
+        assert_eq!((codec_string::FLAC, codec_string::AC3), ("flac", "ac-3"));
+
+        assert_eq!(
+            codec_string::mime_type(Some("avc1.64001f"), Some("mp4a.40.2")),
+            "video/mp4; codecs=\"avc1.64001f, mp4a.40.2\""
+        );
+        assert_eq!(codec_string::mime_type(None, Some("opus")), "audio/mp4; codecs=\"opus\"");
+    }
+
+    #[test]
+    fn test_composition_time_offset() {
+        use crate::fmpeg::encoder::DEFAULT_VIDEO_TRACK_ID;
+        use crate::fmpeg::timestamp::{Timestamp, VIDEO_TIMESCALE};
+        use crate::fmpeg::remux_context::{SampleContextBuilder, TrackContext, TrackType};
+
+        assert_eq!(Timestamp::from_millis(-40).ticks(VIDEO_TIMESCALE), -3600);
+        assert_eq!(Timestamp::from_millis(80).ticks(VIDEO_TIMESCALE), 7200);
+
+        let trun = |offset: i32| {
+            let mut ctx = RemuxContext::new();
+            let mut track = TrackContext::new(DEFAULT_VIDEO_TRACK_ID, TrackType::Video);
+            let mut sample_ctx = SampleContextBuilder::new()
+                .set_decode_time(0)
+                .set_sample_size(10)
+                .set_sample_duration(1000)
+                .set_composition_time_offset(offset)
+                .build();
+            let moof = Encoder::encode_moof(&mut ctx, &mut track, &mut sample_ctx).serialize();
+            let start = moof.windows(4).position(|window| window == b"trun").unwrap() - 4;
+            moof[start..start + 36].to_vec()
+        };
+
+        // B-frames presented before the first sample need the signed offsets of version 1.
+        let negative = trun(-960);
+        assert_eq!(negative[8], 1);
+        assert_eq!(i32::from_be_bytes(negative[32..36].try_into().unwrap()), -960);
+        let positive = trun(1920);
+        assert_eq!(positive[8], 0);
+        assert_eq!(u32::from_be_bytes(positive[32..36].try_into().unwrap()), 1920);
+    }
+
+    #[test]
+    fn test_sample_durations() {
+        use crate::fmpeg::duration::{DurationTracker, SampleGap};
+        use crate::fmpeg::remux_context::SampleContextBuilder;
+
+        let sample = |decode_time: u64| SampleContextBuilder::new().set_decode_time(decode_time).set_sample_size(1).build();
+        let mut tracker = DurationTracker::new();
+
+        // the first sample is held until the second one arrives.
+        let (released, gap) = tracker.push(sample(0), vec![0], 1000, 24000);
+        assert!(released.is_none() && gap.is_none());
+
+        // 40 ticks of jitter are smoothed out.
+        let (released, _) = tracker.push(sample(1040), vec![1], 1000, 24000);
+        let released = released.unwrap();
+        assert_eq!((released.sample_ctx.decode_time, released.sample_ctx.sample_duration), (0, 1000));
+
+        // further off, the real delta is used, and snapped time carries over.
+        let (released, gap) = tracker.push(sample(2300), vec![2], 1000, 24000);
+        let released = released.unwrap();
+        assert_eq!((released.sample_ctx.decode_time, released.sample_ctx.sample_duration), (1000, 1300));
+        assert_eq!(released.payload, vec![1]);
+        assert!(gap.is_none());
+
+        // missing samples are reported as a gap.
+        let (released, gap) = tracker.push(sample(5000), vec![3], 1000, 24000);
+        assert_eq!(released.unwrap().sample_ctx.sample_duration, 2700);
+        assert_eq!(gap, Some(SampleGap { expected: 3300, decode_time: 5000 }));
+        assert_eq!(tracker.gaps(), 1);
+
+        // the last sample falls back to the nominal duration.
+        let last = tracker.flush().unwrap();
+        assert_eq!((last.sample_ctx.decode_time, last.sample_ctx.sample_duration), (5000, 1000));
+        assert!(tracker.flush().is_none());
+
+        // without a frame rate, the last delta is used instead.
+        let mut tracker = DurationTracker::new();
+        tracker.push(sample(0), vec![], 0, 24000);
+        assert_eq!(tracker.push(sample(700), vec![], 0, 24000).0.unwrap().sample_ctx.sample_duration, 700);
+        assert_eq!(tracker.flush().unwrap().sample_ctx.sample_duration, 700);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        use crate::fmpeg::mp4frag::TrackFragmentDecodeTimeBox;
+        use crate::fmpeg::mp4head::{HandlerType, ISerializable};
+        use crate::fmpeg::timestamp::{FrameRate, Timestamp, MILLISECONDS, VIDEO_TIMESCALE};
+
+        // exact where f32 gave up: the last millisecond before the 32-bit wrap.
+        assert_eq!(Timestamp::from_millis(u32::MAX as i64).ticks(VIDEO_TIMESCALE), 386_547_056_550);
+        assert_eq!(Timestamp::new(1024, 44100).ticks(MILLISECONDS), 23);
+        assert_eq!(Timestamp::from_millis(-1).ticks(44100), -45);
+
+        let ntsc = FrameRate::from_fps(29.97).unwrap();
+        assert_eq!((ntsc.num, ntsc.den, ntsc.timescale()), (30000, 1001, VIDEO_TIMESCALE));
+        assert_eq!(ntsc.frame_duration(VIDEO_TIMESCALE), 3003);
+        // 23.976 doesn't divide 90000, so the track counts in 1/24000 s instead.
+        let film = FrameRate::from_fps(23.976).unwrap();
+        assert_eq!((film.timescale(), film.frame_duration(film.timescale())), (24000, 1001));
+        assert_eq!(FrameRate::from_fps(25.0).unwrap().frame_duration(VIDEO_TIMESCALE), 3600);
+        assert_eq!(FrameRate::from_fps(7.0).unwrap().timescale(), 7000);
+        assert!(FrameRate::from_fps(0.0).is_none());
+
+        // each track writes its own timescale, and the duration in it.
+        let mut ctx = RemuxContext::new();
+        ctx.duration_ms = 2000;
+        ctx.audio_timescale = 44100;
+        let mdhd = Encoder::encode_mdhd(&ctx, HandlerType::Audio).serialize();
+        assert_eq!(u32::from_be_bytes(mdhd[20..24].try_into().unwrap()), 44100);
+        assert_eq!(u32::from_be_bytes(mdhd[24..28].try_into().unwrap()), 88200);
+        let mdhd = Encoder::encode_mdhd(&ctx, HandlerType::Video).serialize();
+        assert_eq!(u32::from_be_bytes(mdhd[20..24].try_into().unwrap()), VIDEO_TIMESCALE);
+
+        // 64-bit decode times switch tfdt to version 1.
+        assert_eq!(TrackFragmentDecodeTimeBox::new(90000).serialize().len(), 16);
+        let tfdt = TrackFragmentDecodeTimeBox::new(386_547_056_550).serialize();
+        assert_eq!((tfdt.len(), tfdt[8]), (20, 1));
+        assert_eq!(u64::from_be_bytes(tfdt[12..20].try_into().unwrap()), 386_547_056_550);
+    }
+
+    #[test]
+    fn test_timeline() {
+        use crate::fmpeg::timeline::{Discontinuity, TimestampNormalizer};
+
+        // the 32-bit wrap continues on a 64-bit timeline; a late tag from before it is held.
+        let mut timeline = TimestampNormalizer::new(10_000);
+        assert_eq!(timeline.push(u32::MAX - 10), (4_294_967_285, None));
+        assert_eq!(timeline.push(29), (4_294_967_325, None));
+        assert_eq!(timeline.push(u32::MAX - 5), (4_294_967_325, None));
+        assert_eq!(timeline.push(69), (4_294_967_365, None));
+
+        // an encoder restart jumps back; the track continues one step after the previous tag.
+        let mut timeline = TimestampNormalizer::new(10_000);
+        timeline.push(100_000);
+        timeline.push(100_040);
+        assert_eq!(
+            timeline.push(500),
+            (100_080, Some(Discontinuity { timestamp: 500, jump_ms: -99_540, output_ms: 100_080 }))
+        );
+        assert_eq!(timeline.push(540), (100_120, None));
+        // forward jumps above the threshold too.
+        assert_eq!(timeline.push(1_000_000).0, 100_160);
+        assert_eq!(timeline.discontinuities(), 2);
+
+        // after a seek the first timestamp is taken as is again.
+        timeline.reset();
+        assert_eq!(timeline.push(5_000), (5_000, None));
+        assert_eq!(timeline.discontinuities(), 2);
+
+        let mut timeline = TimestampNormalizer::new(1_000);
+        timeline.push(0);
+        assert!(timeline.push(2_000).1.is_some());
+    }
+
+    /// Drops the tag at one timestamp and records every timestamp it sees.
+    struct DropFilter {
+        drop_ms: u32,
+        seen: std::sync::Arc<std::sync::Mutex<Vec<u32>>>,
+    }
+
+    impl stage::ITagFilter for DropFilter {
+        fn filter_tag(&mut self, tag: flv::tag::Tag) -> Option<flv::tag::Tag> {
+            self.seen.lock().unwrap().push(tag.timestamp);
+            (tag.timestamp != self.drop_ms).then_some(tag)
+        }
+    }
+
+    #[test]
+    fn test_stage_routing() {
+        use crate::exchange::{Destination, ExchangeRegistrable, IStateMachine, PackedContent, PackedContentToRemuxer, PackedContentToStage, StreamItem};
+        use crate::fmpeg::codec_string;
+        use crate::fmpeg::parser::PcmFormat;
+        use crate::stage::{FilterStage, IPipelineStage};
+        use std::sync::{Arc, Mutex};
+
+        // 16-bit stereo little-endian PCM at 44.1 kHz, one tag every 20 ms.
+        let pcm_tag = |timestamp: u32| {
+            let mut buf = vec![];
+            push_flv_tag(&mut buf, 8, timestamp, &[0x3F; 17]);
+            let mut decoder = Decoder::new(VecDeque::from(buf));
+            decoder.decode_tag().unwrap()
+        };
+
+        // stream items only go to the remuxer or to stages.
+        assert!(StreamItem::Seek(0).pack(&Destination::Core).is_err());
+        assert!(StreamItem::Seek(0).pack(&Destination::Decoder).is_err());
+        assert!(matches!(StreamItem::Seek(0).pack(&Destination::Stage(2)).unwrap().packed_content, PackedContent::ToStage(PackedContentToStage::Push(StreamItem::Seek(0)))));
+
+        // a filter stage passes what its filter keeps on to its downstream.
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut stage = FilterStage::new(1, Box::new(DropFilter { drop_ms: 20, seen: seen.clone() }));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        stage.set_exchange(sender);
+        stage.set_downstream(Destination::Remuxer);
+        for timestamp in [0, 20, 40] {
+            stage.handle(PackedContent::ToStage(PackedContentToStage::Push(StreamItem::Tag(pcm_tag(timestamp))))).unwrap();
+        }
+        stage.handle(PackedContent::ToStage(PackedContentToStage::StartProcessing)).unwrap();
+        stage.pump().unwrap();
+        let sent: Vec<u32> = receiver.try_iter().map(|packed| {
+            assert!(matches!(packed.packed_routing, Destination::Remuxer));
+            match packed.packed_content {
+                PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(tag)) => tag.timestamp,
+                _ => panic!("expected a tag for the remuxer"),
+            }
+        }).collect();
+        assert_eq!(sent, vec![0, 40]);
+
+        // through the whole pipeline: the filter sees every tag and the remuxer configures from the rest.
+        let mut buf = flv_with_metadata(0x04, &[]);
+        for timestamp in [0, 20, 40] {
+            push_flv_tag(&mut buf, 8, timestamp, &[0x3F; 17]);
+        }
+        let seen = Arc::new(Mutex::new(vec![]));
+        let pipeline = stage::PipelineBuilder::new()
+            .filter(7, Box::new(DropFilter { drop_ms: 20, seen: seen.clone() }))
+            .build(VecDeque::from(buf));
+        let mut core = pipeline.core;
+        core.start().unwrap();
+        run_until(&mut core, |core| core.get_audio_codec_conf().is_some() && seen.lock().unwrap().len() == 3);
+        assert_eq!(*seen.lock().unwrap(), vec![0, 20, 40]);
+        assert_eq!(core.get_audio_codec_conf(), Some(codec_string::pcm(PcmFormat::LittleEndian)));
+        core.drop_all_workers().unwrap();
+    }
+
+    struct RecordingObserver {
+        min_level: event::EventLevel,
+        events: std::sync::Mutex<Vec<event::Event>>,
+    }
+
+    impl event::IEventObserver for RecordingObserver {
+        fn on_event(&self, event: &event::Event) {
+            if event.level >= self.min_level {
+                self.events.lock().unwrap().push(event.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        use crate::event::{EventLevel, Logger};
+        use crate::exchange::{Destination, ExchangeRegistrable};
+        use std::sync::{Arc, Mutex};
+
+        // every observer gets every event, each keeping the levels it wants.
+        let logger = Logger::new(Destination::Decoder).with_stream_id(5);
+        let all = Arc::new(RecordingObserver { min_level: EventLevel::Trace, events: Mutex::new(vec![]) });
+        let warnings = Arc::new(RecordingObserver { min_level: EventLevel::Warn, events: Mutex::new(vec![]) });
+        logger.add_observer(all.clone());
+        logger.add_observer(warnings.clone());
+        logger.debug("one");
+        logger.for_stage(Destination::Stage(3)).warn("two");
+        logger.error("three");
+
+        let levels: Vec<EventLevel> = all.events.lock().unwrap().iter().map(|event| event.level).collect();
+        assert_eq!(levels, vec![EventLevel::Debug, EventLevel::Warn, EventLevel::Error]);
+        let warned: Vec<String> = warnings.events.lock().unwrap().iter().map(|event| event.to_string()).collect();
+        assert_eq!(warned, vec!["WARN [Stage 3] #5 two", "ERROR [Decoder] #5 three"]);
+        // clones share the counters.
+        assert_eq!(logger.for_stage(Destination::Core).metrics().snapshot().errors, 1);
+
+        // counters after decoding a small stream: a script tag, two audio tags and three video tags.
+        let mut buf = flv_with_metadata(0x05, &[]);
+        for timestamp in [0, 20] {
+            push_flv_tag(&mut buf, 8, timestamp, &[0x3F; 17]);
+        }
+        for timestamp in [0, 40, 80] {
+            push_flv_tag(&mut buf, 9, timestamp, &[0x27, 1, 0, 0, 0, 0, 0, 0, 1, 0x41]);
+        }
+        let total = buf.len() as u64;
+        let mut decoder = Decoder::new(VecDeque::from(buf));
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        decoder.set_exchange(sender);
+        decoder.decode_header().unwrap();
+        while decoder.decode_body_once().is_ok() {}
+
+        let snapshot = decoder.logger().metrics().snapshot();
+        assert_eq!(snapshot.script_tags_decoded, 1);
+        assert_eq!(snapshot.audio_tags_decoded, 2);
+        assert_eq!(snapshot.video_tags_decoded, 3);
+        // everything but the last previous tag size.
+        assert_eq!(snapshot.bytes_in, total - 4);
+        assert_eq!(snapshot.errors, 0);
+    }
+
+    #[test]
+    fn test_avcc_length_rewrite() {
+        use crate::fmpeg::avc::{split_avcc, to_avcc, NaluIter};
+
+        // 1 and 2-byte lengths are widened to 4.
+        assert_eq!(to_avcc(&[2, 0x09, 0xF0, 1, 0x65], 1).unwrap(), (vec![0, 0, 0, 2, 0x09, 0xF0, 0, 0, 0, 1, 0x65], 0));
+        assert_eq!(to_avcc(&[0, 3, 0x65, 0x88, 0x84], 2).unwrap(), (vec![0, 0, 0, 3, 0x65, 0x88, 0x84], 0));
+
+        // empty units are skipped and a truncated one is dropped, whatever the length size.
+        assert_eq!(to_avcc(&[0, 1, 0x09, 0, 0, 0, 2, 0x65, 0x88, 0, 5, 0x06], 2).unwrap(), (vec![0, 0, 0, 1, 0x09, 0, 0, 0, 2, 0x65, 0x88], 2));
+        assert_eq!(to_avcc(&[1, 0x65, 0], 1).unwrap(), (vec![0, 0, 0, 1, 0x65], 1));
+        assert_eq!(to_avcc(&[0, 0, 0, 0, 0, 0, 0, 1, 0x65, 0, 0], 4).unwrap(), (vec![0, 0, 0, 1, 0x65], 2));
+        assert_eq!(to_avcc(&[0, 0, 0, 9, 0x65], 4).unwrap(), (vec![], 1));
+
+        // the iterator agrees on what is kept.
+        let payload = [0, 0, 0, 0, 0, 0, 0, 1, 0x65, 0, 0, 0, 9, 0x41];
+        let (units, dropped) = split_avcc(&payload, 4).unwrap();
+        assert_eq!(units, vec![&[0x65][..]]);
+        assert_eq!(dropped, 2);
+        assert_eq!(NaluIter::new(&payload, 4).map(|nalu| nalu.data.to_vec()).collect::<Vec<_>>(), vec![vec![0x65]]);
+    }
+
+    #[test]
+    fn test_repeated_video_sequence_header() {
+        use crate::event::EventLevel;
+        use std::sync::{Arc, Mutex};
+
+        let sequence_header = |level: u8| {
+            let mut body = avc_sequence_header();
+            body[8] = level;
+            body
+        };
+        // the remuxer only configures once it has metadata and both tracks; PCM audio is the simplest.
+        let mut buf = flv_with_metadata(0x05, &[]);
+        push_flv_tag(&mut buf, 9, 0, &sequence_header(0x1F));
+        for timestamp in (0..=120).step_by(40) {
+            push_flv_tag(&mut buf, 8, timestamp, &[0x3F; 17]);
+            match timestamp {
+                // resent unchanged with the next keyframe, then changed.
+                40 => push_flv_tag(&mut buf, 9, 40, &sequence_header(0x1F)),
+                80 => push_flv_tag(&mut buf, 9, 80, &sequence_header(0x28)),
+                _ => {}
+            }
+            push_flv_tag(&mut buf, 9, timestamp, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        }
+
+        let observer = Arc::new(RecordingObserver { min_level: EventLevel::Debug, events: Mutex::new(vec![]) });
+        let pipeline = stage::PipelineBuilder::new()
+            .observer(observer.clone())
+            .build(VecDeque::from(buf));
+        let mut core = pipeline.core;
+        core.start().unwrap();
+        let headers = || observer.events.lock().unwrap().iter()
+            .filter(|event| event.message.contains("sequence header"))
+            .map(|event| (event.level, event.message.clone()))
+            .collect::<Vec<_>>();
+        run_until(&mut core, |_| headers().len() == 2);
+        assert_eq!(headers(), vec![
+            (EventLevel::Debug, "Repeated video sequence header at 40 ms ignored.".to_string()),
+            (EventLevel::Warn, "Video sequence header changed at 80 ms; ignored, the track keeps its configuration.".to_string()),
+        ]);
+        assert_eq!(core.get_video_codec_conf(), Some("avc1.64001f".to_string()));
+        assert_eq!(core.get_metrics().errors, 0);
+        core.drop_all_workers().unwrap();
+    }
+
+    #[test]
+    fn test_repeated_audio_sequence_header() {
+        use crate::event::EventLevel;
+        use std::sync::{Arc, Mutex};
+
+        // Enhanced FLV metadata gives the codec as a FourCC, leaving no legacy codec id.
+        let mut buf = flv_with_metadata(0x05, &[("audiocodecid", u32::from_be_bytes(*b"mp4a") as f64)]);
+        // AAC LC, 44.1 kHz stereo.
+        push_flv_tag(&mut buf, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+        push_flv_tag(&mut buf, 9, 0, &avc_sequence_header());
+        for timestamp in (0..=120).step_by(40) {
+            match timestamp {
+                // resent unchanged, then switched to 48 kHz.
+                40 => push_flv_tag(&mut buf, 8, 40, &[0xAF, 0, 0x12, 0x10]),
+                80 => push_flv_tag(&mut buf, 8, 80, &[0xAF, 0, 0x11, 0x90]),
+                _ => {}
+            }
+            push_flv_tag(&mut buf, 8, timestamp, &[0xAF, 1, 0x21, 0x00, 0x49, 0x90]);
+            push_flv_tag(&mut buf, 9, timestamp, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        }
+
+        let observer = Arc::new(RecordingObserver { min_level: EventLevel::Debug, events: Mutex::new(vec![]) });
+        let pipeline = stage::PipelineBuilder::new()
+            .observer(observer.clone())
+            .build(VecDeque::from(buf));
+        let mut core = pipeline.core;
+        core.start().unwrap();
+        let headers = || observer.events.lock().unwrap().iter()
+            .filter(|event| event.message.contains("sequence header"))
+            .map(|event| (event.level, event.message.clone()))
+            .collect::<Vec<_>>();
+        run_until(&mut core, |_| headers().len() == 2);
+        assert_eq!(headers(), vec![
+            (EventLevel::Debug, "Repeated audio sequence header at 40 ms ignored.".to_string()),
+            (EventLevel::Warn, "Audio sequence header changed at 80 ms; ignored, the track keeps its configuration.".to_string()),
+        ]);
+        assert_eq!(core.get_audio_codec_conf(), Some("mp4a.40.2".to_string()));
+        assert_eq!(core.get_metrics().errors, 0);
+        core.drop_all_workers().unwrap();
+    }
+
+    #[test]
+    fn test_edit_list() {
+        use crate::exchange::RemuxedData;
+
+        let elst = |moov: &[u8]| moov.windows(4).position(|window| window == b"elst").map(|position| {
+            let start = position - 4;
+            (u32::from_be_bytes(moov[start + 16..start + 20].try_into().unwrap()), i32::from_be_bytes(moov[start + 20..start + 24].try_into().unwrap()))
+        });
+
+        // samples are presented from the first one's composition time, here 80 ms at 90 kHz.
+        let mut buf = flv_with_metadata(0x05, &[]);
+        push_flv_tag(&mut buf, 9, 0, &avc_sequence_header());
+        for (timestamp, cts) in [(0u32, 80u8), (40, 0), (80, 80), (120, 0)] {
+            push_flv_tag(&mut buf, 8, timestamp, &[0x3F; 17]);
+            push_flv_tag(&mut buf, 9, timestamp, &[0x17, 1, 0, 0, cts, 0, 0, 0, 1, 0x65]);
+        }
+
+        let pipeline = stage::PipelineBuilder::new().build(VecDeque::from(buf));
+        let mut core = pipeline.core;
+        core.start().unwrap();
+        let mut header = None;
+        let mut offsets = vec![];
+        run_until(&mut core, |core| {
+            while let Ok(data) = core.consume() {
+                match data {
+                    RemuxedData::Header(data) => header = Some(data),
+                    RemuxedData::Video(data) => {
+                        let start = data.windows(4).position(|window| window == b"trun").unwrap() - 4;
+                        offsets.push((data[start + 8], i32::from_be_bytes(data[start + 32..start + 36].try_into().unwrap())));
+                    }
+                    _ => {}
+                }
+            }
+            header.is_some() && offsets.len() >= 2
+        });
+        core.drop_all_workers().unwrap();
+
+        assert_eq!(elst(&header.unwrap()), Some((0, 7200)));
+        // the offsets are the ones of the tags, all positive, so trun stays at version 0.
+        assert_eq!(offsets[..2], [(0, 7200), (0, 0)]);
+
+        // nothing to skip without a composition time on the first sample.
+        let mut ctx = RemuxContext::new();
+        ctx.video_codec_type = VideoCodecType::Avc1;
+        ctx.video_composition_shift = Some(0);
+        assert!(Encoder::encode_video_trak(&ctx).edit_box.is_none());
+        ctx.video_composition_shift = Some(40);
+        ctx.duration_ms = 5000;
+        let mut trak = Encoder::encode_video_trak(&ctx);
+        assert_eq!(elst(&trak.serialize()), Some((5000, 3600)));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::event::Logger;
+use crate::exchange::{Destination, ExchangeRegistrable, IStateMachine, Packed, PackedContent, SessionId};
+use crate::flv::decoder::Decoder;
+use crate::flv::demuxer::Demuxer;
+use crate::fmpeg::remuxer::Remuxer;
+use crate::stage::IPipelineStage;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+enum WorkerMessage {
+    Open(Box<Session>),
+    Wake(SessionId),
+    Close(SessionId),
+    Shutdown,
+}
+
+/// Held by the core of a pooled session; tells the owning worker that commands are queued.
+#[derive(Clone)]
+pub(crate) struct SessionWaker {
+    session_id: SessionId,
+    sender: mpsc::Sender<WorkerMessage>,
+}
+
+impl SessionWaker {
+    #[inline]
+    pub(crate) fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    pub(crate) fn wake(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.sender.send(WorkerMessage::Wake(self.session_id)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("[Session {}] Worker closed.", self.session_id).into())
+        }
+    }
+}
+
+/// The state machines of one stream.
+/// Messages between them go through the session's own outbox instead of an `Exchange` thread.
+pub(crate) struct Session {
+    session_id: SessionId,
+
+    outbox: mpsc::Receiver<Packed>,
+    core: mpsc::Sender<PackedContent>,
+
+    decoder: Decoder,
+    demuxer: Demuxer,
+    remuxer: Remuxer,
+    stages: Vec<Box<dyn IPipelineStage>>,
+
+    logger: Logger,
+}
+
+impl Session {
+    /// Registers every machine to a fresh outbox. The returned sender is the one the core must use.
+    pub(crate) fn new(
+        session_id: SessionId,
+        core: mpsc::Sender<PackedContent>,
+        mut decoder: Decoder,
+        mut demuxer: Demuxer,
+        mut remuxer: Remuxer,
+        mut stages: Vec<Box<dyn IPipelineStage>>,
+        logger: Logger,
+    ) -> (Session, mpsc::Sender<Packed>) {
+        let (sender, outbox) = mpsc::channel();
+
+        Self::register(&mut decoder, &sender, &logger);
+        Self::register(&mut demuxer, &sender, &logger);
+        Self::register(&mut remuxer, &sender, &logger);
+        for stage in stages.iter_mut() {
+            Self::register(stage.as_mut(), &sender, &logger);
+        }
+
+        let session = Session {
+            session_id,
+            outbox,
+            core,
+            decoder,
+            demuxer,
+            remuxer,
+            stages,
+            logger,
+        };
+        (session, sender)
+    }
+
+    fn register<T: ExchangeRegistrable + ?Sized>(registry: &mut T, sender: &mpsc::Sender<Packed>, logger: &Logger) {
+        registry.set_exchange(sender.clone());
+        registry.set_logger(logger.for_stage(registry.get_self_as_destination()));
+    }
+
+    /// Delivers everything queued in the outbox, including what the machines queue meanwhile.
+    /// Returns `false` once the session has been closed.
+    fn drive(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        while let Ok(packed) = self.outbox.try_recv() {
+            if !self.dispatch(packed)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn dispatch(&mut self, packed: Packed) -> Result<bool, Box<dyn std::error::Error>> {
+        let machine: &mut dyn IStateMachine = match packed.packed_routing {
+            Destination::Core => {
+                // the core lives with the caller. once it is dropped, nobody is listening anymore.
+                return Ok(self.core.send(packed.packed_content).is_ok());
+            }
+            Destination::Decoder => &mut self.decoder,
+            Destination::Demuxer => &mut self.demuxer,
+            Destination::Remuxer => &mut self.remuxer,
+            Destination::Stage(stage_id) => {
+                match self.stages
+                    .iter_mut()
+                    .find(|stage| stage.get_self_as_destination() == Destination::Stage(stage_id)) {
+                    Some(stage) => stage.as_mut(),
+                    None => return Err(format!("[Session {}] Unknown stage {}.", self.session_id, stage_id).into())
+                }
+            }
+        };
+        Ok(machine.handle(packed.packed_content)? && machine.pump()?)
+    }
+}
+
+/// Serves many independent streams with a fixed number of worker threads.
+/// Sessions are sharded by id; each one is only ever touched by its own worker,
+/// and a session that fails or panics is torn down without affecting the others.
+pub struct SessionPool {
+    workers: Vec<mpsc::Sender<WorkerMessage>>,
+    handles: Vec<JoinHandle<()>>,
+    open_sessions: Arc<Mutex<HashSet<SessionId>>>,
+}
+
+impl SessionPool {
+    pub fn new(worker_count: usize) -> Self {
+        let open_sessions = Arc::new(Mutex::new(HashSet::new()));
+        let mut workers = vec![];
+        let mut handles = vec![];
+        for _ in 0..worker_count.max(1) {
+            let (sender, receiver) = mpsc::channel();
+            let open_sessions = open_sessions.clone();
+            workers.push(sender);
+            handles.push(std::thread::spawn(move || Self::run_worker(receiver, open_sessions)));
+        }
+        Self {
+            workers,
+            handles,
+            open_sessions,
+        }
+    }
+
+    #[inline]
+    fn worker_for(&self, session_id: SessionId) -> &mpsc::Sender<WorkerMessage> {
+        &self.workers[(session_id % self.workers.len() as u64) as usize]
+    }
+
+    pub fn is_open(&self, session_id: SessionId) -> bool {
+        self.open_sessions.lock().unwrap().contains(&session_id)
+    }
+
+    /// Hands a session over to its worker and returns the waker its core must use.
+    pub(crate) fn open(&self, session: Session) -> Result<SessionWaker, Box<dyn std::error::Error>> {
+        let session_id = session.session_id;
+        if !self.open_sessions.lock().unwrap().insert(session_id) {
+            return Err(format!("[Session {}] Session already open.", session_id).into());
+        }
+
+        let sender = self.worker_for(session_id).clone();
+        if sender.send(WorkerMessage::Open(Box::new(session))).is_err() {
+            self.open_sessions.lock().unwrap().remove(&session_id);
+            return Err(format!("[Session {}] Worker closed.", session_id).into());
+        }
+        Ok(
+            SessionWaker {
+                session_id,
+                sender,
+            }
+        )
+    }
+
+    /// Tears a session down immediately, dropping whatever it has not processed yet.
+    pub fn close(&self, session_id: SessionId) -> Result<(), Box<dyn std::error::Error>> {
+        match self.worker_for(session_id).send(WorkerMessage::Close(session_id)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("[Session {}] Worker closed.", session_id).into())
+        }
+    }
+
+    /// Tears down every session and waits for the workers to exit.
+    pub fn shutdown(self) {
+        for worker in self.workers.iter() {
+            let _ = worker.send(WorkerMessage::Shutdown);
+        }
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+
+    fn run_worker(receiver: mpsc::Receiver<WorkerMessage>, open_sessions: Arc<Mutex<HashSet<SessionId>>>) {
+        let mut sessions: HashMap<SessionId, Box<Session>> = HashMap::new();
+        // exits once every sender is gone, i.e. the pool and all pooled cores have been dropped.
+        while let Ok(message) = receiver.recv() {
+            let session_id = match message {
+                WorkerMessage::Open(session) => {
+                    let session_id = session.session_id;
+                    session.logger.info("Session opened.");
+                    sessions.insert(session_id, session);
+                    session_id
+                }
+                WorkerMessage::Wake(session_id) => session_id,
+                WorkerMessage::Close(session_id) => {
+                    if let Some(session) = sessions.remove(&session_id) {
+                        session.logger.info("Session closed.");
+                    }
+                    open_sessions.lock().unwrap().remove(&session_id);
+                    continue;
+                }
+                WorkerMessage::Shutdown => break,
+            };
+
+            let Some(session) = sessions.get_mut(&session_id) else {
+                continue;
+            };
+            let alive = match panic::catch_unwind(AssertUnwindSafe(|| session.drive())) {
+                Ok(Ok(alive)) => alive,
+                Ok(Err(e)) => {
+                    session.logger.error(format!("Session failed: {}", e));
+                    false
+                }
+                Err(_) => {
+                    session.logger.error("Session panicked.");
+                    false
+                }
+            };
+            if !alive {
+                session.logger.info("Session torn down.");
+                sessions.remove(&session_id);
+                open_sessions.lock().unwrap().remove(&session_id);
+            }
+        }
+
+        let mut open_sessions = open_sessions.lock().unwrap();
+        for session_id in sessions.keys() {
+            open_sessions.remove(session_id);
+        }
+    }
+}
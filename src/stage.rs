@@ -0,0 +1,374 @@
+use crate::core::Core;
+use crate::event::{IEventObserver, Logger, StreamId};
+use crate::exchange::{Destination, Exchange, ExchangeRegistrable, IStateMachine, Packed, PackedContent, PackedContentToStage, SessionId, StageId, StreamItem};
+use crate::flv::decoder::Decoder;
+use crate::flv::demuxer::{Demuxer, DEFAULT_MAX_INTERLEAVE_DELTA};
+use crate::flv::header::FlvHeader;
+use crate::flv::meta::RawMetaData;
+use crate::flv::tag::Tag;
+use crate::fmpeg::avc::NaluFilter;
+use crate::fmpeg::remuxer::Remuxer;
+use crate::fmpeg::timeline::DEFAULT_DISCONTINUITY_THRESHOLD_MS;
+use crate::session::{Session, SessionPool};
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+
+/// A stage that can be inserted into the pipeline between the demuxer and the remuxer.
+/// Every stage is addressed by `Destination::Stage(id)` and forwards its output to a downstream
+/// destination chosen when the pipeline is built.
+/// Stages are also driven through `IStateMachine` when the pipeline runs inside a `SessionPool`.
+pub trait IPipelineStage: ExchangeRegistrable + IStateMachine + Send {
+    fn set_downstream(&mut self, destination: Destination);
+
+    /// Moves the stage into its own worker thread.
+    fn launch_worker_thread(self: Box<Self>) -> JoinHandle<()>;
+}
+
+/// Per-item hook used by `FilterStage`.
+/// Returning `None` drops the item, otherwise the (possibly rewritten) item is passed on.
+pub trait ITagFilter: Send {
+    fn filter_tag(&mut self, tag: Tag) -> Option<Tag>;
+
+    fn filter_flv_header(&mut self, flv_header: FlvHeader) -> Option<FlvHeader> {
+        Some(flv_header)
+    }
+
+    fn filter_metadata(&mut self, metadata: RawMetaData) -> Option<RawMetaData> {
+        Some(metadata)
+    }
+}
+
+/// Wraps an `ITagFilter` into a pipeline stage with its own worker thread.
+pub struct FilterStage {
+    stage_id: StageId,
+    filter: Box<dyn ITagFilter>,
+    downstream: Destination,
+
+    channel_exchange: Option<mpsc::Sender<Packed>>,
+    channel_receiver: mpsc::Receiver<PackedContent>,
+    channel_sender: mpsc::Sender<PackedContent>,
+    processing: bool,
+
+    pending: VecDeque<StreamItem>,
+
+    logger: Logger,
+}
+
+impl FilterStage {
+    pub fn new(stage_id: StageId, filter: Box<dyn ITagFilter>) -> Self {
+        let (channel_sender, channel_receiver) = mpsc::channel();
+        Self {
+            stage_id,
+            filter,
+            downstream: Destination::Remuxer,
+            channel_exchange: None,
+            channel_receiver,
+            channel_sender,
+            processing: false,
+            pending: VecDeque::new(),
+            logger: Logger::new(Destination::Stage(stage_id)),
+        }
+    }
+
+    fn send(&self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
+        match self.channel_exchange.as_ref().unwrap().send(pack) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("[Stage {}] Channel closed.", self.stage_id).into())
+        }
+    }
+
+    fn apply_filter(&mut self, item: StreamItem) -> Option<StreamItem> {
+        match item {
+            StreamItem::Tag(tag) => self.filter.filter_tag(tag).map(StreamItem::Tag),
+            StreamItem::FlvHeader(header) => self.filter.filter_flv_header(header).map(StreamItem::FlvHeader),
+            StreamItem::Metadata(metadata) => self.filter.filter_metadata(metadata).map(StreamItem::Metadata),
+            StreamItem::Seek(time_ms) => Some(StreamItem::Seek(time_ms)),
+        }
+    }
+
+    fn process_pending(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        while let Some(item) = self.pending.pop_front() {
+            if let Some(item) = self.apply_filter(item) {
+                self.send(item.pack(&self.downstream)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            if let Ok(received) = self.channel_receiver.recv() {
+                if !self.handle(received)? || !self.pump()? {
+                    return Ok(());
+                }
+            } else {
+                self.logger.warn("Channel closed.");
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl IStateMachine for FilterStage {
+    fn handle(&mut self, content: PackedContent) -> Result<bool, Box<dyn std::error::Error>> {
+        if let PackedContent::ToStage(content) = content {
+            match content {
+                PackedContentToStage::Push(item) => {
+                    self.pending.push_back(item);
+                }
+                PackedContentToStage::StartProcessing => {
+                    self.logger.info("Start processing.");
+                    self.processing = true;
+                }
+                PackedContentToStage::StopProcessing => {
+                    self.logger.info("Stop processing.");
+                    self.processing = false;
+                }
+                PackedContentToStage::CloseWorkerThread => {
+                    self.logger.info("Closing worker thread.");
+                    return Ok(false);
+                }
+                PackedContentToStage::Now => { }
+            }
+        }
+        Ok(true)
+    }
+
+    fn pump(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.processing {
+            self.process_pending()?;
+        }
+        Ok(true)
+    }
+}
+
+impl ExchangeRegistrable for FilterStage {
+    fn set_exchange(&mut self, sender: mpsc::Sender<Packed>) {
+        self.channel_exchange = Some(sender);
+    }
+
+    fn get_sender(&self) -> mpsc::Sender<PackedContent> {
+        self.channel_sender.clone()
+    }
+
+    fn get_self_as_destination(&self) -> Destination {
+        Destination::Stage(self.stage_id)
+    }
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+}
+
+impl IPipelineStage for FilterStage {
+    fn set_downstream(&mut self, destination: Destination) {
+        self.downstream = destination;
+    }
+
+    fn launch_worker_thread(mut self: Box<Self>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            if let Err(e) = self.run() {
+                panic!("Stage {} worker thread stopped unexpectedly: {}", self.stage_id, e);
+            }
+        })
+    }
+}
+
+/// A running pipeline: the core used to drive it and the handles of all worker threads.
+pub struct Pipeline {
+    pub core: Core,
+    pub handles: Vec<JoinHandle<()>>,
+}
+
+/// Builds the stage graph decoder -> demuxer -> [custom stages...] -> remuxer.
+/// Custom stages are chained in the order they are added.
+pub struct PipelineBuilder {
+    stages: Vec<Box<dyn IPipelineStage>>,
+    observers: Vec<Arc<dyn IEventObserver>>,
+    stream_id: StreamId,
+    nalu_filter: NaluFilter,
+    caption_track: bool,
+    discontinuity_threshold_ms: u64,
+    max_interleave_delta: u32,
+    seekable: bool,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self {
+            stages: vec![],
+            observers: vec![],
+            stream_id: 0,
+            nalu_filter: NaluFilter::new(),
+            caption_track: false,
+            discontinuity_threshold_ms: DEFAULT_DISCONTINUITY_THRESHOLD_MS,
+            max_interleave_delta: DEFAULT_MAX_INTERLEAVE_DELTA,
+            seekable: false,
+        }
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn IEventObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Tags every event of this pipeline with the given stream id.
+    pub fn stream_id(mut self, stream_id: StreamId) -> Self {
+        self.stream_id = stream_id;
+        self
+    }
+
+    /// NAL units to drop from video samples before they are written to mdat.
+    pub fn nalu_filter(mut self, filter: NaluFilter) -> Self {
+        self.nalu_filter = filter;
+        self
+    }
+
+    /// Adds a WebVTT track carrying the decoded captions to the MP4 output.
+    /// Captions are offered through `Core::take_captions` either way.
+    pub fn caption_track(mut self, flag: bool) -> Self {
+        self.caption_track = flag;
+        self
+    }
+
+    /// Timestamp jumps larger than this, in ms, are treated as discontinuities and rebased.
+    pub fn discontinuity_threshold(mut self, threshold_ms: u64) -> Self {
+        self.discontinuity_threshold_ms = threshold_ms;
+        self
+    }
+
+    /// How far, in ms, the demuxer lets one track run ahead while waiting for the other.
+    pub fn max_interleave_delta(mut self, delta: u32) -> Self {
+        self.max_interleave_delta = delta;
+        self
+    }
+
+    /// Keeps the whole input around so that `Core::seek` can go backwards; meant for VOD files.
+    pub fn seekable(mut self, flag: bool) -> Self {
+        self.seekable = flag;
+        self
+    }
+
+    pub fn stage(mut self, stage: Box<dyn IPipelineStage>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn filter(self, stage_id: StageId, filter: Box<dyn ITagFilter>) -> Self {
+        self.stage(Box::new(FilterStage::new(stage_id, filter)))
+    }
+
+    /// Chains the stages in order and points the demuxer at the first one.
+    fn wire(demuxer: &mut Demuxer, stages: &mut [Box<dyn IPipelineStage>]) {
+        let mut downstream = Destination::Remuxer;
+        for stage in stages.iter_mut().rev() {
+            stage.set_downstream(downstream);
+            downstream = stage.get_self_as_destination();
+        }
+        demuxer.set_downstream(downstream);
+    }
+
+    fn add_stages_to_core(core: &mut Core, stages: &[Box<dyn IPipelineStage>]) {
+        for stage in stages.iter() {
+            if let Destination::Stage(stage_id) = stage.get_self_as_destination() {
+                core.add_stage(stage_id);
+            }
+        }
+    }
+
+    pub fn build(self, data: VecDeque<u8>) -> Pipeline {
+        let mut exchange = Exchange::new();
+        let logger = exchange.get_logger().with_stream_id(self.stream_id);
+        for observer in self.observers {
+            logger.add_observer(observer);
+        }
+        exchange.set_logger(logger);
+
+        let mut core = Core::new();
+        exchange.register(&mut core);
+
+        let mut decoder = Decoder::new(data);
+        decoder.set_seekable(self.seekable);
+        exchange.register(&mut decoder);
+
+        let mut demuxer = Demuxer::new();
+        demuxer.set_max_interleave_delta(self.max_interleave_delta);
+        exchange.register(&mut demuxer);
+
+        let mut remuxer = Remuxer::new();
+        remuxer.set_nalu_filter(self.nalu_filter);
+        remuxer.set_caption_track(self.caption_track);
+        remuxer.set_discontinuity_threshold(self.discontinuity_threshold_ms);
+        exchange.register(&mut remuxer);
+
+        let mut stages = self.stages;
+        for stage in stages.iter_mut() {
+            exchange.register(stage.as_mut());
+        }
+
+        // wire the graph: every node points at the one after it.
+        Self::wire(&mut demuxer, &mut stages);
+        Self::add_stages_to_core(&mut core, &stages);
+
+        let mut handles = vec![];
+        handles.push(decoder.launch_worker_thread());
+        handles.push(demuxer.launch_worker_thread());
+        for stage in stages {
+            handles.push(stage.launch_worker_thread());
+        }
+        handles.push(remuxer.launch_worker_thread());
+        handles.push(exchange.launch_worker_thread());
+
+        Pipeline {
+            core,
+            handles,
+        }
+    }
+
+    /// Builds the same graph as `build`, but runs it as a session of the pool instead of on dedicated threads.
+    /// The stream id of every event is the session id. The returned core is used exactly like a standalone one;
+    /// `drop_all_workers` (or `SessionPool::close`) tears the session down.
+    pub fn build_session(self, pool: &SessionPool, session_id: SessionId, data: VecDeque<u8>) -> Result<Core, Box<dyn std::error::Error>> {
+        let logger = Logger::new(Destination::Core).with_stream_id(session_id as StreamId);
+        for observer in self.observers {
+            logger.add_observer(observer);
+        }
+
+        let mut core = Core::new();
+        core.set_logger(logger.clone());
+
+        let mut demuxer = Demuxer::new();
+        demuxer.set_max_interleave_delta(self.max_interleave_delta);
+        let mut stages = self.stages;
+        Self::wire(&mut demuxer, &mut stages);
+        Self::add_stages_to_core(&mut core, &stages);
+
+        let mut remuxer = Remuxer::new();
+        remuxer.set_nalu_filter(self.nalu_filter);
+        remuxer.set_caption_track(self.caption_track);
+        remuxer.set_discontinuity_threshold(self.discontinuity_threshold_ms);
+
+        let mut decoder = Decoder::new(data);
+        decoder.set_seekable(self.seekable);
+
+        let (session, sender) = Session::new(
+            session_id,
+            core.get_sender(),
+            decoder,
+            demuxer,
+            remuxer,
+            stages,
+            logger,
+        );
+        core.set_exchange(sender);
+        core.set_waker(pool.open(session)?);
+        Ok(core)
+    }
+}